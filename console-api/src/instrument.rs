@@ -1 +1,22 @@
 tonic::include_proto!("rs.tokio.console.instrument");
+
+/// The version of the protocol implemented by this crate.
+///
+/// A console and an instrumented process can only understand each other if
+/// they speak the same version of the protocol. See [`Protocol::version`] for
+/// when it changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+impl Protocol {
+    /// Returns the version of the protocol implemented by this crate, with
+    /// the given optional `capabilities` supported.
+    pub fn new(capabilities: impl IntoIterator<Item = Capability>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities
+                .into_iter()
+                .map(|capability| capability as i32)
+                .collect(),
+        }
+    }
+}
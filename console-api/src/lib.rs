@@ -12,4 +12,7 @@ pub mod resources;
 pub mod tasks;
 /// Represents events on the tracing subsystem: thread registration and span activities.
 pub mod trace;
+/// Describes the problems with tasks which the console warns about, so that
+/// they are described the same way wherever they are reported.
+pub mod warnings;
 pub use common::*;
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+/// Tasks which wake themselves more often than this percentage of the time
+/// are warned about, unless the console is configured otherwise.
+pub const DEFAULT_SELF_WAKE_PERCENT: u64 = 50;
+
+/// Describes a task which has lost its waker.
+pub const LOST_WAKER: &str = "This task has lost its waker, and will never be woken again.";
+
+/// Describes a task which lost events because the subscriber's event buffer
+/// was full.
+pub const EVENTS_DROPPED: &str = "Some of this task's events were dropped because the subscriber's event buffer was full, so its stats may be wrong";
+
+/// Describes a task which has woken itself for `percent`% of its wakeups,
+/// more than the `min_percent`% which is warned about.
+pub fn self_wakes(min_percent: u64, percent: u64) -> String {
+    format!(
+        "This task has woken itself for more than {}% of its total wakeups ({}%)",
+        min_percent, percent
+    )
+}
+
+/// Describes a task whose current poll has been running for `polling_for`.
+pub fn blocking_poll(polling_for: Duration) -> String {
+    format!(
+        "This task has been polling for {:.2?} without yielding, blocking its worker thread",
+        polling_for
+    )
+}
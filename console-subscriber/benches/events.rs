@@ -0,0 +1,168 @@
+//! Measures the overhead of recording runtime events with a `ConsoleLayer`.
+//!
+//! Each benchmark spawns a number of tasks which yield repeatedly, so that
+//! most of the time is spent entering and exiting task spans and waking tasks,
+//! on a runtime with and without a `ConsoleLayer` installed.
+//!
+//! The `channel_baseline` case records events the way the layer did before it
+//! buffered them per thread, by timestamping each with `SystemTime::now()` and
+//! sending it on a bounded channel with `try_send`, so that the two can be
+//! compared.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::SystemTime,
+};
+use tokio::{
+    runtime,
+    sync::{mpsc, Barrier},
+    task,
+};
+use tracing::{span, Dispatch, Subscriber};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+const TASKS: usize = 64;
+const YIELDS: usize = 100;
+
+fn runtime(dispatch: Option<Dispatch>) -> runtime::Runtime {
+    let mut builder = runtime::Builder::new_multi_thread();
+    builder.worker_threads(4).enable_all();
+    if let Some(dispatch) = dispatch {
+        builder.on_thread_start(move || {
+            // The runtime's worker threads live as long as the runtime, so the
+            // guard may be leaked.
+            std::mem::forget(tracing::dispatcher::set_default(&dispatch));
+        });
+    }
+    builder.build().unwrap()
+}
+
+fn console_dispatch() -> Dispatch {
+    let (layer, server) = console_subscriber::ConsoleLayer::builder()
+        .server_addr(([127, 0, 0, 1], 0))
+        .build();
+    thread::Builder::new()
+        .name("console_subscriber".into())
+        .spawn(move || {
+            runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(server.serve())
+                .unwrap();
+        })
+        .unwrap();
+    Dispatch::new(tracing_subscriber::registry().with(layer))
+}
+
+/// The events recorded by [`ChannelLayer`], like those the `ConsoleLayer`
+/// sent on its channel.
+#[allow(dead_code)]
+#[derive(Debug)]
+enum ChannelEvent {
+    NewSpan { id: span::Id, at: SystemTime },
+    Enter { id: span::Id, at: SystemTime },
+    Exit { id: span::Id, at: SystemTime },
+    Close { id: span::Id, at: SystemTime },
+    Event { at: SystemTime },
+}
+
+/// Records every span and event on a bounded channel, dropping events which
+/// don't fit.
+struct ChannelLayer {
+    tx: mpsc::Sender<ChannelEvent>,
+    dropped: AtomicUsize,
+}
+
+impl ChannelLayer {
+    fn send(&self, event: ChannelEvent) {
+        if self.tx.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+impl<S> Layer<S> for ChannelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _: &span::Attributes<'_>, id: &span::Id, _: Context<'_, S>) {
+        let at = SystemTime::now();
+        self.send(ChannelEvent::NewSpan { id: id.clone(), at });
+    }
+
+    fn on_event(&self, _: &tracing::Event<'_>, _: Context<'_, S>) {
+        let at = SystemTime::now();
+        self.send(ChannelEvent::Event { at });
+    }
+
+    fn on_enter(&self, id: &span::Id, _: Context<'_, S>) {
+        let at = SystemTime::now();
+        self.send(ChannelEvent::Enter { id: id.clone(), at });
+    }
+
+    fn on_exit(&self, id: &span::Id, _: Context<'_, S>) {
+        let at = SystemTime::now();
+        self.send(ChannelEvent::Exit { id: id.clone(), at });
+    }
+
+    fn on_close(&self, id: span::Id, _: Context<'_, S>) {
+        let at = SystemTime::now();
+        self.send(ChannelEvent::Close { id, at });
+    }
+}
+
+fn channel_dispatch() -> Dispatch {
+    let (tx, mut rx) =
+        mpsc::channel(console_subscriber::ConsoleLayer::DEFAULT_EVENT_BUFFER_CAPACITY);
+    thread::Builder::new()
+        .name("channel_baseline".into())
+        .spawn(move || {
+            runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(async move { while rx.recv().await.is_some() {} });
+        })
+        .unwrap();
+    let layer = ChannelLayer {
+        tx,
+        dropped: AtomicUsize::new(0),
+    };
+    Dispatch::new(tracing_subscriber::registry().with(layer))
+}
+
+async fn spawn_and_yield(tasks: usize) {
+    let barrier = Arc::new(Barrier::new(tasks + 1));
+    for _ in 0..tasks {
+        let barrier = barrier.clone();
+        task::spawn(async move {
+            for _ in 0..YIELDS {
+                task::yield_now().await;
+            }
+            barrier.wait().await;
+        });
+    }
+    barrier.wait().await;
+}
+
+fn bench_spawn_and_yield(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn_and_yield");
+    let runtimes = [
+        ("no_console", runtime(None)),
+        ("channel_baseline", runtime(Some(channel_dispatch()))),
+        ("console", runtime(Some(console_dispatch()))),
+    ];
+    for (name, rt) in &runtimes {
+        group.bench_with_input(BenchmarkId::new(*name, TASKS), &TASKS, |b, &tasks| {
+            b.iter(|| rt.block_on(spawn_and_yield(tasks)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_spawn_and_yield);
+criterion_main!(benches);
@@ -15,7 +15,12 @@ OPTIONS:
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    console_subscriber::init();
+    // The tasks below are instrumented with spans, which the console shows
+    // them yielding in.
+    console_subscriber::ConsoleLayer::builder()
+        .with_default_env()
+        .track_user_spans(true)
+        .init();
     // spawn optional extras from CLI args
     // skip first which is command name
     for opt in std::env::args().skip(1) {
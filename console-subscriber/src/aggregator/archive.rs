@@ -0,0 +1,360 @@
+use super::{Id, Task, TaskStats};
+use console_api as proto;
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    time::{Duration, SystemTime},
+};
+use tracing_core::Metadata;
+
+/// Summaries of completed tasks, which are kept after the tasks themselves
+/// have been dropped from the aggregator's state.
+///
+/// The archive is bounded both by the number of summaries it holds and by how
+/// long ago their tasks completed. Each summary is numbered in the order in
+/// which tasks completed, so that listing them page by page can continue from
+/// where the previous page left off, even as old summaries are evicted.
+pub(crate) struct TaskArchive {
+    tasks: VecDeque<CompletedTask>,
+
+    /// The number of the next task to be archived.
+    next_seq: u64,
+
+    /// The maximum number of summaries to keep.
+    capacity: usize,
+
+    /// How long to keep each summary for after its task completed.
+    max_age: Duration,
+}
+
+struct CompletedTask {
+    seq: u64,
+    id: Id,
+    name: Option<Box<str>>,
+    metadata: &'static Metadata<'static>,
+    location: Option<proto::Location>,
+    created_at: Option<SystemTime>,
+    dropped_at: SystemTime,
+    polls: u64,
+    busy_time: Duration,
+    wakes: u64,
+    self_wakes: u64,
+}
+
+impl TaskArchive {
+    /// The maximum number of tasks returned by a single call to
+    /// [`TaskArchive::list`].
+    const MAX_PAGE_SIZE: usize = 1000;
+
+    pub(super) const NAME_FIELD: &'static str = "task.name";
+
+    pub(crate) fn new(capacity: usize, max_age: Duration) -> Self {
+        Self {
+            tasks: VecDeque::new(),
+            // Start numbering tasks from 1, so that a page token of 0 can mean
+            // "from the beginning".
+            next_seq: 1,
+            capacity,
+            max_age,
+        }
+    }
+
+    /// Archives a summary of a task which completed at `dropped_at`.
+    pub(crate) fn push(&mut self, task: &Task, stats: &TaskStats, dropped_at: SystemTime) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.evict_expired(dropped_at);
+        if self.tasks.len() >= self.capacity {
+            self.tasks.pop_front();
+        }
+
+        self.tasks.push_back(CompletedTask {
+            seq: self.next_seq,
+            id: task.id,
+            name: task_name(task),
+            metadata: task.metadata,
+            location: task.location.clone(),
+            created_at: stats.created_at,
+            dropped_at,
+            polls: stats.poll_stats.polls,
+            busy_time: stats.poll_stats.busy_time,
+            wakes: stats.wakes,
+            self_wakes: stats.self_wakes,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Returns the page of archived tasks requested by `request`.
+    pub(crate) fn list(
+        &mut self,
+        request: &proto::instrument::ListCompletedTasksRequest,
+        now: SystemTime,
+    ) -> proto::instrument::ListCompletedTasksResponse {
+        self.evict_expired(now);
+
+        let completed_after = request
+            .completed_after
+            .clone()
+            .and_then(|time| SystemTime::try_from(time).ok());
+        let completed_before = request
+            .completed_before
+            .clone()
+            .and_then(|time| SystemTime::try_from(time).ok());
+        let matches = |task: &CompletedTask| {
+            if let Some(name) = request.name.as_deref() {
+                if !task.name.as_deref().unwrap_or_default().contains(name) {
+                    return false;
+                }
+            }
+            if let Some(location) = request.location.as_deref() {
+                if !format_location(task.location.as_ref()).contains(location) {
+                    return false;
+                }
+            }
+            completed_after.map_or(true, |after| task.dropped_at >= after)
+                && completed_before.map_or(true, |before| task.dropped_at < before)
+        };
+
+        let page_size = match request.page_size as usize {
+            0 => Self::MAX_PAGE_SIZE,
+            page_size => page_size.min(Self::MAX_PAGE_SIZE),
+        };
+        let start = self
+            .tasks
+            .partition_point(|task| task.seq < request.page_token);
+
+        let mut tasks = Vec::new();
+        let mut next_page_token = 0;
+        for task in self.tasks.range(start..) {
+            if tasks.len() == page_size {
+                next_page_token = task.seq;
+                break;
+            }
+            if matches(task) {
+                tasks.push(task.to_proto());
+            }
+        }
+
+        proto::instrument::ListCompletedTasksResponse {
+            tasks,
+            next_page_token,
+        }
+    }
+
+    fn evict_expired(&mut self, now: SystemTime) {
+        while let Some(task) = self.tasks.front() {
+            let age = now.duration_since(task.dropped_at).unwrap_or_default();
+            if age <= self.max_age {
+                break;
+            }
+            self.tasks.pop_front();
+        }
+    }
+}
+
+impl CompletedTask {
+    fn to_proto(&self) -> proto::tasks::CompletedTask {
+        proto::tasks::CompletedTask {
+            id: Some(self.id.into()),
+            name: self.name.as_deref().map(Into::into),
+            target: self.metadata.target().into(),
+            location: self.location.clone(),
+            created_at: self.created_at.map(Into::into),
+            dropped_at: Some(self.dropped_at.into()),
+            polls: self.polls,
+            busy_time: Some(self.busy_time.into()),
+            wakes: self.wakes,
+            self_wakes: self.self_wakes,
+        }
+    }
+}
+
+/// Returns the value of the task's `task.name` field, if it has one.
+fn task_name(task: &Task) -> Option<Box<str>> {
+    use proto::field::{Name, Value};
+
+    task.fields.iter().find_map(|field| {
+        let is_name = match field.name.as_ref()? {
+            Name::StrName(name) => name == TaskArchive::NAME_FIELD,
+            Name::NameIdx(idx) => task
+                .metadata
+                .fields()
+                .iter()
+                .nth(*idx as usize)
+                .map_or(false, |field| field.name() == TaskArchive::NAME_FIELD),
+        };
+        if !is_name {
+            return None;
+        }
+        match field.value.as_ref()? {
+            Value::StrVal(name) | Value::DebugVal(name) => Some(name.as_str().into()),
+            _ => None,
+        }
+    })
+}
+
+fn format_location(location: Option<&proto::Location>) -> String {
+    let location = match location {
+        Some(location) => location,
+        None => return String::new(),
+    };
+    let mut formatted = location.file.clone().unwrap_or_default();
+    if let Some(line) = location.line {
+        formatted.push_str(&format!(":{}", line));
+        if let Some(column) = location.column {
+            formatted.push_str(&format!(":{}", column));
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::tests::TASK;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// Returns when the task with the ID `id` completed: tasks complete a
+    /// second apart, in the order of their IDs.
+    fn dropped_at(id: Id) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(id)
+    }
+
+    /// Archives the tasks with IDs `ids`, naming the even ones `worker {id}`
+    /// and spawning each one on the line of `src/main.rs` given by its ID.
+    fn archive(archive: &mut TaskArchive, ids: impl IntoIterator<Item = Id>) {
+        for id in ids {
+            let fields = (id % 2 == 0).then(|| proto::Field {
+                metadata_id: None,
+                name: Some(proto::field::Name::StrName(TaskArchive::NAME_FIELD.into())),
+                value: Some(proto::field::Value::StrVal(format!("worker {}", id))),
+            });
+            let task = Task {
+                id,
+                metadata: &TASK,
+                fields: fields.into_iter().collect(),
+                location: Some(proto::Location {
+                    file: Some("src/main.rs".into()),
+                    module_path: None,
+                    line: Some(id as u32),
+                    column: Some(5),
+                }),
+                metrics_location: 0,
+            };
+            archive.push(&task, &TaskStats::default(), dropped_at(id));
+        }
+    }
+
+    fn page(page_size: u32, page_token: u64) -> proto::instrument::ListCompletedTasksRequest {
+        proto::instrument::ListCompletedTasksRequest {
+            page_size,
+            page_token,
+            ..Default::default()
+        }
+    }
+
+    fn ids(response: &proto::instrument::ListCompletedTasksResponse) -> Vec<Id> {
+        response
+            .tasks
+            .iter()
+            .map(|task| task.id.as_ref().expect("archived tasks have IDs").id)
+            .collect()
+    }
+
+    #[test]
+    fn page_tokens_survive_eviction() {
+        let mut tasks = TaskArchive::new(100, Duration::from_secs(10));
+        archive(&mut tasks, 1..=10);
+
+        let first = tasks.list(&page(4, 0), dropped_at(10));
+        assert_eq!(ids(&first), [1, 2, 3, 4]);
+        assert_eq!(first.next_page_token, 5);
+
+        // Tasks 1 to 5 are now more than ten seconds old, including the one
+        // the page token points at, so the next page starts after them.
+        let next = tasks.list(&page(4, first.next_page_token), dropped_at(16));
+        assert_eq!(ids(&next), [6, 7, 8, 9]);
+        assert_eq!(next.next_page_token, 10);
+
+        // Evicting the oldest summary to make room doesn't renumber the rest.
+        let mut tasks = TaskArchive::new(5, HOUR);
+        archive(&mut tasks, 1..=5);
+        let first = tasks.list(&page(2, 0), dropped_at(5));
+        assert_eq!(ids(&first), [1, 2]);
+        archive(&mut tasks, 6..=8);
+        let next = tasks.list(&page(2, first.next_page_token), dropped_at(8));
+        assert_eq!(ids(&next), [4, 5]);
+    }
+
+    #[test]
+    fn page_size_is_capped() {
+        let mut tasks = TaskArchive::new(2 * TaskArchive::MAX_PAGE_SIZE, HOUR);
+        archive(&mut tasks, 1..=1500);
+        let now = dropped_at(1500);
+
+        for page_size in [0, 5000] {
+            let response = tasks.list(&page(page_size, 0), now);
+            assert_eq!(response.tasks.len(), TaskArchive::MAX_PAGE_SIZE);
+            assert_eq!(response.next_page_token, 1001);
+        }
+        let response = tasks.list(&page(10, 0), now);
+        assert_eq!(response.tasks.len(), 10);
+        assert_eq!(response.next_page_token, 11);
+    }
+
+    #[test]
+    fn last_page_has_no_next_page_token() {
+        let mut tasks = TaskArchive::new(100, HOUR);
+        archive(&mut tasks, 1..=5);
+        let now = dropped_at(5);
+
+        let all = tasks.list(&page(5, 0), now);
+        assert_eq!(ids(&all), [1, 2, 3, 4, 5]);
+        assert_eq!(all.next_page_token, 0);
+
+        let first = tasks.list(&page(3, 0), now);
+        assert_eq!(first.next_page_token, 4);
+        let last = tasks.list(&page(3, first.next_page_token), now);
+        assert_eq!(ids(&last), [4, 5]);
+        assert_eq!(last.next_page_token, 0);
+    }
+
+    #[test]
+    fn filters_match_name_location_and_time() {
+        let mut tasks = TaskArchive::new(100, HOUR);
+        archive(&mut tasks, 1..=12);
+        let now = dropped_at(12);
+        let list = |tasks: &mut TaskArchive, request| ids(&tasks.list(&request, now));
+
+        let named = proto::instrument::ListCompletedTasksRequest {
+            name: Some("worker 1".into()),
+            ..Default::default()
+        };
+        assert_eq!(list(&mut tasks, named), [10, 12]);
+
+        let located = proto::instrument::ListCompletedTasksRequest {
+            location: Some("src/main.rs:1".into()),
+            ..Default::default()
+        };
+        assert_eq!(list(&mut tasks, located), [1, 10, 11, 12]);
+
+        // `completed_after` is inclusive, and `completed_before` exclusive.
+        let between = proto::instrument::ListCompletedTasksRequest {
+            completed_after: Some(dropped_at(3).into()),
+            completed_before: Some(dropped_at(6).into()),
+            ..Default::default()
+        };
+        assert_eq!(list(&mut tasks, between), [3, 4, 5]);
+
+        let all = proto::instrument::ListCompletedTasksRequest {
+            name: Some("worker".into()),
+            location: Some("src/main.rs".into()),
+            completed_after: Some(dropped_at(5).into()),
+            ..Default::default()
+        };
+        assert_eq!(list(&mut tasks, all), [6, 8, 10, 12]);
+    }
+}
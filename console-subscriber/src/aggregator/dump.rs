@@ -0,0 +1,99 @@
+use super::{local_snapshot::task_snapshot, Aggregator};
+use crate::snapshot;
+use console_api as proto;
+use prost::Message;
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::mpsc as blocking,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+impl Aggregator {
+    /// Writes the complete current state to a new file in the dump directory,
+    /// and sends the file's path, or the error which writing it failed with,
+    /// to `tx`.
+    ///
+    /// Events which are still buffered are aggregated first, so that the dump
+    /// includes everything recorded before it was requested. The file is
+    /// written on a blocking thread, so that aggregation carries on while a
+    /// large dump is written.
+    pub(super) fn dump(&mut self, reason: String, tx: blocking::Sender<io::Result<PathBuf>>) {
+        self.drain_events();
+        let now = self.anchor.now();
+        let state = self.snapshot_update(now, &[]);
+
+        let mut task_details = self
+            .task_stats
+            .all()
+            .map(|(&id, stats)| stats.details_proto(id, now))
+            .collect::<Vec<_>>();
+        task_details.sort_by_key(|details| details.task_id.as_ref().map(|id| id.id));
+
+        let mut tasks = self
+            .tasks
+            .all()
+            .filter_map(|(id, task)| Some(task_snapshot(task, self.task_stats.get(id)?)))
+            .collect::<Vec<_>>();
+        tasks.sort_by_key(|task| task.id);
+        let warnings = tasks
+            .iter()
+            .flat_map(|task| {
+                task_warnings(task)
+                    .into_iter()
+                    .map(move |warning| proto::instrument::TaskWarning {
+                        task_id: Some(task.id.into()),
+                        warning,
+                    })
+            })
+            .collect();
+
+        let dump = proto::instrument::Dump {
+            reason,
+            state: Some(state),
+            task_details,
+            warnings,
+        };
+
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = self.dump_dir.join(format!(
+            "tokio-console-{}-{}.dump",
+            std::process::id(),
+            since_epoch.as_millis()
+        ));
+        tokio::task::spawn_blocking(move || {
+            let dumped = fs::write(&path, dump.encode_to_vec()).map(|()| path);
+            match &dumped {
+                Ok(path) => tracing::info!(path = %path.display(), "dumped console state"),
+                Err(error) => tracing::warn!(%error, "failed to dump console state"),
+            }
+            // If this fails, whoever asked for the dump isn't waiting for it.
+            let _ = tx.send(dumped);
+        });
+    }
+}
+
+/// Returns descriptions of the problems the console would warn about for
+/// `task`.
+pub(super) fn task_warnings(task: &snapshot::Task) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if task.has_lost_waker() {
+        warnings.push(proto::warnings::LOST_WAKER.to_string());
+    }
+    let self_wakes = task.self_wake_percent();
+    if self_wakes > proto::warnings::DEFAULT_SELF_WAKE_PERCENT {
+        warnings.push(proto::warnings::self_wakes(
+            proto::warnings::DEFAULT_SELF_WAKE_PERCENT,
+            self_wakes,
+        ));
+    }
+    if let Some(blocking_poll) = task.blocking_poll {
+        warnings.push(proto::warnings::blocking_poll(blocking_poll));
+    }
+    if task.events_dropped {
+        warnings.push(proto::warnings::EVENTS_DROPPED.to_string());
+    }
+    warnings
+}
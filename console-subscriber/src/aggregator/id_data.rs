@@ -25,13 +25,6 @@ impl<T> Default for IdData<T> {
 }
 
 impl<T> IdData<T> {
-    pub(crate) fn update_or_default(&mut self, id: Id) -> Updating<'_, T>
-    where
-        T: Default,
-    {
-        Updating(self.data.entry(id).or_default())
-    }
-
     pub(crate) fn update(&mut self, id: &Id) -> Option<Updating<'_, T>> {
         self.data.get_mut(id).map(Updating)
     }
@@ -55,10 +48,23 @@ impl<T> IdData<T> {
         self.data.iter().map(|(id, (data, _))| (id, data))
     }
 
+    /// Returns every entity's data, without marking any of it as updated.
+    pub(crate) fn all_mut(&mut self) -> impl Iterator<Item = (&Id, &mut T)> {
+        self.data.iter_mut().map(|(id, (data, _))| (id, data))
+    }
+
     pub(crate) fn get(&self, id: &Id) -> Option<&T> {
         self.data.get(id).map(|(data, _)| data)
     }
 
+    pub(crate) fn remove(&mut self, id: &Id) -> Option<T> {
+        self.data.remove(id).map(|(data, _)| data)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
     pub(crate) fn as_proto(&mut self, include: Include) -> HashMap<u64, T::Output>
     where
         T: ToProto,
@@ -72,6 +78,11 @@ impl<T> IdData<T> {
         }
     }
 
+    /// Drops the entities which closed longer than `retention` ago, except
+    /// for those whose final state hasn't been sent to the current watchers.
+    ///
+    /// The IDs of the dropped entities are added to `dropped`. Returns `true`
+    /// if any of the dropped entities' final state was never published.
     pub(crate) fn drop_closed<R: DroppedAt>(
         &mut self,
         stats: &mut IdData<R>,
@@ -79,7 +90,8 @@ impl<T> IdData<T> {
         retention: Duration,
         has_watchers: bool,
         ids: &mut Ids,
-    ) {
+        dropped: &mut HashSet<Id>,
+    ) -> bool {
         let _span = tracing::debug_span!(
             "drop_closed",
             entity = %std::any::type_name::<T>(),
@@ -91,13 +103,14 @@ impl<T> IdData<T> {
         tracing::trace!(?retention, has_watchers, "dropping closed");
 
         let mut dropped_ids = HashSet::new();
+        let mut dropped_unpublished = false;
         stats.data.retain_and_shrink(|id, (stats, dirty)| {
             if let Some(dropped_at) = stats.dropped_at() {
                 let dropped_for = now.duration_since(dropped_at).unwrap_or_default();
                 let should_drop =
                         // if there are any clients watching, retain all dirty tasks regardless of age
-                        (*dirty && has_watchers)
-                        || dropped_for > retention;
+                        !(*dirty && has_watchers)
+                        && dropped_for > retention;
                 tracing::trace!(
                     stats.id = ?id,
                     stats.dropped_at = ?dropped_at,
@@ -108,6 +121,7 @@ impl<T> IdData<T> {
 
                 if should_drop {
                     dropped_ids.insert(*id);
+                    dropped_unpublished |= *dirty;
                 }
                 return !should_drop;
             }
@@ -124,7 +138,9 @@ impl<T> IdData<T> {
             self.data
                 .retain_and_shrink(|id, (_, _)| stats.data.contains_key(id));
             ids.remove_all(&dropped_ids);
+            dropped.extend(dropped_ids);
         }
+        dropped_unpublished
     }
 }
 
@@ -0,0 +1,290 @@
+use super::{id_data::IdData, shrink::ShrinkVec, Id};
+use console_api as proto;
+use std::collections::{HashSet, VecDeque};
+
+/// Limits on the number of tasks, resources, async ops, and poll ops which the
+/// aggregator tracks at once.
+pub(super) struct Limits {
+    pub(super) tasks: Limit,
+    pub(super) resources: Limit,
+    pub(super) async_ops: Limit,
+
+    /// The maximum number of poll ops to keep for new clients.
+    max_poll_ops: usize,
+
+    /// The number of poll ops evicted since the last update.
+    pub(super) evicted_poll_ops: u64,
+}
+
+/// A limit on the number of entities of one kind which are tracked at once.
+pub(super) struct Limit {
+    /// The maximum number of entities to track, if there is one.
+    max: Option<usize>,
+
+    /// Completed entities which may be evicted to make room for new ones,
+    /// starting with the one which completed longest ago.
+    ///
+    /// This may still contain entities which were dropped when they stopped
+    /// being retained, which are skipped.
+    completed: VecDeque<Id>,
+
+    /// The entities evicted since the last update, which clients should
+    /// forget.
+    pub(super) evicted: Vec<Id>,
+
+    /// The entities evicted since the span ID mappings were last cleaned up.
+    unmapped: Vec<Id>,
+
+    /// The number of entities which weren't tracked since the last update.
+    pub(super) untracked: u64,
+}
+
+// === impl Limits ===
+
+impl Limits {
+    pub(super) fn new(builder: &crate::Builder) -> Self {
+        Self {
+            tasks: Limit::new(builder.max_tasks),
+            resources: Limit::new(builder.max_resources),
+            async_ops: Limit::new(builder.max_async_ops),
+            max_poll_ops: builder.max_poll_ops,
+            evicted_poll_ops: 0,
+        }
+    }
+
+    /// Adds a poll op to those kept for new clients, evicting the oldest ones
+    /// if too many are kept.
+    pub(super) fn push_poll_op(
+        &mut self,
+        poll_ops: &mut ShrinkVec<proto::resources::PollOp>,
+        poll_op: proto::resources::PollOp,
+    ) {
+        if self.max_poll_ops == 0 {
+            self.evicted_poll_ops += 1;
+            return;
+        }
+        if poll_ops.len() >= self.max_poll_ops {
+            // Evict an eighth of the poll ops at a time, rather than shifting
+            // every poll op along for each new one.
+            let evict = (self.max_poll_ops / 8).max(1).min(poll_ops.len());
+            poll_ops.drain(..evict);
+            self.evicted_poll_ops += evict as u64;
+        }
+        poll_ops.push(poll_op);
+    }
+
+    /// Returns every entity evicted since the span ID mappings were last
+    /// cleaned up.
+    pub(super) fn take_unmapped(&mut self) -> HashSet<Id> {
+        self.tasks
+            .unmapped
+            .drain(..)
+            .chain(self.resources.unmapped.drain(..))
+            .chain(self.async_ops.unmapped.drain(..))
+            .collect()
+    }
+
+    /// Forgets the entities evicted since the last update, when there are no
+    /// clients to tell about them.
+    ///
+    /// Returns `true` if any entities were forgotten.
+    pub(super) fn clear_evicted(&mut self) -> bool {
+        let any = !(self.tasks.evicted.is_empty()
+            && self.resources.evicted.is_empty()
+            && self.async_ops.evicted.is_empty());
+        self.tasks.evicted.clear();
+        self.resources.evicted.clear();
+        self.async_ops.evicted.clear();
+        any
+    }
+}
+
+// === impl Limit ===
+
+impl Limit {
+    fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            completed: VecDeque::new(),
+            evicted: Vec::new(),
+            unmapped: Vec::new(),
+            untracked: 0,
+        }
+    }
+
+    /// Records that the entity `id` completed, so that it may be evicted.
+    pub(super) fn completed(&mut self, id: Id) {
+        // Without a limit, nothing is ever evicted.
+        if self.max.is_some() {
+            self.completed.push_back(id);
+        }
+    }
+
+    /// Makes room to track a new entity, by evicting the entity in `data` and
+    /// `stats` which completed longest ago, if as many entities as the limit
+    /// allows are tracked.
+    ///
+    /// Returns `false` if the new entity shouldn't be tracked, because no
+    /// tracked entity has completed yet.
+    pub(super) fn make_room<T, S>(&mut self, data: &mut IdData<T>, stats: &mut IdData<S>) -> bool {
+        let max = match self.max {
+            Some(max) => max,
+            None => return true,
+        };
+        while stats.len() >= max {
+            match self.completed.pop_front() {
+                Some(id) => {
+                    if stats.remove(&id).is_some() {
+                        data.remove(&id);
+                        self.evicted.push(id);
+                        self.unmapped.push(id);
+                    }
+                }
+                None => {
+                    self.untracked += 1;
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Skips the completed entities which have already been dropped from
+    /// `stats`, from the one which completed longest ago.
+    pub(super) fn skip_dropped<S>(&mut self, stats: &IdData<S>) {
+        while let Some(id) = self.completed.front() {
+            if stats.get(id).is_some() {
+                break;
+            }
+            self.completed.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(ids: impl IntoIterator<Item = Id>) -> (IdData<()>, IdData<()>) {
+        let mut data = IdData::default();
+        let mut stats = IdData::default();
+        for id in ids {
+            data.insert(id, ());
+            stats.insert(id, ());
+        }
+        (data, stats)
+    }
+
+    fn poll_op(id: Id) -> proto::resources::PollOp {
+        proto::resources::PollOp {
+            async_op_id: Some(id.into()),
+            ..Default::default()
+        }
+    }
+
+    fn poll_op_ids(poll_ops: &[proto::resources::PollOp]) -> Vec<Id> {
+        poll_ops
+            .iter()
+            .map(|poll_op| poll_op.async_op_id.as_ref().unwrap().id)
+            .collect()
+    }
+
+    #[test]
+    fn evicts_the_oldest_completed_entity_first() {
+        let mut limit = Limit::new(Some(3));
+        let (mut data, mut stats) = tracked(1..=3);
+        limit.completed(2);
+        limit.completed(1);
+
+        assert!(limit.make_room(&mut data, &mut stats));
+        assert!(data.get(&2).is_none());
+        assert!(stats.get(&2).is_none());
+        assert_eq!(limit.evicted, [2]);
+        data.insert(4, ());
+        stats.insert(4, ());
+
+        assert!(limit.make_room(&mut data, &mut stats));
+        assert!(stats.get(&1).is_none());
+        assert_eq!(limit.evicted, [2, 1]);
+        assert_eq!(limit.unmapped, [2, 1]);
+        assert_eq!(limit.untracked, 0);
+    }
+
+    #[test]
+    fn refuses_new_entities_while_all_are_live() {
+        let mut limit = Limit::new(Some(2));
+        let (mut data, mut stats) = tracked(1..=2);
+
+        assert!(!limit.make_room(&mut data, &mut stats));
+        assert!(!limit.make_room(&mut data, &mut stats));
+        assert_eq!(stats.len(), 2);
+        assert!(limit.evicted.is_empty());
+        assert_eq!(limit.untracked, 2);
+
+        // Without a limit, completed entities aren't even remembered.
+        let mut limit = Limit::new(None);
+        limit.completed(1);
+        assert!(limit.completed.is_empty());
+        assert!(limit.make_room(&mut data, &mut stats));
+        assert_eq!(stats.len(), 2);
+    }
+
+    #[test]
+    fn skips_entities_which_were_already_dropped() {
+        let mut limit = Limit::new(Some(2));
+        let (mut data, mut stats) = tracked(1..=3);
+        for id in 1..=3 {
+            limit.completed(id);
+        }
+        // Entity 1 is dropped once it is no longer retained.
+        data.remove(&1);
+        stats.remove(&1);
+
+        limit.skip_dropped(&stats);
+        assert_eq!(limit.completed, [2, 3]);
+
+        data.remove(&2);
+        stats.remove(&2);
+        data.insert(4, ());
+        stats.insert(4, ());
+        // Entity 2 was dropped rather than evicted, so clients already forgot
+        // it and aren't told about it again.
+        assert!(limit.make_room(&mut data, &mut stats));
+        assert_eq!(limit.evicted, [3]);
+        assert!(limit.completed.is_empty());
+    }
+
+    #[test]
+    fn poll_ops_are_evicted_an_eighth_at_a_time() {
+        let mut limits = Limits::new(&crate::Builder::default().max_poll_ops(16));
+        let mut poll_ops = ShrinkVec::new();
+        for id in 0..16 {
+            limits.push_poll_op(&mut poll_ops, poll_op(id));
+        }
+        assert_eq!(limits.evicted_poll_ops, 0);
+
+        limits.push_poll_op(&mut poll_ops, poll_op(16));
+        assert_eq!(poll_op_ids(&poll_ops), (2..=16).collect::<Vec<_>>());
+        assert_eq!(limits.evicted_poll_ops, 2);
+
+        // At least one poll op is evicted, however few are kept.
+        let mut limits = Limits::new(&crate::Builder::default().max_poll_ops(3));
+        let mut poll_ops = ShrinkVec::new();
+        for id in 0..4 {
+            limits.push_poll_op(&mut poll_ops, poll_op(id));
+        }
+        assert_eq!(poll_op_ids(&poll_ops), [1, 2, 3]);
+        assert_eq!(limits.evicted_poll_ops, 1);
+    }
+
+    #[test]
+    fn no_poll_ops_are_kept_without_room_for_any() {
+        let mut limits = Limits::new(&crate::Builder::default().max_poll_ops(0));
+        let mut poll_ops = ShrinkVec::new();
+        for id in 0..3 {
+            limits.push_poll_op(&mut poll_ops, poll_op(id));
+        }
+        assert!(poll_ops.is_empty());
+        assert_eq!(limits.evicted_poll_ops, 3);
+    }
+}
@@ -0,0 +1,172 @@
+use super::{
+    Aggregator, AsyncOp, AsyncOpStats, PollStats, Resource, ResourceStats, Task, TaskStats,
+};
+use crate::snapshot;
+use console_api as proto;
+use proto::resources::resource;
+use std::sync::atomic::Ordering::Acquire;
+
+impl Aggregator {
+    /// Returns a typed snapshot of the current state, for a [`ConsoleHandle`].
+    ///
+    /// Events which are still buffered are aggregated first, so that the
+    /// snapshot includes everything recorded before it was requested.
+    ///
+    /// [`ConsoleHandle`]: crate::ConsoleHandle
+    pub(super) fn local_snapshot(&mut self) -> snapshot::Snapshot {
+        self.drain_events();
+        let now = self.anchor.now();
+        self.update_blocking_polls(now);
+
+        let mut tasks = self
+            .tasks
+            .all()
+            .filter_map(|(id, task)| Some(task_snapshot(task, self.task_stats.get(id)?)))
+            .collect::<Vec<_>>();
+        tasks.sort_by_key(|task| task.id);
+
+        let mut resources = self
+            .resources
+            .all()
+            .filter_map(|(id, resource)| {
+                Some(resource_snapshot(resource, self.resource_stats.get(id)?))
+            })
+            .collect::<Vec<_>>();
+        resources.sort_by_key(|resource| resource.id);
+
+        let mut async_ops = self
+            .async_ops
+            .all()
+            .filter_map(|(id, async_op)| {
+                Some(async_op_snapshot(async_op, self.async_op_stats.get(id)?))
+            })
+            .collect::<Vec<_>>();
+        async_ops.sort_by_key(|async_op| async_op.id);
+
+        snapshot::Snapshot {
+            now,
+            tasks,
+            resources,
+            async_ops,
+            dropped_events: snapshot::DroppedEvents {
+                tasks: self.shared.dropped_tasks.load(Acquire) as u64,
+                resources: self.shared.dropped_resources.load(Acquire) as u64,
+                async_ops: self.shared.dropped_async_ops.load(Acquire) as u64,
+            },
+        }
+    }
+}
+
+pub(super) fn task_snapshot(task: &Task, stats: &TaskStats) -> snapshot::Task {
+    let mut name = None;
+    let mut fields = Vec::with_capacity(task.fields.len());
+    for (field_name, value) in task.fields.iter().filter_map(field) {
+        if field_name == super::archive::TaskArchive::NAME_FIELD {
+            // Tokio records an empty name for tasks which weren't given one.
+            name = Some(value).filter(|name| !name.is_empty());
+        } else {
+            fields.push((field_name, value));
+        }
+    }
+
+    snapshot::Task {
+        id: task.id,
+        name,
+        target: task.metadata.target(),
+        location: task.location.as_ref().map(ToString::to_string),
+        fields,
+        created_at: stats.created_at,
+        dropped_at: stats.dropped_at,
+        poll_stats: poll_stats(&stats.poll_stats),
+        poll_times: snapshot::PollTimes::new(stats.poll_times_histogram.clone()),
+        wakes: stats.wakes,
+        self_wakes: stats.self_wakes,
+        waker_clones: stats.waker_clones,
+        waker_drops: stats.waker_drops,
+        last_wake: stats.last_wake,
+        blocking_poll: stats.blocking_poll,
+        events_dropped: stats.events_dropped,
+    }
+}
+
+fn resource_snapshot(resource: &Resource, stats: &ResourceStats) -> snapshot::Resource {
+    snapshot::Resource {
+        id: resource.id,
+        parent_id: resource.parent_id,
+        kind: resource_kind(&resource.kind),
+        concrete_type: resource.concrete_type.clone(),
+        target: resource.metadata.target(),
+        location: resource.location.as_ref().map(ToString::to_string),
+        is_internal: resource.is_internal,
+        created_at: stats.created_at,
+        dropped_at: stats.dropped_at,
+        attributes: attributes(stats.attributes.values()),
+        events_dropped: stats.events_dropped,
+    }
+}
+
+/// Returns the name of a resource's kind, such as `"Timer"`.
+pub(crate) fn resource_kind(kind: &resource::Kind) -> String {
+    match &kind.kind {
+        Some(resource::kind::Kind::Known(known)) => resource::kind::Known::from_i32(*known)
+            .map(|known| format!("{:?}", known))
+            .unwrap_or_default(),
+        Some(resource::kind::Kind::Other(other)) => other.clone(),
+        None => String::new(),
+    }
+}
+
+fn async_op_snapshot(async_op: &AsyncOp, stats: &AsyncOpStats) -> snapshot::AsyncOp {
+    snapshot::AsyncOp {
+        id: async_op.id,
+        parent_id: async_op.parent_id,
+        resource_id: async_op.resource_id,
+        task_id: stats.task_id,
+        source: async_op.source.clone(),
+        target: async_op.metadata.target(),
+        created_at: stats.created_at,
+        dropped_at: stats.dropped_at,
+        poll_stats: poll_stats(&stats.poll_stats),
+        attributes: attributes(stats.attributes.values()),
+        events_dropped: stats.events_dropped,
+    }
+}
+
+fn poll_stats(stats: &PollStats) -> snapshot::PollStats {
+    snapshot::PollStats {
+        polls: stats.polls,
+        first_poll: stats.first_poll,
+        last_poll_started: stats.last_poll_started,
+        last_poll_ended: stats.last_poll_ended,
+        busy_time: stats.busy_time,
+    }
+}
+
+fn attributes<'a>(
+    attributes: impl Iterator<Item = &'a proto::Attribute>,
+) -> Vec<snapshot::Attribute> {
+    let mut attributes = attributes
+        .filter_map(|attribute| {
+            let (name, value) = field(attribute.field.as_ref()?)?;
+            Some(snapshot::Attribute {
+                name,
+                value,
+                unit: attribute.unit.clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+    attributes.sort_by(|a, b| a.name.cmp(&b.name));
+    attributes
+}
+
+/// Returns a field's name and formatted value.
+///
+/// The layer always records field names as strings, rather than as indices
+/// into the fields of the span's metadata.
+fn field(field: &proto::Field) -> Option<(String, String)> {
+    let name = match field.name.as_ref()? {
+        proto::field::Name::StrName(name) => name.clone(),
+        proto::field::Name::NameIdx(_) => return None,
+    };
+    Some((name, field.value.as_ref()?.to_string()))
+}
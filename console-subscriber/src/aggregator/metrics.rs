@@ -0,0 +1,592 @@
+use super::{local_snapshot::resource_kind, Aggregator, Id};
+use crate::WakeOp;
+use console_api as proto;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering::*},
+    time::{Duration, SystemTime},
+};
+
+/// The upper bounds of the poll duration histogram buckets, in seconds.
+const POLL_DURATION_BUCKETS: [f64; 7] = [0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0, 10.0];
+
+/// The location label of tasks whose spawn location isn't known.
+const UNKNOWN_LOCATION: &str = "unknown";
+
+/// Cumulative metrics, aggregated by spawn location rather than by task, so
+/// that the number of series stays bounded by the number of places tasks are
+/// spawned from.
+///
+/// Unlike the state sent to clients, these counters are never reset, and
+/// still include tasks which have been dropped once the retention period has
+/// passed.
+#[derive(Debug, Default)]
+pub(super) struct Metrics {
+    /// The index into `locations` of the location each live task was spawned
+    /// at.
+    tasks: HashMap<Id, usize>,
+    location_ids: HashMap<String, usize>,
+    locations: Vec<LocationMetrics>,
+    pub(super) dropped_events: DroppedEvents,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct DroppedEvents {
+    pub(super) tasks: u64,
+    pub(super) resources: u64,
+    pub(super) async_ops: u64,
+}
+
+#[derive(Debug)]
+struct LocationMetrics {
+    name: String,
+    spawned: u64,
+    completed: u64,
+    busy_time: Duration,
+    /// The idle time of tasks which have completed.
+    completed_idle_time: Duration,
+    /// The number of polls which took no longer than each of
+    /// `POLL_DURATION_BUCKETS`, and then the total number of polls.
+    poll_buckets: [u64; POLL_DURATION_BUCKETS.len() + 1],
+    wakes: u64,
+    wakes_by_ref: u64,
+    self_wakes: u64,
+    waker_clones: u64,
+    waker_drops: u64,
+}
+
+/// The state of the tasks spawned at a location, computed when the metrics
+/// are rendered.
+#[derive(Default)]
+struct LocationState {
+    running: u64,
+    idle: u64,
+    completed: u64,
+    idle_time: Duration,
+}
+
+impl Aggregator {
+    /// Returns the current metrics in the Prometheus text exposition format.
+    ///
+    /// Events which are still buffered are aggregated first, so that the
+    /// metrics include everything recorded before they were requested.
+    pub(super) fn prometheus_metrics(&mut self) -> String {
+        self.drain_events();
+        let now = self.anchor.now();
+
+        let mut states: Vec<LocationState> = self
+            .metrics
+            .locations
+            .iter()
+            .map(|location| LocationState {
+                idle_time: location.completed_idle_time,
+                ..Default::default()
+            })
+            .collect();
+        for (id, stats) in self.task_stats.all() {
+            let state = match self.metrics.tasks.get(id) {
+                Some(&location) => &mut states[location],
+                // Completed tasks have already been counted.
+                None => {
+                    if let Some(task) = self.tasks.get(id) {
+                        states[task.metrics_location].completed += 1;
+                    }
+                    continue;
+                }
+            };
+            if stats.poll_stats.current_polls > 0 {
+                state.running += 1;
+            } else {
+                state.idle += 1;
+            }
+            state.idle_time += idle_time(stats.created_at, now, stats.poll_stats.busy_time);
+        }
+
+        let mut resources = BTreeMap::<String, u64>::new();
+        for (id, resource) in self.resources.all() {
+            let live = self
+                .resource_stats
+                .get(id)
+                .map_or(false, |stats| stats.dropped_at.is_none());
+            if live {
+                *resources.entry(resource_kind(&resource.kind)).or_default() += 1;
+            }
+        }
+
+        let dropped = &self.metrics.dropped_events;
+        let dropped_events = [
+            (
+                "task",
+                dropped.tasks + self.shared.dropped_tasks.load(Acquire) as u64,
+            ),
+            (
+                "resource",
+                dropped.resources + self.shared.dropped_resources.load(Acquire) as u64,
+            ),
+            (
+                "async_op",
+                dropped.async_ops + self.shared.dropped_async_ops.load(Acquire) as u64,
+            ),
+        ];
+
+        let mut text = String::new();
+        // Writing to a `String` never fails.
+        let _ = self
+            .metrics
+            .render(&mut text, &states, &resources, &dropped_events);
+        text
+    }
+}
+
+impl Metrics {
+    /// Counts a newly spawned task, returning the index of its location.
+    pub(super) fn task_spawned(&mut self, id: Id, location: Option<&proto::Location>) -> usize {
+        let location = self.location_id_or_insert(location);
+        self.locations[location].spawned += 1;
+        self.tasks.insert(id, location);
+        location
+    }
+
+    pub(super) fn task_polled(&mut self, id: Id, duration: Duration) {
+        if let Some(location) = self.location(id) {
+            location.busy_time += duration;
+            let seconds = duration.as_secs_f64();
+            for (bucket, &le) in location.poll_buckets.iter_mut().zip(&POLL_DURATION_BUCKETS) {
+                if seconds <= le {
+                    *bucket += 1;
+                }
+            }
+            location.poll_buckets[POLL_DURATION_BUCKETS.len()] += 1;
+        }
+    }
+
+    pub(super) fn task_completed(
+        &mut self,
+        id: Id,
+        created_at: Option<SystemTime>,
+        at: SystemTime,
+        busy_time: Duration,
+    ) {
+        if let Some(location) = self.location(id) {
+            location.completed += 1;
+            location.completed_idle_time += idle_time(created_at, at, busy_time);
+        }
+        self.tasks.remove(&id);
+    }
+
+    pub(super) fn waker_op(&mut self, id: Id, op: WakeOp) {
+        if let Some(location) = self.location(id) {
+            match op {
+                WakeOp::Wake { self_wake } => {
+                    location.wakes += 1;
+                    location.self_wakes += self_wake as u64;
+                }
+                WakeOp::WakeByRef { self_wake } => {
+                    location.wakes_by_ref += 1;
+                    location.self_wakes += self_wake as u64;
+                }
+                WakeOp::Clone => location.waker_clones += 1,
+                WakeOp::Drop => location.waker_drops += 1,
+            }
+        }
+    }
+
+    fn location(&mut self, id: Id) -> Option<&mut LocationMetrics> {
+        let &location = self.tasks.get(&id)?;
+        Some(&mut self.locations[location])
+    }
+
+    fn location_id_or_insert(&mut self, location: Option<&proto::Location>) -> usize {
+        let name = location.map_or_else(|| UNKNOWN_LOCATION.to_string(), ToString::to_string);
+        let locations = &mut self.locations;
+        *self.location_ids.entry(name).or_insert_with_key(|name| {
+            locations.push(LocationMetrics::new(name.clone()));
+            locations.len() - 1
+        })
+    }
+
+    fn render(
+        &self,
+        f: &mut String,
+        states: &[LocationState],
+        resources: &BTreeMap<String, u64>,
+        dropped_events: &[(&str, u64)],
+    ) -> fmt::Result {
+        // Render locations in a stable order, so that the output is easy to
+        // read and to compare.
+        let mut locations = self.locations.iter().zip(states).collect::<Vec<_>>();
+        locations.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+        header(
+            f,
+            "tokio_console_tasks",
+            "gauge",
+            "The number of tasks which are running, idle, or have completed within the retention period, by spawn location.",
+        )?;
+        for (location, state) in &locations {
+            for (name, count) in [
+                ("running", state.running),
+                ("idle", state.idle),
+                ("completed", state.completed),
+            ] {
+                writeln!(
+                    f,
+                    "tokio_console_tasks{{location=\"{}\",state=\"{}\"}} {}",
+                    Escape(&location.name),
+                    name,
+                    count
+                )?;
+            }
+        }
+
+        per_location(
+            f,
+            "tokio_console_tasks_spawned_total",
+            "counter",
+            "The number of tasks spawned, by spawn location.",
+            &locations,
+            |location, _| location.spawned,
+        )?;
+        per_location(
+            f,
+            "tokio_console_tasks_completed_total",
+            "counter",
+            "The number of tasks which have completed, by spawn location.",
+            &locations,
+            |location, _| location.completed,
+        )?;
+        per_location(
+            f,
+            "tokio_console_task_busy_seconds_total",
+            "counter",
+            "The total time tasks have spent being polled, by spawn location.",
+            &locations,
+            |location, _| location.busy_time.as_secs_f64(),
+        )?;
+        per_location(
+            f,
+            "tokio_console_task_idle_seconds_total",
+            "counter",
+            "The total time tasks have existed without being polled, by spawn location.",
+            &locations,
+            |_, state| state.idle_time.as_secs_f64(),
+        )?;
+
+        header(
+            f,
+            "tokio_console_task_poll_duration_seconds",
+            "histogram",
+            "The durations of task polls, by spawn location.",
+        )?;
+        for (location, _) in &locations {
+            let name = Escape(&location.name);
+            for (le, count) in POLL_DURATION_BUCKETS.iter().zip(&location.poll_buckets) {
+                writeln!(
+                    f,
+                    "tokio_console_task_poll_duration_seconds_bucket{{location=\"{}\",le=\"{}\"}} {}",
+                    name, le, count
+                )?;
+            }
+            let polls = location.poll_buckets[POLL_DURATION_BUCKETS.len()];
+            writeln!(
+                f,
+                "tokio_console_task_poll_duration_seconds_bucket{{location=\"{}\",le=\"+Inf\"}} {}",
+                name, polls
+            )?;
+            writeln!(
+                f,
+                "tokio_console_task_poll_duration_seconds_sum{{location=\"{}\"}} {}",
+                name,
+                location.busy_time.as_secs_f64()
+            )?;
+            writeln!(
+                f,
+                "tokio_console_task_poll_duration_seconds_count{{location=\"{}\"}} {}",
+                name, polls
+            )?;
+        }
+
+        header(
+            f,
+            "tokio_console_task_waker_ops_total",
+            "counter",
+            "The number of operations on tasks' wakers, by spawn location and operation.",
+        )?;
+        for (location, _) in &locations {
+            for (op, count) in [
+                ("wake", location.wakes),
+                ("wake_by_ref", location.wakes_by_ref),
+                ("clone", location.waker_clones),
+                ("drop", location.waker_drops),
+            ] {
+                writeln!(
+                    f,
+                    "tokio_console_task_waker_ops_total{{location=\"{}\",op=\"{}\"}} {}",
+                    Escape(&location.name),
+                    op,
+                    count
+                )?;
+            }
+        }
+
+        per_location(
+            f,
+            "tokio_console_task_self_wakes_total",
+            "counter",
+            "The number of times tasks have woken themselves, by spawn location.",
+            &locations,
+            |location, _| location.self_wakes,
+        )?;
+
+        header(
+            f,
+            "tokio_console_resources",
+            "gauge",
+            "The number of resources which have not been dropped, by kind.",
+        )?;
+        for (kind, count) in resources {
+            writeln!(
+                f,
+                "tokio_console_resources{{kind=\"{}\"}} {}",
+                Escape(kind),
+                count
+            )?;
+        }
+
+        header(
+            f,
+            "tokio_console_dropped_events_total",
+            "counter",
+            "The number of events which were dropped because the event buffer was full, by the kind of object they describe.",
+        )?;
+        for (kind, count) in dropped_events {
+            writeln!(
+                f,
+                "tokio_console_dropped_events_total{{kind=\"{}\"}} {}",
+                kind, count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl LocationMetrics {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            spawned: 0,
+            completed: 0,
+            busy_time: Duration::ZERO,
+            completed_idle_time: Duration::ZERO,
+            poll_buckets: [0; POLL_DURATION_BUCKETS.len() + 1],
+            wakes: 0,
+            wakes_by_ref: 0,
+            self_wakes: 0,
+            waker_clones: 0,
+            waker_drops: 0,
+        }
+    }
+}
+
+/// Takes the number of events counted by `dropped` since it was last taken,
+/// adding it to the `total` reported in the metrics.
+pub(super) fn take_dropped(dropped: &AtomicUsize, total: &mut u64) -> u64 {
+    let dropped = dropped.swap(0, AcqRel) as u64;
+    *total += dropped;
+    dropped
+}
+
+fn idle_time(created_at: Option<SystemTime>, until: SystemTime, busy_time: Duration) -> Duration {
+    created_at
+        .and_then(|created_at| until.duration_since(created_at).ok())
+        .unwrap_or_default()
+        .saturating_sub(busy_time)
+}
+
+/// Writes a metric with one series for each location.
+fn per_location<T: fmt::Display>(
+    f: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    locations: &[(&LocationMetrics, &LocationState)],
+    value: impl Fn(&LocationMetrics, &LocationState) -> T,
+) -> fmt::Result {
+    header(f, name, kind, help)?;
+    for (location, state) in locations {
+        writeln!(
+            f,
+            "{}{{location=\"{}\"}} {}",
+            name,
+            Escape(&location.name),
+            value(location, state)
+        )?;
+    }
+    Ok(())
+}
+
+fn header(f: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(f, "# HELP {} {}", name, help)?;
+    writeln!(f, "# TYPE {} {}", name, kind)
+}
+
+/// Escapes a label value.
+struct Escape<'a>(&'a str);
+
+impl fmt::Display for Escape<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '\\' => f.write_str("\\\\")?,
+                '"' => f.write_str("\\\"")?,
+                '\n' => f.write_str("\\n")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aggregator::{
+            tests::{new_aggregator, spawn_task},
+            Event,
+        },
+        ConsoleLayer,
+    };
+    use std::time::Instant;
+    use tracing_core::span;
+
+    fn location(file: &str) -> proto::Location {
+        proto::Location {
+            file: Some(file.into()),
+            module_path: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    fn render(metrics: &Metrics) -> String {
+        let states = metrics
+            .locations
+            .iter()
+            .map(|_| LocationState::default())
+            .collect::<Vec<_>>();
+        let mut text = String::new();
+        metrics
+            .render(&mut text, &states, &BTreeMap::new(), &[])
+            .unwrap();
+        text
+    }
+
+    /// Returns the value of the series in `text` which starts with `series`.
+    fn value(text: &str, series: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no `{}` series in:\n{}", series, text))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn poll_duration_buckets_are_cumulative() {
+        let mut metrics = Metrics::default();
+        metrics.task_spawned(1, Some(&location("src/main.rs")));
+        for micros in [50, 5_000, 2_000_000, 20_000_000] {
+            metrics.task_polled(1, Duration::from_micros(micros));
+        }
+        let text = render(&metrics);
+
+        let buckets = text
+            .lines()
+            .filter(|line| line.starts_with("tokio_console_task_poll_duration_seconds_bucket{"))
+            .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+            .collect::<Vec<u64>>();
+        assert_eq!(buckets, [0, 1, 1, 2, 2, 2, 3, 4]);
+        let series = "tokio_console_task_poll_duration_seconds";
+        assert_eq!(
+            value(
+                &text,
+                &format!("{}_bucket{{location=\"src/main.rs\",le=\"+Inf\"}}", series)
+            ),
+            value(
+                &text,
+                &format!("{}_count{{location=\"src/main.rs\"}}", series)
+            ),
+        );
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(
+            Escape("a \"quoted\" C:\\path\nand more").to_string(),
+            "a \\\"quoted\\\" C:\\\\path\\nand more"
+        );
+
+        let mut metrics = Metrics::default();
+        metrics.task_spawned(1, Some(&location("C:\\src\\\"main\".rs\n")));
+        let text = render(&metrics);
+        assert_eq!(
+            value(
+                &text,
+                "tokio_console_tasks_spawned_total{location=\"C:\\\\src\\\\\\\"main\\\".rs\\n\"}"
+            ),
+            1.0
+        );
+        // Every series is still on a line of its own.
+        assert!(text
+            .lines()
+            .all(|line| line.starts_with('#') || line.starts_with("tokio_console_")));
+    }
+
+    #[test]
+    fn completed_tasks_move_to_completed() {
+        let mut aggregator = new_aggregator(ConsoleLayer::builder());
+        for task in 1..=3 {
+            spawn_task(&mut aggregator, task);
+        }
+        aggregator.update_state(Event::Enter {
+            id: span::Id::from_u64(1),
+            parent_id: None,
+            at: Instant::now(),
+        });
+        aggregator.update_state(Event::Close {
+            id: span::Id::from_u64(3),
+            at: Instant::now(),
+        });
+        let tasks = |aggregator: &mut Aggregator| {
+            let text = aggregator.prometheus_metrics();
+            ["running", "idle", "completed"].map(|state| {
+                let series = format!(
+                    "tokio_console_tasks{{location=\"{}\",state=\"{}\"}}",
+                    UNKNOWN_LOCATION, state
+                );
+                value(&text, &series)
+            })
+        };
+        assert_eq!(tasks(&mut aggregator), [1.0, 1.0, 1.0]);
+
+        aggregator.update_state(Event::Exit {
+            id: span::Id::from_u64(1),
+            parent_id: None,
+            at: Instant::now(),
+            async_backtrace: Vec::new(),
+            span_busy_times: Vec::new(),
+        });
+        aggregator.update_state(Event::Close {
+            id: span::Id::from_u64(1),
+            at: Instant::now(),
+        });
+        assert_eq!(tasks(&mut aggregator), [0.0, 1.0, 2.0]);
+        let text = aggregator.prometheus_metrics();
+        let completed = format!(
+            "tokio_console_tasks_completed_total{{location=\"{}\"}}",
+            UNKNOWN_LOCATION
+        );
+        assert_eq!(value(&text, &completed), 2.0);
+    }
+}
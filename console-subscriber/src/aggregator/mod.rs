@@ -1,16 +1,19 @@
 use super::{
-    AttributeUpdate, AttributeUpdateOp, Command, Event, Shared, UpdateType, WakeOp, Watch,
+    AttributeUpdate, AttributeUpdateOp, Command, Event, LostEvents, OverflowPolicy,
+    RecordingChange, SettingsUpdate, Shared, UpdateType, WakeOp, Watch,
 };
-use crate::{record::Recorder, WatchRequest};
+use crate::{buffer::Batches, clock::Anchor, record::Recorder, service::Encoded, WatchRequest};
 use console_api as proto;
+use proto::instrument::snapshot_request::Kind as SnapshotKind;
 use proto::resources::resource;
 use proto::Attribute;
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{mpsc, oneshot, Notify};
 
-use futures::FutureExt;
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     convert::TryInto,
+    io,
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering::*},
         Arc,
@@ -26,14 +29,47 @@ use hdrhistogram::{
 
 pub type Id = u64;
 
+/// The number of published updates which are kept so that clients which
+/// reconnect can resume their stream of updates.
+const MAX_RESUMABLE_UPDATES: usize = 64;
+
+/// The number of other tasks whose wakes are counted for each task, in each
+/// direction.
+///
+/// Once a task has this many, the edge with the fewest wakes is evicted to
+/// make room for a new one, so that a task which wakes many short-lived
+/// tasks, such as an accept loop, doesn't accumulate edges forever.
+const MAX_WAKE_EDGES: usize = 32;
+
+mod archive;
+mod dump;
 mod id_data;
+mod limits;
+mod local_snapshot;
+mod metrics;
+mod process_info;
+mod report;
 mod shrink;
+use self::archive::TaskArchive;
 use self::id_data::{IdData, Include};
+use self::limits::Limits;
+pub(crate) use self::local_snapshot::resource_kind;
+use self::metrics::{take_dropped, Metrics};
 use self::shrink::{ShrinkMap, ShrinkVec};
 
 pub(crate) struct Aggregator {
-    /// Channel of incoming events emitted by `TaskLayer`s.
-    events: mpsc::Receiver<Event>,
+    /// Batches of incoming events emitted by `TaskLayer`s.
+    events: Batches,
+
+    /// The batch of events currently being aggregated.
+    ///
+    /// This is kept around so that its allocation can be reused by the next
+    /// batch.
+    batch: Vec<Event>,
+
+    /// Converts the monotonic timestamps of incoming events into wall-clock
+    /// times.
+    anchor: Anchor,
 
     /// New incoming RPCs.
     rpcs: mpsc::Receiver<Command>,
@@ -44,12 +80,29 @@ pub(crate) struct Aggregator {
     /// How long to keep task data after a task has completed.
     retention: Duration,
 
+    /// How long a single poll may run for before the task is reported as
+    /// blocking its worker thread.
+    blocking_poll_threshold: Duration,
+
     /// Shared state, including a `Notify` that triggers a flush when the event
     /// buffer is approaching capacity.
     shared: Arc<Shared>,
 
     /// Currently active RPCs streaming task events.
-    watchers: ShrinkVec<Watch<proto::instrument::Update>>,
+    ///
+    /// Each update is encoded once, and the encoded update is shared by every
+    /// watcher.
+    watchers: ShrinkVec<Watch<Encoded<proto::instrument::Update>>>,
+
+    /// The sequence number of the most recently published update.
+    seq: u64,
+
+    /// The most recently published updates, ending with the update numbered
+    /// `seq`.
+    ///
+    /// A client which reconnects after missing only these updates is sent
+    /// them, rather than the complete current state.
+    history: VecDeque<Encoded<proto::instrument::Update>>,
 
     /// Currently active RPCs streaming task details events, by task ID.
     details_watchers: ShrinkMap<Id, Vec<Watch<proto::tasks::TaskDetails>>>,
@@ -70,6 +123,9 @@ pub(crate) struct Aggregator {
     /// Map of task IDs to task stats.
     task_stats: IdData<TaskStats>,
 
+    /// Summaries of completed tasks, kept after the tasks are dropped.
+    completed_tasks: TaskArchive,
+
     /// Map of resource IDs to resource static data.
     resources: IdData<Resource>,
 
@@ -82,10 +138,10 @@ pub(crate) struct Aggregator {
     /// Map of AsyncOp IDs to AsyncOp stats.
     async_op_stats: IdData<AsyncOpStats>,
 
-    /// *All* PollOp events for AsyncOps on Resources.
+    /// *All* PollOp events for AsyncOps on Resources which are still tracked,
+    /// up to the limit on the number of poll ops.
     ///
     /// This is sent to new clients as part of the initial state.
-    // TODO: drop the poll ops for async ops that have been dropped
     all_poll_ops: ShrinkVec<proto::resources::PollOp>,
 
     /// *New* PollOp events that whave occurred since the last update
@@ -95,9 +151,47 @@ pub(crate) struct Aggregator {
 
     ids: Ids,
 
+    /// Limits on the number of tasks, resources, async ops, and poll ops
+    /// which are tracked at once.
+    limits: Limits,
+
     /// A sink to record all events to a file.
     recorder: Option<Recorder>,
 
+    /// The directory in which clients may start recordings, if they may.
+    recording_dir: Option<PathBuf>,
+
+    /// A recording which is being started by an `UpdateSettings` request,
+    /// whose other changes are applied once it has started.
+    starting_recording: Option<StartingRecording>,
+
+    /// Whether the capabilities advertised to clients have changed since the
+    /// last update was published, so the next update should say which
+    /// protocol the process speaks again.
+    protocol_changed: bool,
+
+    /// What the layer does with events when the event buffer is full.
+    overflow_policy: OverflowPolicy,
+
+    /// Whether the aggregator runs on a thread of its own.
+    owns_thread: bool,
+
+    /// Spans which lost events before the events creating them were drained.
+    lost_events: Vec<(span::Id, LostEvents)>,
+
+    /// The directory in which to write dumps of the current state.
+    dump_dir: PathBuf,
+
+    /// If and where to write a summary report when shutting down.
+    shutdown_report_path: Option<PathBuf>,
+
+    /// Cumulative metrics for the Prometheus endpoint.
+    metrics: Metrics,
+
+    /// Information about this process, which is sent to clients with the
+    /// complete state.
+    process_info: proto::instrument::ProcessInfo,
+
     /// The time "state" of the aggregator, such as paused or live.
     temporality: Temporality,
 }
@@ -130,6 +224,26 @@ pub(crate) struct Ids {
     id_mappings: ShrinkMap<span::Id, Id>,
 }
 
+/// A recording which is being started on a blocking thread, on behalf of an
+/// `UpdateSettings` request.
+struct StartingRecording {
+    started: tokio::task::JoinHandle<io::Result<Recorder>>,
+    /// The request's other changes, which are applied once the recording has
+    /// started.
+    update: SettingsUpdate,
+    tx: oneshot::Sender<io::Result<proto::instrument::Settings>>,
+}
+
+/// Waits for the recording which is being started, if any, to start.
+async fn wait_for_recording(starting: &mut Option<StartingRecording>) -> io::Result<Recorder> {
+    match starting {
+        Some(starting) => (&mut starting.started)
+            .await
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?,
+        None => std::future::pending().await,
+    }
+}
+
 #[derive(Debug)]
 enum Temporality {
     Live,
@@ -174,6 +288,7 @@ struct ResourceStats {
     created_at: Option<SystemTime>,
     dropped_at: Option<SystemTime>,
     attributes: HashMap<FieldKey, Attribute>,
+    events_dropped: bool,
 }
 
 /// Represents static data for tasks
@@ -182,6 +297,8 @@ struct Task {
     metadata: &'static Metadata<'static>,
     fields: Vec<proto::Field>,
     location: Option<proto::Location>,
+    /// The index of the task's spawn location in the [`Metrics`].
+    metrics_location: usize,
 }
 
 struct TaskStats {
@@ -198,6 +315,32 @@ struct TaskStats {
 
     poll_times_histogram: Histogram<u64>,
     poll_stats: PollStats,
+
+    /// The user spans the task was inside of when its most recent poll
+    /// ended, from outermost to innermost.
+    async_backtrace: Vec<&'static Metadata<'static>>,
+
+    /// The task's busy time spent inside of each user span, by the span's
+    /// metadata ID.
+    span_busy_times: HashMap<u64, (&'static Metadata<'static>, Duration)>,
+
+    /// The number of times this task was woken by each other task, for up to
+    /// [`MAX_WAKE_EDGES`] tasks.
+    woken_by: HashMap<Id, u64>,
+
+    /// The number of times this task has woken each other task, for up to
+    /// [`MAX_WAKE_EDGES`] tasks.
+    woke: HashMap<Id, u64>,
+
+    /// How long the task's current poll had been running for when it was last
+    /// found to exceed the blocking poll threshold.
+    ///
+    /// This is `None` if the task is not currently blocking in a poll.
+    blocking_poll: Option<Duration>,
+
+    /// Whether any of the task's events were dropped because the event buffer
+    /// was full.
+    events_dropped: bool,
 }
 
 struct AsyncOp {
@@ -216,6 +359,7 @@ struct AsyncOpStats {
     task_id: Option<Id>,
     poll_stats: PollStats,
     attributes: HashMap<FieldKey, Attribute>,
+    events_dropped: bool,
 }
 
 impl DroppedAt for ResourceStats {
@@ -259,6 +403,15 @@ impl PollStats {
         }
     }
 
+    /// Returns how long the current poll has been running for, if the object
+    /// is currently being polled.
+    fn current_poll(&self, timestamp: SystemTime) -> Option<Duration> {
+        if self.current_polls == 0 {
+            return None;
+        }
+        self.since_last_poll(timestamp)
+    }
+
     fn since_last_poll(&self, timestamp: SystemTime) -> Option<Duration> {
         self.last_poll_started
             .map(|lps| timestamp.duration_since(lps).unwrap())
@@ -279,29 +432,101 @@ impl Default for TaskStats {
             // grows exponentially with higher a sigfig
             poll_times_histogram: Histogram::<u64>::new(2).unwrap(),
             poll_stats: PollStats::default(),
+            async_backtrace: Vec::new(),
+            span_busy_times: HashMap::new(),
+            woken_by: HashMap::new(),
+            woke: HashMap::new(),
+            blocking_poll: None,
+            events_dropped: false,
+        }
+    }
+}
+
+impl TaskStats {
+    fn details_proto(&self, id: Id, now: SystemTime) -> proto::tasks::TaskDetails {
+        proto::tasks::TaskDetails {
+            task_id: Some(id.into()),
+            now: Some(now.into()),
+            poll_times_histogram: serialize_histogram(&self.poll_times_histogram).ok(),
+            async_backtrace: self.async_backtrace_proto(),
+            span_busy_times: self.span_busy_times_proto(),
+            woken_by: Self::wake_edges_proto(&self.woken_by),
+            woke: Self::wake_edges_proto(&self.woke),
+        }
+    }
+
+    fn async_backtrace_proto(&self) -> Vec<proto::MetaId> {
+        self.async_backtrace
+            .iter()
+            .map(|&meta| meta.into())
+            .collect()
+    }
+
+    /// Counts a wake between this task and `peer` in `edges`, evicting the
+    /// edge with the fewest wakes if there's no room for a new edge.
+    fn count_wake(edges: &mut HashMap<Id, u64>, peer: Id) {
+        if edges.len() >= MAX_WAKE_EDGES && !edges.contains_key(&peer) {
+            let fewest = edges
+                .iter()
+                .min_by_key(|&(_, &wakes)| wakes)
+                .map(|(&id, _)| id);
+            if let Some(fewest) = fewest {
+                edges.remove(&fewest);
+            }
         }
+        *edges.entry(peer).or_default() += 1;
+    }
+
+    fn wake_edges_proto(edges: &HashMap<Id, u64>) -> Vec<proto::tasks::WakeCount> {
+        edges
+            .iter()
+            .map(|(&task_id, &wakes)| proto::tasks::WakeCount {
+                task_id: Some(task_id.into()),
+                wakes,
+            })
+            .collect()
+    }
+
+    fn span_busy_times_proto(&self) -> Vec<proto::tasks::SpanBusyTime> {
+        self.span_busy_times
+            .values()
+            .map(|&(metadata, busy_time)| proto::tasks::SpanBusyTime {
+                metadata: Some(metadata.into()),
+                busy_time: Some(busy_time.into()),
+            })
+            .collect()
     }
 }
 
 impl Aggregator {
     pub(crate) fn new(
-        events: mpsc::Receiver<Event>,
+        events: Batches,
         rpcs: mpsc::Receiver<Command>,
         builder: &crate::Builder,
         shared: Arc<crate::Shared>,
     ) -> Self {
+        let anchor = Anchor::new();
         Self {
             shared,
             rpcs,
             publish_interval: builder.publish_interval,
             retention: builder.retention,
+            blocking_poll_threshold: builder.blocking_poll_threshold,
             events,
+            batch: Vec::new(),
+            anchor,
             watchers: Default::default(),
+            seq: 0,
+            history: VecDeque::new(),
             details_watchers: Default::default(),
             all_metadata: Default::default(),
             new_metadata: Default::default(),
             tasks: IdData::default(),
             task_stats: IdData::default(),
+            completed_tasks: TaskArchive::new(
+                builder.completed_task_history_capacity,
+                builder.completed_task_history_retention,
+            ),
             resources: IdData::default(),
             resource_stats: IdData::default(),
             async_ops: IdData::default(),
@@ -309,15 +534,42 @@ impl Aggregator {
             all_poll_ops: Default::default(),
             new_poll_ops: Default::default(),
             ids: Ids::default(),
+            limits: Limits::new(builder),
             recorder: builder
                 .recording_path
                 .as_ref()
-                .map(|path| Recorder::new(path).expect("creating recorder")),
+                .map(|path| Recorder::new(path, anchor).expect("creating recorder")),
+            recording_dir: builder.recording_dir.clone(),
+            starting_recording: None,
+            protocol_changed: false,
+            overflow_policy: builder.overflow_policy,
+            owns_thread: false,
+            lost_events: Vec::new(),
+            dump_dir: builder.dump_dir.clone(),
+            shutdown_report_path: builder.shutdown_report_path.clone(),
+            metrics: Metrics::default(),
+            process_info: process_info::process_info(builder, anchor.base()),
             temporality: Temporality::Live,
         }
     }
 
+    /// Marks that this aggregator runs on a thread of its own, which nothing
+    /// else that records events runs on, so that [`OverflowPolicy::Block`]
+    /// may wait for it.
+    ///
+    /// The aggregator must be run on a current-thread runtime on that thread.
+    pub(crate) fn set_owns_thread(&mut self) {
+        self.owns_thread = true;
+    }
+
     pub(crate) async fn run(mut self) {
+        if self.owns_thread {
+            *self.shared.aggregator_thread.lock() = Some(std::thread::current().id());
+        } else if let OverflowPolicy::Block(_) = self.overflow_policy {
+            tracing::warn!(
+                "the aggregator doesn't have a thread of its own, so events are dropped rather than waiting for room"
+            );
+        }
         let mut publish = tokio::time::interval(self.publish_interval);
         loop {
             let should_send = tokio::select! {
@@ -335,15 +587,54 @@ impl Aggregator {
                     false
                 }
 
+                // a recording requested by a client has started, or failed to
+                started = wait_for_recording(&mut self.starting_recording) => {
+                    self.recording_started(started);
+                    false
+                }
+
                 // a new command from a client
                 cmd = self.rpcs.recv() => {
                     match cmd {
-                        Some(Command::Instrument(subscription)) => {
-                            self.add_instrument_subscription(subscription);
+                        Some(Command::Instrument(subscription, resume)) => {
+                            self.add_instrument_subscription(subscription, resume);
                         },
                         Some(Command::WatchTaskDetail(watch_request)) => {
                             self.add_task_detail_subscription(watch_request);
                         },
+                        Some(Command::Snapshot(request, tx)) => {
+                            self.send_snapshot(request, tx);
+                        },
+                        Some(Command::UpdateSettings(update, tx)) => {
+                            self.update_settings(update, tx);
+                        },
+                        Some(Command::ListCompletedTasks(request, tx)) => {
+                            let now = self.anchor.now();
+                            // If this fails, the client has gone away.
+                            let _ = tx.send(self.completed_tasks.list(&request, now));
+                        },
+                        Some(Command::LocalSnapshot(tx)) => {
+                            // If this fails, the handle's caller has gone away.
+                            let _ = tx.send(self.local_snapshot());
+                        }
+                        Some(Command::Dump(reason, tx)) => {
+                            self.dump(reason, tx);
+                        }
+                        Some(Command::Metrics(tx)) => {
+                            // If this fails, the scrape was cancelled.
+                            let _ = tx.send(self.prometheus_metrics());
+                        }
+                        Some(Command::Shutdown(tx)) => {
+                            self.shutdown();
+                            // If this fails, whoever asked for the shutdown
+                            // isn't waiting for it.
+                            let _ = tx.send(());
+                            return;
+                        }
+                        Some(Command::Enable(tx)) => {
+                            // If this fails, the client has gone away.
+                            let _ = tx.send(self.shared.enable());
+                        }
                         Some(Command::Pause) => {
                             self.temporality = Temporality::Paused;
                         }
@@ -363,30 +654,18 @@ impl Aggregator {
 
             // drain and aggregate buffered events.
             //
-            // Note: we *don't* want to actually await the call to `recv` --- we
-            // don't want the aggregator task to be woken on every event,
-            // because it will then be woken when its own `poll` calls are
-            // exited. that would result in a busy-loop. instead, we only want
-            // to be woken when the flush interval has elapsed, or when the
-            // channel is almost full.
-            let mut drained = false;
-            while let Some(event) = self.events.recv().now_or_never() {
-                match event {
-                    Some(event) => {
-                        // always be recording...
-                        if let Some(ref recorder) = self.recorder {
-                            recorder.record(&event);
-                        }
-                        self.update_state(event);
-                        drained = true;
-                    }
-                    // The channel closed, no more events will be emitted...time
-                    // to stop aggregating.
-                    None => {
-                        tracing::debug!("event channel closed; terminating");
-                        return;
-                    }
-                };
+            // Note: we *don't* want to be woken on every event, because the
+            // aggregator task will then be woken when its own `poll` calls
+            // are exited. that would result in a busy-loop. instead, we only
+            // want to be woken when the flush interval has elapsed, or when
+            // the buffer is almost full, and then drain every buffered event
+            // in one batch.
+            //
+            // Every `ConsoleLayer` was dropped, so no more events will be
+            // emitted...time to stop aggregating.
+            if !self.drain_events() {
+                tracing::debug!("event buffer closed; terminating");
+                return;
             }
 
             // flush data to clients, if there are any currently subscribed
@@ -395,8 +674,103 @@ impl Aggregator {
                 self.publish();
             }
             self.cleanup_closed();
-            if drained {
-                self.shared.flush.has_flushed();
+
+            // a client may have changed the publish interval
+            if publish.period() != self.publish_interval {
+                publish = tokio::time::interval(self.publish_interval);
+            }
+        }
+    }
+
+    /// Drains and aggregates every buffered event.
+    ///
+    /// Returns `false` if every `ConsoleLayer` has been dropped, so no more
+    /// events will be emitted.
+    fn drain_events(&mut self) -> bool {
+        let mut batch = std::mem::take(&mut self.batch);
+        let open = self.events.drain_into(&mut batch);
+        let drained = !batch.is_empty();
+        for event in batch.drain(..) {
+            // always be recording...
+            if let Some(ref recorder) = self.recorder {
+                recorder.record(&event);
+            }
+            self.update_state(event);
+        }
+        self.batch = batch;
+        self.mark_lost_events();
+        if drained {
+            self.shared.flush.has_flushed();
+        }
+        open
+    }
+
+    /// Marks the tasks, resources, and async ops which lost events since the
+    /// last drain.
+    fn mark_lost_events(&mut self) {
+        // A span may lose an event after the event creating it was recorded,
+        // but before that event was drained, so give spans which aren't known
+        // yet one more drain to turn up.
+        let pending = std::mem::take(&mut self.lost_events);
+        let lost_events = std::mem::take(&mut *self.shared.lost_events.lock());
+        for (span_id, lost) in lost_events {
+            match self.ids.get(&span_id) {
+                Some(id) => self.mark_lost(span_id, id, lost),
+                None => self.lost_events.push((span_id, lost)),
+            }
+        }
+        for (span_id, lost) in pending {
+            if let Some(id) = self.ids.get(&span_id) {
+                self.mark_lost(span_id, id, lost);
+            }
+        }
+    }
+
+    fn mark_lost(&mut self, span_id: span::Id, id: Id, lost: LostEvents) {
+        // If the span's close was lost, or if the layer has stopped recording
+        // the task under `DropTask`, nothing else will close it.
+        let mut close = lost.closed;
+        if let Some(mut task_stats) = self.task_stats.update(&id) {
+            task_stats.events_dropped = true;
+            close |= self.overflow_policy == OverflowPolicy::DropTask;
+            close &= task_stats.dropped_at.is_none();
+        }
+        if let Some(mut resource_stats) = self.resource_stats.update(&id) {
+            resource_stats.events_dropped = true;
+            close &= resource_stats.dropped_at.is_none();
+        }
+        if let Some(mut async_op_stats) = self.async_op_stats.update(&id) {
+            async_op_stats.events_dropped = true;
+            close &= async_op_stats.dropped_at.is_none();
+        }
+        if close {
+            self.update_state(Event::Close {
+                id: span_id,
+                at: lost.at,
+            });
+        }
+    }
+
+    /// Publishes everything recorded so far, and then ends the recording and
+    /// writes the shutdown report, if there is one.
+    fn shutdown(&mut self) {
+        tracing::debug!("shutting down");
+        self.drain_events();
+        if !self.watchers.is_empty() {
+            self.publish();
+        }
+
+        // Dropping the recorder waits for everything recorded to be written.
+        if let Some(recorder) = self.recorder.take() {
+            tracing::info!(path = %recorder.path().display(), "stopped recording");
+        }
+
+        if let Some(path) = self.shutdown_report_path.take() {
+            match self.write_report(&path) {
+                Ok(()) => tracing::info!(path = %path.display(), "wrote shutdown report"),
+                Err(error) => {
+                    tracing::warn!(%error, path = %path.display(), "failed to write shutdown report")
+                }
             }
         }
     }
@@ -404,74 +778,434 @@ impl Aggregator {
     fn cleanup_closed(&mut self) {
         // drop all closed have that has completed *and* whose final data has already
         // been sent off.
-        let now = SystemTime::now();
+        let now = self.anchor.now();
         let has_watchers = !self.watchers.is_empty();
-        self.tasks.drop_closed(
+        let mut gone_tasks = HashSet::new();
+        let mut forgotten = self.tasks.drop_closed(
             &mut self.task_stats,
             now,
             self.retention,
             has_watchers,
             &mut self.ids,
+            &mut gone_tasks,
         );
-        self.resources.drop_closed(
+        forgotten |= self.resources.drop_closed(
             &mut self.resource_stats,
             now,
             self.retention,
             has_watchers,
             &mut self.ids,
+            &mut HashSet::new(),
         );
-        self.async_ops.drop_closed(
+        forgotten |= self.async_ops.drop_closed(
             &mut self.async_op_stats,
             now,
             self.retention,
             has_watchers,
             &mut self.ids,
+            &mut HashSet::new(),
+        );
+
+        self.limits.tasks.skip_dropped(&self.task_stats);
+        self.limits.resources.skip_dropped(&self.resource_stats);
+        self.limits.async_ops.skip_dropped(&self.async_op_stats);
+        let unmapped = self.limits.take_unmapped();
+        if !unmapped.is_empty() {
+            self.ids.remove_all(&unmapped);
+        }
+        // Clients which connect later are sent the current state, which
+        // already leaves out the evicted entities.
+        if !has_watchers {
+            forgotten |= self.limits.clear_evicted();
+        }
+        if forgotten {
+            self.forget_history();
+        }
+
+        // Drop the wake edges to tasks which are no longer tracked, whether
+        // they were dropped just now or evicted since the last cleanup. Each
+        // task has only a few edges, so this is cheap.
+        gone_tasks.extend(&unmapped);
+        if !gone_tasks.is_empty() {
+            for (_, stats) in self.task_stats.all_mut() {
+                stats.woken_by.retain(|id, _| !gone_tasks.contains(id));
+                stats.woke.retain(|id, _| !gone_tasks.contains(id));
+            }
+        }
+
+        // Drop the poll ops of async ops which are no longer tracked.
+        let async_op_stats = &self.async_op_stats;
+        self.all_poll_ops.retain_and_shrink(|poll_op| {
+            poll_op
+                .async_op_id
+                .as_ref()
+                .map_or(false, |id| async_op_stats.get(&id.id).is_some())
+        });
+    }
+
+    /// Stops clients from resuming from any update published so far.
+    ///
+    /// This is necessary when changes which were never published are
+    /// forgotten, such as entities which closed while no clients were
+    /// watching, as clients which resumed would never learn of them. Such
+    /// clients are sent the complete current state instead.
+    fn forget_history(&mut self) {
+        tracing::debug!(
+            seq = self.seq,
+            "unpublished changes were dropped; forgetting history"
         );
+        self.history.clear();
+        // Skip a sequence number, so that clients which received the last
+        // published update can't resume from it, either.
+        self.seq += 1;
+    }
+
+    /// Flags tasks whose current poll has been running for longer than the
+    /// blocking poll threshold.
+    ///
+    /// Such tasks are marked as updated, so that clients learn how long they
+    /// have been blocking for on every update, rather than only once the poll
+    /// completes.
+    fn update_blocking_polls(&mut self, now: SystemTime) {
+        let threshold = self.blocking_poll_threshold;
+        let blocking: Vec<(Id, Duration)> = self
+            .task_stats
+            .all()
+            .filter_map(|(&id, stats)| {
+                let polling_for = stats.poll_stats.current_poll(now)?;
+                if polling_for > threshold {
+                    Some((id, polling_for))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for (id, polling_for) in blocking {
+            if let Some(mut stats) = self.task_stats.update(&id) {
+                tracing::trace!(task.id = id, ?polling_for, "task is blocking in a poll");
+                stats.blocking_poll = Some(polling_for);
+            }
+        }
     }
 
     /// Add the task subscription to the watchers after sending the first update
-    fn add_instrument_subscription(&mut self, subscription: Watch<proto::instrument::Update>) {
+    fn add_instrument_subscription(
+        &mut self,
+        subscription: Watch<Encoded<proto::instrument::Update>>,
+        resume: Option<proto::instrument::ResumeCursor>,
+    ) {
+        // A client watching a dormant layer wants to see what it records.
+        self.shared.enable();
+
+        if let Some((seq, missed)) =
+            resume.and_then(|cursor| Some((cursor.seq, self.updates_since(&cursor)?)))
+        {
+            tracing::debug!(
+                missed.updates = missed.len(),
+                "resuming instrument subscription"
+            );
+            // The published updates don't say what the process is, or which
+            // protocol it speaks, so start by repeating the last update the
+            // client received with only those.
+            let resumed = Encoded::new(&proto::instrument::Update {
+                now: Some(self.anchor.now().into()),
+                clock_base: Some(self.anchor.base().into()),
+                seq,
+                process_info: Some(self.process_info.clone()),
+                protocol: Some(crate::service::protocol(&self.shared)),
+                ..Default::default()
+            });
+            // If the client's buffer fills up while it catches up, the
+            // subscription is dropped, and the client can resume from the
+            // last update it did receive.
+            if std::iter::once(&resumed)
+                .chain(&missed)
+                .all(|update| subscription.update(update))
+            {
+                self.watchers.push(subscription);
+            }
+            return;
+        }
+
         tracing::debug!("new instrument subscription");
-        let now = SystemTime::now();
+        let now = self.anchor.now();
+        self.update_blocking_polls(now);
+        let mut update = self.state_update(now, &[]);
+        update.seq = self.seq;
+        if let Some(task_update) = &mut update.task_update {
+            task_update.dropped_events = take_dropped(
+                &self.shared.dropped_tasks,
+                &mut self.metrics.dropped_events.tasks,
+            );
+        }
+        if let Some(async_op_update) = &mut update.async_op_update {
+            async_op_update.dropped_events = take_dropped(
+                &self.shared.dropped_async_ops,
+                &mut self.metrics.dropped_events.async_ops,
+            );
+        }
+        // Rather than cloning every poll op and all of the metadata into the
+        // initial state, move them into it while it is encoded, and move them
+        // back afterwards.
+        if let Some(resource_update) = &mut update.resource_update {
+            resource_update.new_poll_ops = std::mem::take(&mut *self.all_poll_ops);
+            resource_update.dropped_events = take_dropped(
+                &self.shared.dropped_resources,
+                &mut self.metrics.dropped_events.resources,
+            );
+        }
+        update.new_metadata = Some(proto::RegisterMetadata {
+            metadata: std::mem::take(&mut *self.all_metadata),
+        });
+        let encoded = Encoded::new(&update);
+        if let Some(resource_update) = update.resource_update {
+            *self.all_poll_ops = resource_update.new_poll_ops;
+        }
+        if let Some(new_metadata) = update.new_metadata {
+            *self.all_metadata = new_metadata.metadata;
+        }
+
         // Send the initial state --- if this fails, the subscription is already dead
-        let update = &proto::instrument::Update {
-            task_update: Some(proto::tasks::TaskUpdate {
+        if subscription.update(&encoded) {
+            self.watchers.push(subscription)
+        }
+    }
+
+    /// Returns an update containing the complete current state of the kinds
+    /// of object in `include`, or of every kind if `include` is empty.
+    ///
+    /// The update's poll ops, metadata, and dropped event counts are left
+    /// empty, to be filled in by the caller.
+    fn state_update(
+        &mut self,
+        now: SystemTime,
+        include: &[SnapshotKind],
+    ) -> proto::instrument::Update {
+        let includes = |kind| include.is_empty() || include.contains(&kind);
+        proto::instrument::Update {
+            task_update: includes(SnapshotKind::Tasks).then(|| proto::tasks::TaskUpdate {
                 new_tasks: self
                     .tasks
                     .all()
                     .map(|(_, value)| value.to_proto())
                     .collect(),
                 stats_update: self.task_stats.as_proto(Include::All),
-                dropped_events: self.shared.dropped_tasks.swap(0, AcqRel) as u64,
+                dropped_events: 0,
+                evicted_tasks: Vec::new(),
+                untracked_tasks: 0,
             }),
-            resource_update: Some(proto::resources::ResourceUpdate {
-                new_resources: self
-                    .resources
-                    .all()
-                    .map(|(_, value)| value.to_proto())
-                    .collect(),
-                stats_update: self.resource_stats.as_proto(Include::All),
-                new_poll_ops: (*self.all_poll_ops).clone(),
-                dropped_events: self.shared.dropped_resources.swap(0, AcqRel) as u64,
+            resource_update: includes(SnapshotKind::Resources).then(|| {
+                proto::resources::ResourceUpdate {
+                    new_resources: self
+                        .resources
+                        .all()
+                        .map(|(_, value)| value.to_proto())
+                        .collect(),
+                    stats_update: self.resource_stats.as_proto(Include::All),
+                    new_poll_ops: Vec::new(),
+                    dropped_events: 0,
+                    evicted_resources: Vec::new(),
+                    untracked_resources: 0,
+                    evicted_poll_ops: 0,
+                }
             }),
-            async_op_update: Some(proto::async_ops::AsyncOpUpdate {
-                new_async_ops: self
-                    .async_ops
-                    .all()
-                    .map(|(_, value)| value.to_proto())
-                    .collect(),
-                stats_update: self.async_op_stats.as_proto(Include::All),
-                dropped_events: self.shared.dropped_async_ops.swap(0, AcqRel) as u64,
+            async_op_update: includes(SnapshotKind::AsyncOps).then(|| {
+                proto::async_ops::AsyncOpUpdate {
+                    new_async_ops: self
+                        .async_ops
+                        .all()
+                        .map(|(_, value)| value.to_proto())
+                        .collect(),
+                    stats_update: self.async_op_stats.as_proto(Include::All),
+                    dropped_events: 0,
+                    evicted_async_ops: Vec::new(),
+                    untracked_async_ops: 0,
+                }
             }),
             now: Some(now.into()),
-            new_metadata: Some(proto::RegisterMetadata {
-                metadata: (*self.all_metadata).clone(),
-            }),
-        };
+            new_metadata: None,
+            clock_base: Some(self.anchor.base().into()),
+            seq: 0,
+            process_info: Some(self.process_info.clone()),
+            protocol: Some(crate::service::protocol(&self.shared)),
+        }
+    }
 
-        if subscription.update(update) {
-            self.watchers.push(subscription)
+    /// Sends a snapshot of the current state in response to a `GetSnapshot`
+    /// request.
+    fn send_snapshot(
+        &mut self,
+        request: proto::instrument::SnapshotRequest,
+        tx: oneshot::Sender<proto::instrument::Update>,
+    ) {
+        let include = request.include().collect::<Vec<_>>();
+        tracing::debug!(?include, "taking snapshot");
+        let now = self.anchor.now();
+        let update = self.snapshot_update(now, &include);
+        // If this fails, the client has gone away, and there's nothing to do.
+        let _ = tx.send(update);
+    }
+
+    /// Returns an update containing the complete current state of the kinds
+    /// of object in `include`, or of every kind if `include` is empty,
+    /// together with all of the metadata and poll ops.
+    fn snapshot_update(
+        &mut self,
+        now: SystemTime,
+        include: &[SnapshotKind],
+    ) -> proto::instrument::Update {
+        self.update_blocking_polls(now);
+        let mut update = self.state_update(now, include);
+        update.seq = self.seq;
+        // Unlike the initial state sent to a new watcher, a snapshot doesn't
+        // reset the dropped event counts, since it doesn't replace any updates
+        // which would have reported them.
+        if let Some(task_update) = &mut update.task_update {
+            task_update.dropped_events = self.shared.dropped_tasks.load(Acquire) as u64;
+        }
+        if let Some(async_op_update) = &mut update.async_op_update {
+            async_op_update.dropped_events = self.shared.dropped_async_ops.load(Acquire) as u64;
         }
+        if let Some(resource_update) = &mut update.resource_update {
+            resource_update.new_poll_ops = self.all_poll_ops.to_vec();
+            resource_update.dropped_events = self.shared.dropped_resources.load(Acquire) as u64;
+        }
+        update.new_metadata = Some(proto::RegisterMetadata {
+            metadata: self.all_metadata.to_vec(),
+        });
+        update
+    }
+
+    /// Applies the changes requested by an `UpdateSettings` request, and
+    /// sends the settings in effect afterwards to `tx`.
+    ///
+    /// If a new recording can't be started, no other changes are applied.
+    /// Files are only created and closed on a blocking thread, so that the
+    /// aggregator isn't held up by the file system. While a new recording is
+    /// being started, the aggregator keeps running, and the other changes are
+    /// applied once it has started.
+    fn update_settings(
+        &mut self,
+        mut update: SettingsUpdate,
+        tx: oneshot::Sender<io::Result<proto::instrument::Settings>>,
+    ) {
+        match update.recording.take() {
+            Some(RecordingChange::Start(path)) => {
+                if self.starting_recording.is_some() {
+                    // If this fails, the client has gone away.
+                    let _ = tx.send(Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "another recording is already being started",
+                    )));
+                    return;
+                }
+                let dir = match self.recording_dir.as_ref() {
+                    Some(dir) => dir,
+                    None => {
+                        let _ = tx.send(Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "this process doesn't allow recordings to be started remotely",
+                        )));
+                        return;
+                    }
+                };
+                let path = dir.join(path);
+                let anchor = self.anchor;
+                // The recording in progress is only replaced once the new one
+                // has started, so that it is kept if the new one can't be.
+                let started =
+                    tokio::task::spawn_blocking(move || Recorder::replacing(&path, anchor));
+                self.starting_recording = Some(StartingRecording {
+                    started,
+                    update,
+                    tx,
+                });
+                return;
+            }
+            Some(RecordingChange::Stop) => {
+                if let Some(recorder) = self.recorder.take() {
+                    tracing::info!(path = %recorder.path().display(), "stopped recording");
+                    // Dropping the recorder waits for its file to be written.
+                    tokio::task::spawn_blocking(move || drop(recorder));
+                }
+            }
+            None => {}
+        }
+        let _ = tx.send(Ok(self.apply_settings(update)));
+    }
+
+    /// Replaces the recording in progress with one which was started by an
+    /// `UpdateSettings` request, if it could be started, and then applies the
+    /// request's other changes.
+    fn recording_started(&mut self, started: io::Result<Recorder>) {
+        let StartingRecording { update, tx, .. } = self
+            .starting_recording
+            .take()
+            .expect("a recording was being started");
+        let settings = started.map(|recorder| {
+            tracing::info!(path = %recorder.path().display(), "started recording");
+            if let Some(previous) = self.recorder.replace(recorder) {
+                // Dropping the recorder waits for its file to be written.
+                tokio::task::spawn_blocking(move || drop(previous));
+            }
+            self.apply_settings(update)
+        });
+        // If this fails, the client has gone away.
+        let _ = tx.send(settings);
+    }
+
+    /// Applies the changes requested by an `UpdateSettings` request, other
+    /// than starting or stopping a recording, and returns the settings in
+    /// effect afterwards.
+    fn apply_settings(&mut self, update: SettingsUpdate) -> proto::instrument::Settings {
+        if let Some(publish_interval) = update.publish_interval {
+            tracing::info!(?publish_interval, "changed publish interval");
+            self.publish_interval = publish_interval;
+        }
+        if let Some(retention) = update.retention {
+            tracing::info!(?retention, "changed retention");
+            self.retention = retention;
+        }
+        let protocol = crate::service::protocol(&self.shared);
+        if let Some(track_resources) = update.track_resources {
+            tracing::info!(track_resources, "changed resource tracking");
+            self.shared
+                .resources_disabled
+                .store(!track_resources, Release);
+        }
+        if let Some(track_async_ops) = update.track_async_ops {
+            tracing::info!(track_async_ops, "changed async op tracking");
+            self.shared
+                .async_ops_disabled
+                .store(!track_async_ops, Release);
+        }
+        self.protocol_changed |= crate::service::protocol(&self.shared) != protocol;
+
+        proto::instrument::Settings {
+            publish_interval: Some(self.publish_interval.into()),
+            retention: Some(self.retention.into()),
+            recording_path: self
+                .recorder
+                .as_ref()
+                .map(|recorder| recorder.path().display().to_string()),
+            track_resources: !self.shared.resources_disabled.load(Acquire),
+            track_async_ops: !self.shared.async_ops_disabled.load(Acquire),
+        }
+    }
+
+    /// Returns the updates published after the one identified by `cursor`, or
+    /// `None` if they are not all in the history.
+    fn updates_since(
+        &self,
+        cursor: &proto::instrument::ResumeCursor,
+    ) -> Option<Vec<Encoded<proto::instrument::Update>>> {
+        // Updates sent by another instance of the aggregator can't be resumed.
+        if cursor.clock_base != Some(self.anchor.base().into()) || cursor.seq > self.seq {
+            return None;
+        }
+        let missed = (self.seq - cursor.seq) as usize;
+        let skip = self.history.len().checked_sub(missed)?;
+        Some(self.history.iter().skip(skip).cloned().collect())
     }
 
     /// Add the task details subscription to the watchers after sending the first update,
@@ -489,15 +1223,10 @@ impl Aggregator {
         if let Some(stats) = self.task_stats.get(&id) {
             let (tx, rx) = mpsc::channel(buffer);
             let subscription = Watch(tx);
-            let now = SystemTime::now();
+            let now = self.anchor.now();
             // Send back the stream receiver.
             // Then send the initial state --- if this fails, the subscription is already dead.
-            if stream_sender.send(rx).is_ok()
-                && subscription.update(&proto::tasks::TaskDetails {
-                    task_id: Some(id.into()),
-                    now: Some(now.into()),
-                    poll_times_histogram: serialize_histogram(&stats.poll_times_histogram).ok(),
-                })
+            if stream_sender.send(rx).is_ok() && subscription.update(&stats.details_proto(id, now))
             {
                 self.details_watchers
                     .entry(id)
@@ -523,10 +1252,17 @@ impl Aggregator {
 
         let new_poll_ops = std::mem::take(&mut self.new_poll_ops);
 
-        let now = SystemTime::now();
+        let now = self.anchor.now();
+        self.update_blocking_polls(now);
+        self.seq += 1;
         let update = proto::instrument::Update {
             now: Some(now.into()),
             new_metadata,
+            clock_base: Some(self.anchor.base().into()),
+            seq: self.seq,
+            process_info: None,
+            protocol: std::mem::take(&mut self.protocol_changed)
+                .then(|| crate::service::protocol(&self.shared)),
             task_update: Some(proto::tasks::TaskUpdate {
                 new_tasks: self
                     .tasks
@@ -535,7 +1271,12 @@ impl Aggregator {
                     .collect(),
                 stats_update: self.task_stats.as_proto(Include::UpdatedOnly),
 
-                dropped_events: self.shared.dropped_tasks.swap(0, AcqRel) as u64,
+                dropped_events: take_dropped(
+                    &self.shared.dropped_tasks,
+                    &mut self.metrics.dropped_events.tasks,
+                ),
+                evicted_tasks: ids_proto(&mut self.limits.tasks.evicted),
+                untracked_tasks: std::mem::take(&mut self.limits.tasks.untracked),
             }),
             resource_update: Some(proto::resources::ResourceUpdate {
                 new_resources: self
@@ -546,7 +1287,13 @@ impl Aggregator {
                 stats_update: self.resource_stats.as_proto(Include::UpdatedOnly),
                 new_poll_ops,
 
-                dropped_events: self.shared.dropped_resources.swap(0, AcqRel) as u64,
+                dropped_events: take_dropped(
+                    &self.shared.dropped_resources,
+                    &mut self.metrics.dropped_events.resources,
+                ),
+                evicted_resources: ids_proto(&mut self.limits.resources.evicted),
+                untracked_resources: std::mem::take(&mut self.limits.resources.untracked),
+                evicted_poll_ops: std::mem::take(&mut self.limits.evicted_poll_ops),
             }),
             async_op_update: Some(proto::async_ops::AsyncOpUpdate {
                 new_async_ops: self
@@ -556,24 +1303,32 @@ impl Aggregator {
                     .collect(),
                 stats_update: self.async_op_stats.as_proto(Include::UpdatedOnly),
 
-                dropped_events: self.shared.dropped_async_ops.swap(0, AcqRel) as u64,
+                dropped_events: take_dropped(
+                    &self.shared.dropped_async_ops,
+                    &mut self.metrics.dropped_events.async_ops,
+                ),
+                evicted_async_ops: ids_proto(&mut self.limits.async_ops.evicted),
+                untracked_async_ops: std::mem::take(&mut self.limits.async_ops.untracked),
             }),
         };
 
+        // Encode the update once, and share it between all of the watchers.
+        let update = Encoded::new(&update);
+        if self.history.len() == MAX_RESUMABLE_UPDATES {
+            self.history.pop_front();
+        }
+        self.history.push_back(update.clone());
         self.watchers
-            .retain_and_shrink(|watch: &Watch<proto::instrument::Update>| watch.update(&update));
+            .retain_and_shrink(|watch: &Watch<Encoded<proto::instrument::Update>>| {
+                watch.update(&update)
+            });
 
         let stats = &self.task_stats;
         // Assuming there are much fewer task details subscribers than there are
         // stats updates, iterate over `details_watchers` and compact the map.
         self.details_watchers.retain_and_shrink(|&id, watchers| {
             if let Some(task_stats) = stats.get(&id) {
-                let details = proto::tasks::TaskDetails {
-                    task_id: Some(id.into()),
-                    now: Some(now.into()),
-                    poll_times_histogram: serialize_histogram(&task_stats.poll_times_histogram)
-                        .ok(),
-                };
+                let details = task_stats.details_proto(id, now);
                 watchers.retain(|watch| watch.update(&details));
                 !watchers.is_empty()
             } else {
@@ -587,6 +1342,11 @@ impl Aggregator {
         // do state update
         match event {
             Event::Metadata(meta) => {
+                if self.process_info.tokio_version.is_empty() {
+                    if let Some(version) = process_info::tokio_version(meta) {
+                        self.process_info.tokio_version = version;
+                    }
+                }
                 self.all_metadata.push(meta.into());
                 self.new_metadata.push(meta.into());
             }
@@ -598,7 +1358,16 @@ impl Aggregator {
                 fields,
                 location,
             } => {
-                let id = self.ids.id_for(id);
+                if !self
+                    .limits
+                    .tasks
+                    .make_room(&mut self.tasks, &mut self.task_stats)
+                {
+                    return;
+                }
+                let at = self.anchor.to_system_time(at);
+                let id = self.ids.insert(id);
+                let metrics_location = self.metrics.task_spawned(id, location.as_ref());
                 self.tasks.insert(
                     id,
                     Task {
@@ -606,6 +1375,7 @@ impl Aggregator {
                         metadata,
                         fields,
                         location,
+                        metrics_location,
                         // TODO: parents
                     },
                 );
@@ -620,9 +1390,10 @@ impl Aggregator {
             }
 
             Event::Enter { id, parent_id, at } => {
-                let id = self.ids.id_for(id);
-                let parent_id = parent_id.map(|id| self.ids.id_for(id));
-                if let Some(mut task_stats) = self.task_stats.update(&id) {
+                let at = self.anchor.to_system_time(at);
+                let id = self.ids.get(&id);
+                let parent_id = parent_id.and_then(|id| self.ids.get(&id));
+                if let Some(mut task_stats) = id.and_then(|id| self.task_stats.update(&id)) {
                     task_stats.poll_stats.update_on_span_enter(at);
                     return;
                 }
@@ -634,12 +1405,34 @@ impl Aggregator {
                 }
             }
 
-            Event::Exit { id, parent_id, at } => {
-                let id = self.ids.id_for(id);
-                let parent_id = parent_id.map(|id| self.ids.id_for(id));
-                if let Some(mut task_stats) = self.task_stats.update(&id) {
+            Event::Exit {
+                id,
+                parent_id,
+                at,
+                async_backtrace,
+                span_busy_times,
+            } => {
+                let at = self.anchor.to_system_time(at);
+                let id = self.ids.get(&id);
+                let parent_id = parent_id.and_then(|id| self.ids.get(&id));
+                if let Some((id, mut task_stats)) =
+                    id.and_then(|id| Some((id, self.task_stats.update(&id)?)))
+                {
                     task_stats.poll_stats.update_on_span_exit(at);
+                    if task_stats.poll_stats.current_polls == 0 {
+                        task_stats.blocking_poll = None;
+                    }
+                    task_stats.async_backtrace = async_backtrace;
+                    for (metadata, busy_time) in span_busy_times {
+                        let id = proto::MetaId::from(metadata).id;
+                        task_stats
+                            .span_busy_times
+                            .entry(id)
+                            .or_insert((metadata, Duration::ZERO))
+                            .1 += busy_time;
+                    }
                     if let Some(since_last_poll) = task_stats.poll_stats.since_last_poll(at) {
+                        self.metrics.task_polled(id, since_last_poll);
                         task_stats
                             .poll_times_histogram
                             .record(since_last_poll.as_nanos().try_into().unwrap_or(u64::MAX))
@@ -656,22 +1449,48 @@ impl Aggregator {
             }
 
             Event::Close { id, at } => {
-                let id = self.ids.id_for(id);
+                let at = self.anchor.to_system_time(at);
+                let id = match self.ids.get(&id) {
+                    Some(id) => id,
+                    None => return,
+                };
                 if let Some(mut task_stats) = self.task_stats.update(&id) {
                     task_stats.dropped_at = Some(at);
+                    self.limits.tasks.completed(id);
+                    self.metrics.task_completed(
+                        id,
+                        task_stats.created_at,
+                        at,
+                        task_stats.poll_stats.busy_time,
+                    );
+                    if let Some(task) = self.tasks.get(&id) {
+                        self.completed_tasks.push(task, &task_stats, at);
+                    }
                 }
 
                 if let Some(mut resource_stats) = self.resource_stats.update(&id) {
                     resource_stats.dropped_at = Some(at);
+                    self.limits.resources.completed(id);
                 }
 
                 if let Some(mut async_op_stats) = self.async_op_stats.update(&id) {
                     async_op_stats.dropped_at = Some(at);
+                    self.limits.async_ops.completed(id);
                 }
             }
 
-            Event::Waker { id, op, at } => {
-                let id = self.ids.id_for(id);
+            Event::Waker {
+                id,
+                op,
+                at,
+                woken_by,
+            } => {
+                let at = self.anchor.to_system_time(at);
+                let id = match self.ids.get(&id) {
+                    Some(id) => id,
+                    None => return,
+                };
+                let woken_by = woken_by.and_then(|id| self.ids.get(&id));
                 // It's possible for wakers to exist long after a task has
                 // finished. We don't want those cases to create a "new"
                 // task that isn't closed, just to insert some waker stats.
@@ -679,6 +1498,7 @@ impl Aggregator {
                 // It may be useful to eventually be able to report about
                 // "wasted" waker ops, but we'll leave that for another time.
                 if let Some(mut task_stats) = self.task_stats.update(&id) {
+                    self.metrics.waker_op(id, op);
                     match op {
                         WakeOp::Wake { self_wake } | WakeOp::WakeByRef { self_wake } => {
                             task_stats.wakes += 1;
@@ -690,6 +1510,12 @@ impl Aggregator {
                                 task_stats.self_wakes += 1;
                             }
 
+                            // If another task woke this task, record the edge
+                            // on both tasks' stats.
+                            if let Some(waker) = woken_by {
+                                TaskStats::count_wake(&mut task_stats.woken_by, waker);
+                            }
+
                             // Note: `Waker::wake` does *not* call the `drop`
                             // implementation, so waking by value doesn't
                             // trigger a drop event. so, count this as a `drop`
@@ -709,6 +1535,14 @@ impl Aggregator {
                             task_stats.waker_drops += 1;
                         }
                     }
+                } else {
+                    return;
+                }
+
+                if let Some(mut waker_stats) =
+                    woken_by.and_then(|waker| self.task_stats.update(&waker))
+                {
+                    TaskStats::count_wake(&mut waker_stats.woke, id);
                 }
             }
 
@@ -724,8 +1558,16 @@ impl Aggregator {
                 inherit_child_attrs,
                 ..
             } => {
-                let id = self.ids.id_for(id);
-                let parent_id = parent_id.map(|id| self.ids.id_for(id));
+                if !self
+                    .limits
+                    .resources
+                    .make_room(&mut self.resources, &mut self.resource_stats)
+                {
+                    return;
+                }
+                let at = self.anchor.to_system_time(at);
+                let id = self.ids.insert(id);
+                let parent_id = parent_id.and_then(|id| self.ids.get(&id));
                 self.resources.insert(
                     id,
                     Resource {
@@ -757,23 +1599,34 @@ impl Aggregator {
                 task_id,
                 is_ready,
             } => {
-                let async_op_id = self.ids.id_for(async_op_id);
-                let resource_id = self.ids.id_for(resource_id);
-                let task_id = self.ids.id_for(task_id);
+                // Poll ops of async ops and resources which aren't tracked,
+                // or have been evicted, are ignored.
+                let (async_op_id, resource_id) =
+                    match (self.ids.get(&async_op_id), self.ids.get(&resource_id)) {
+                        (Some(async_op_id), Some(resource_id)) => (async_op_id, resource_id),
+                        _ => return,
+                    };
+                let task_id = self.ids.get(&task_id);
 
-                let mut async_op_stats = self.async_op_stats.update_or_default(async_op_id);
-                async_op_stats.task_id.get_or_insert(task_id);
+                let mut async_op_stats = match self.async_op_stats.update(&async_op_id) {
+                    Some(async_op_stats) => async_op_stats,
+                    None => return,
+                };
+                if let Some(task_id) = task_id {
+                    async_op_stats.task_id.get_or_insert(task_id);
+                }
 
                 let poll_op = proto::resources::PollOp {
                     metadata: Some(metadata.into()),
                     resource_id: Some(resource_id.into()),
                     name: op_name,
-                    task_id: Some(task_id.into()),
+                    task_id: task_id.map(Into::into),
                     async_op_id: Some(async_op_id.into()),
                     is_ready,
                 };
 
-                self.all_poll_ops.push(poll_op.clone());
+                self.limits
+                    .push_poll_op(&mut self.all_poll_ops, poll_op.clone());
                 self.new_poll_ops.push(poll_op);
             }
 
@@ -783,7 +1636,10 @@ impl Aggregator {
                 update,
                 ..
             } => {
-                let update_id = self.ids.id_for(update_id);
+                let update_id = match self.ids.get(&update_id) {
+                    Some(update_id) => update_id,
+                    None => return,
+                };
                 let mut to_update = vec![(update_id, update_type.clone())];
 
                 fn update_entry(e: Entry<'_, FieldKey, Attribute>, upd: &AttributeUpdate) {
@@ -857,9 +1713,20 @@ impl Aggregator {
                 inherit_child_attrs,
                 ..
             } => {
-                let id = self.ids.id_for(id);
-                let parent_id = parent_id.map(|id| self.ids.id_for(id));
-                let resource_id = self.ids.id_for(resource_id);
+                let resource_id = match self.ids.get(&resource_id) {
+                    Some(resource_id) => resource_id,
+                    None => return,
+                };
+                if !self
+                    .limits
+                    .async_ops
+                    .make_room(&mut self.async_ops, &mut self.async_op_stats)
+                {
+                    return;
+                }
+                let at = self.anchor.to_system_time(at);
+                let id = self.ids.insert(id);
+                let parent_id = parent_id.and_then(|id| self.ids.get(&id));
 
                 self.async_ops.insert(
                     id,
@@ -962,6 +1829,8 @@ impl ToProto for TaskStats {
             self_wakes: self.self_wakes,
             waker_drops: self.waker_drops,
             last_wake: self.last_wake.map(Into::into),
+            blocking_poll: self.blocking_poll.map(Into::into),
+            events_dropped: self.events_dropped,
         }
     }
 }
@@ -991,6 +1860,7 @@ impl ToProto for ResourceStats {
             created_at: self.created_at.map(Into::into),
             dropped_at: self.dropped_at.map(Into::into),
             attributes,
+            events_dropped: self.events_dropped,
         }
     }
 }
@@ -1020,6 +1890,7 @@ impl ToProto for AsyncOpStats {
             dropped_at: self.dropped_at.map(Into::into),
             task_id: self.task_id.map(Into::into),
             attributes,
+            events_dropped: self.events_dropped,
         }
     }
 }
@@ -1036,16 +1907,19 @@ impl From<AttributeUpdate> for Attribute {
 // === impl Ids ===
 
 impl Ids {
-    fn id_for(&mut self, span_id: span::Id) -> Id {
-        match self.id_mappings.entry(span_id) {
-            Entry::Occupied(entry) => *entry.get(),
-            Entry::Vacant(entry) => {
-                let task_id = self.next;
-                entry.insert(task_id);
-                self.next = self.next.wrapping_add(1);
-                task_id
-            }
-        }
+    /// Assigns an ID to a newly created span.
+    ///
+    /// Span IDs may be reused once a span has closed, so this replaces the ID
+    /// of any earlier span with the same span ID.
+    fn insert(&mut self, span_id: span::Id) -> Id {
+        let id = self.next;
+        self.id_mappings.insert(span_id, id);
+        self.next = self.next.wrapping_add(1);
+        id
+    }
+
+    fn get(&self, span_id: &span::Id) -> Option<Id> {
+        self.id_mappings.get(span_id).copied()
     }
 
     #[inline]
@@ -1054,6 +1928,11 @@ impl Ids {
     }
 }
 
+/// Takes a list of IDs to send to clients.
+fn ids_proto(ids: &mut Vec<Id>) -> Vec<proto::Id> {
+    ids.drain(..).map(Into::into).collect()
+}
+
 fn serialize_histogram(histogram: &Histogram<u64>) -> Result<Vec<u8>, V2SerializeError> {
     let mut serializer = V2Serializer::new();
     let mut buf = Vec::new();
@@ -1108,3 +1987,682 @@ fn update_attribute(attribute: &mut Attribute, update: &AttributeUpdate) {
         }
     }
 }
+
+/// An aggregator which has aggregated the spawning of some tasks, and which
+/// has a number of watchers, for measuring what publishing an update to them
+/// costs.
+///
+/// This is only public for `tests/publish_allocations.rs`, which counts
+/// allocations with a global allocator of its own.
+#[doc(hidden)]
+pub struct PublishFixture {
+    aggregator: Aggregator,
+    receivers: Vec<mpsc::Receiver<Result<Encoded<proto::instrument::Update>, tonic::Status>>>,
+}
+
+impl PublishFixture {
+    /// The number of tasks spawned before the update is published.
+    pub const TASKS: usize = 32;
+
+    /// Returns an aggregator with `watchers` watchers, which hasn't published
+    /// the tasks it has aggregated yet.
+    pub fn new(watchers: usize) -> Self {
+        use tracing_subscriber::prelude::*;
+
+        let (layer, mut server) = crate::ConsoleLayer::builder().build();
+        let mut aggregator = server
+            .aggregator
+            .take()
+            .expect("a new server has an aggregator");
+        let filter = layer.filter();
+        let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter));
+        tracing::subscriber::with_default(subscriber, || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                for _ in 0..Self::TASKS {
+                    tokio::spawn(async {}).await.unwrap();
+                }
+            });
+        });
+        aggregator.drain_events();
+
+        let receivers = (0..watchers)
+            .map(|_| {
+                let (tx, rx) = mpsc::channel(4);
+                aggregator.watchers.push(Watch(tx));
+                rx
+            })
+            .collect();
+        Self {
+            aggregator,
+            receivers,
+        }
+    }
+
+    /// Publishes an update to every watcher.
+    pub fn publish(&mut self) {
+        self.aggregator.publish();
+    }
+
+    /// Returns the number of new tasks in the update each watcher received.
+    pub fn new_tasks_received(&mut self) -> Vec<usize> {
+        self.receivers
+            .iter_mut()
+            .map(|rx| {
+                let update = rx.try_recv().unwrap().unwrap().decode();
+                update
+                    .task_update
+                    .map_or(0, |update| update.new_tasks.len())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::TestClock, executor::TaskSpan, ConsoleLayer};
+    use std::time::Instant;
+    use tracing_core::{callsite::Callsite, metadata::Kind, subscriber::Interest, Level};
+    use tracing_subscriber::prelude::*;
+
+    struct TaskCallsite;
+
+    pub(super) static TASK: Metadata<'static> = tracing_core::metadata! {
+        name: "runtime.spawn",
+        target: "tokio::task",
+        level: Level::TRACE,
+        fields: &[],
+        callsite: &TaskCallsite,
+        kind: Kind::SPAN,
+    };
+
+    impl Callsite for TaskCallsite {
+        fn set_interest(&self, _: Interest) {}
+
+        fn metadata(&self) -> &Metadata<'_> {
+            &TASK
+        }
+    }
+
+    pub(super) fn new_aggregator(builder: crate::Builder) -> Aggregator {
+        let (_, mut server) = builder.build();
+        server
+            .aggregator
+            .take()
+            .expect("a new server has an aggregator")
+    }
+
+    #[test]
+    fn busy_time_survives_wall_clock_steps() {
+        const HOUR: Duration = Duration::from_secs(60 * 60);
+        const POLL: Duration = Duration::from_millis(5);
+
+        for backwards in [true, false] {
+            let mut clock = TestClock::new();
+            let mut aggregator = new_aggregator(ConsoleLayer::builder());
+            aggregator.anchor = clock.anchor();
+
+            let span = span::Id::from_u64(1);
+            aggregator.update_state(Event::Spawn {
+                id: span.clone(),
+                metadata: &TASK,
+                at: clock.instant(),
+                fields: Vec::new(),
+                location: None,
+            });
+            for _ in 0..2 {
+                clock.advance(Duration::from_millis(1));
+                aggregator.update_state(Event::Enter {
+                    id: span.clone(),
+                    parent_id: None,
+                    at: clock.instant(),
+                });
+                clock.advance(POLL);
+                // The wall clock is stepped while the task is being polled.
+                let stepped = if backwards {
+                    clock.system() - HOUR
+                } else {
+                    clock.system() + HOUR
+                };
+                clock.set_system(stepped);
+                aggregator.update_state(Event::Exit {
+                    id: span.clone(),
+                    parent_id: None,
+                    at: clock.instant(),
+                    async_backtrace: Vec::new(),
+                    span_busy_times: Vec::new(),
+                });
+            }
+
+            let id = aggregator.ids.get(&span).unwrap();
+            let stats = aggregator.task_stats.get(&id).unwrap();
+            assert_eq!(stats.poll_stats.polls, 2);
+            assert_eq!(stats.poll_stats.busy_time, POLL * 2);
+            assert_eq!(
+                stats.poll_stats.last_poll_ended,
+                Some(aggregator.anchor.base() + Duration::from_millis(12))
+            );
+
+            let histogram = &stats.poll_times_histogram;
+            let poll = POLL.as_nanos() as u64;
+            assert_eq!(histogram.len(), 2);
+            assert!(histogram.equivalent(histogram.min(), poll));
+            assert!(histogram.equivalent(histogram.max(), poll));
+        }
+    }
+
+    #[test]
+    fn blocking_polls_are_flagged_past_the_threshold() {
+        const THRESHOLD: Duration = Duration::from_millis(10);
+
+        let mut clock = TestClock::new();
+        let builder = ConsoleLayer::builder().blocking_poll_threshold(THRESHOLD);
+        let mut aggregator = new_aggregator(builder);
+        aggregator.anchor = clock.anchor();
+        for task in 1..=2 {
+            aggregator.update_state(Event::Spawn {
+                id: span::Id::from_u64(task),
+                metadata: &TASK,
+                at: clock.instant(),
+                fields: Vec::new(),
+                location: None,
+            });
+            aggregator.update_state(Event::Enter {
+                id: span::Id::from_u64(task),
+                parent_id: None,
+                at: clock.instant(),
+            });
+        }
+        let exit = |aggregator: &mut Aggregator, task, at| {
+            aggregator.update_state(Event::Exit {
+                id: span::Id::from_u64(task),
+                parent_id: None,
+                at,
+                async_backtrace: Vec::new(),
+                span_busy_times: Vec::new(),
+            })
+        };
+        let blocking_polls = |aggregator: &mut Aggregator, clock: &TestClock| {
+            let now = aggregator.anchor.to_system_time(clock.instant());
+            aggregator.update_blocking_polls(now);
+            [1, 2].map(|task| {
+                let id = aggregator.ids.get(&span::Id::from_u64(task)).unwrap();
+                aggregator.task_stats.get(&id).unwrap().blocking_poll
+            })
+        };
+
+        clock.advance(THRESHOLD / 2);
+        assert_eq!(blocking_polls(&mut aggregator, &clock), [None, None]);
+
+        // The second task yields in time, but the first keeps on polling.
+        exit(&mut aggregator, 2, clock.instant());
+        clock.advance(THRESHOLD);
+        assert_eq!(
+            blocking_polls(&mut aggregator, &clock),
+            [Some(THRESHOLD * 3 / 2), None]
+        );
+
+        exit(&mut aggregator, 1, clock.instant());
+        assert_eq!(blocking_polls(&mut aggregator, &clock), [None, None]);
+    }
+
+    type UpdateRx = mpsc::Receiver<Result<Encoded<proto::instrument::Update>, tonic::Status>>;
+
+    /// Subscribes to `aggregator`'s updates, resuming from `cursor` if it is
+    /// set.
+    fn subscribe(
+        aggregator: &mut Aggregator,
+        cursor: Option<proto::instrument::ResumeCursor>,
+    ) -> UpdateRx {
+        let (tx, rx) = mpsc::channel(MAX_RESUMABLE_UPDATES * 2);
+        aggregator.add_instrument_subscription(Watch(tx), cursor);
+        rx
+    }
+
+    /// Returns the updates which have been sent to `rx`.
+    fn received(rx: &mut UpdateRx) -> Vec<proto::instrument::Update> {
+        std::iter::from_fn(|| Some(rx.try_recv().ok()?.unwrap().decode())).collect()
+    }
+
+    fn cursor(update: &proto::instrument::Update) -> proto::instrument::ResumeCursor {
+        proto::instrument::ResumeCursor {
+            clock_base: update.clock_base.clone(),
+            seq: update.seq,
+        }
+    }
+
+    pub(super) fn spawn_task(aggregator: &mut Aggregator, span: u64) {
+        aggregator.update_state(Event::Spawn {
+            id: span::Id::from_u64(span),
+            metadata: &TASK,
+            at: Instant::now(),
+            fields: Vec::new(),
+            location: None,
+        });
+    }
+
+    /// Returns whether `update` contains the complete state, rather than
+    /// resuming a stream.
+    fn is_complete_state(update: &proto::instrument::Update) -> bool {
+        update.new_metadata.is_some() && update.task_update.is_some()
+    }
+
+    #[test]
+    fn resume_from_the_latest_update() {
+        let mut aggregator = new_aggregator(ConsoleLayer::builder());
+        let mut rx = subscribe(&mut aggregator, None);
+        spawn_task(&mut aggregator, 1);
+        aggregator.publish();
+        let last = received(&mut rx).pop().unwrap();
+        drop(rx);
+
+        // Nothing is dropped while no clients are watching, so the client
+        // misses nothing.
+        aggregator.cleanup_closed();
+        let mut rx = subscribe(&mut aggregator, Some(cursor(&last)));
+        let updates = received(&mut rx);
+        assert_eq!(updates.len(), 1);
+        let resumed = &updates[0];
+        assert!(!is_complete_state(resumed));
+        assert_eq!(resumed.seq, last.seq);
+        assert!(resumed.process_info.is_some());
+        assert!(resumed.protocol.is_some());
+
+        // The changes made since then are in the next update.
+        spawn_task(&mut aggregator, 2);
+        aggregator.publish();
+        let updates = received(&mut rx);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].seq, last.seq + 1);
+        assert_eq!(updates[0].task_update.as_ref().unwrap().new_tasks.len(), 1);
+    }
+
+    #[test]
+    fn resume_after_missed_updates() {
+        let mut aggregator = new_aggregator(ConsoleLayer::builder());
+        let mut rx = subscribe(&mut aggregator, None);
+        let first = received(&mut rx).pop().unwrap();
+        for span in 1..=3 {
+            spawn_task(&mut aggregator, span);
+            aggregator.publish();
+        }
+
+        let mut rx = subscribe(&mut aggregator, Some(cursor(&first)));
+        let updates = received(&mut rx);
+        let seqs = updates.iter().map(|update| update.seq).collect::<Vec<_>>();
+        assert_eq!(
+            seqs,
+            [first.seq, first.seq + 1, first.seq + 2, first.seq + 3]
+        );
+        assert!(updates[0].process_info.is_some());
+        assert!(updates.iter().all(|update| !is_complete_state(update)));
+    }
+
+    #[test]
+    fn no_resume_after_unpublished_changes_are_dropped() {
+        let builder = ConsoleLayer::builder().retention(Duration::ZERO);
+        let mut aggregator = new_aggregator(builder);
+        let mut rx = subscribe(&mut aggregator, None);
+        spawn_task(&mut aggregator, 1);
+        aggregator.publish();
+        let last = received(&mut rx).pop().unwrap();
+        drop(rx);
+        // The closed watcher is dropped by the next publish.
+        aggregator.publish();
+        assert!(aggregator.watchers.is_empty());
+
+        // The task completes while no clients are watching, and is dropped
+        // before its completion is ever published.
+        aggregator.update_state(Event::Close {
+            id: span::Id::from_u64(1),
+            at: Instant::now(),
+        });
+        std::thread::sleep(Duration::from_millis(1));
+        aggregator.cleanup_closed();
+
+        let mut rx = subscribe(&mut aggregator, Some(cursor(&last)));
+        let updates = received(&mut rx);
+        assert_eq!(updates.len(), 1);
+        assert!(is_complete_state(&updates[0]));
+        assert!(updates[0].seq > last.seq + 1);
+        assert!(updates[0]
+            .task_update
+            .as_ref()
+            .unwrap()
+            .new_tasks
+            .is_empty());
+    }
+
+    #[test]
+    fn no_resume_beyond_the_history() {
+        let mut aggregator = new_aggregator(ConsoleLayer::builder());
+        let mut rx = subscribe(&mut aggregator, None);
+        let first = received(&mut rx).pop().unwrap();
+        for _ in 0..=MAX_RESUMABLE_UPDATES {
+            aggregator.publish();
+        }
+        let updates = received(&mut rx);
+
+        let mut rx = subscribe(&mut aggregator, Some(cursor(&first)));
+        let resumed = received(&mut rx);
+        assert_eq!(resumed.len(), 1);
+        assert!(is_complete_state(&resumed[0]));
+
+        // Updates from the oldest one in the history onwards can be resumed.
+        let mut rx = subscribe(&mut aggregator, Some(cursor(&updates[0])));
+        let resumed = received(&mut rx);
+        assert_eq!(resumed.len(), MAX_RESUMABLE_UPDATES + 1);
+        assert!(resumed.iter().all(|update| !is_complete_state(update)));
+    }
+
+    /// Returns a dispatcher with a layer configured by `builder`, which has
+    /// room for 12 events, and the layer's aggregator, which isn't running.
+    fn recording(builder: crate::Builder) -> (tracing::Dispatch, Aggregator) {
+        let (layer, mut server) = builder.event_buffer_capacity(12).build();
+        let mut aggregator = server
+            .aggregator
+            .take()
+            .expect("a new server has an aggregator");
+        let filter = layer.filter();
+        let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter));
+        let dispatch = tracing::Dispatch::new(subscriber);
+        // Every callsite which other tests have registered is registered with
+        // the new layer too, so drain their metadata to start out empty.
+        aggregator.drain_events();
+        (dispatch, aggregator)
+    }
+
+    fn only_task(aggregator: &mut Aggregator) -> crate::snapshot::Task {
+        let mut tasks = aggregator.local_snapshot().tasks;
+        assert_eq!(tasks.len(), 1);
+        tasks.remove(0)
+    }
+
+    #[test]
+    fn prioritize_lifecycle_keeps_polls_and_completions() {
+        let builder = ConsoleLayer::builder().overflow_policy(OverflowPolicy::PrioritizeLifecycle);
+        let (dispatch, mut aggregator) = recording(builder);
+        tracing::dispatcher::with_default(&dispatch, || {
+            let task = TaskSpan::new(Some("task"));
+            for _ in 0..20 {
+                task.waker_ops().woken_by_ref();
+            }
+            drop(task.enter());
+        });
+
+        let task = only_task(&mut aggregator);
+        assert!(task.events_dropped);
+        assert!(task.wakes < 20);
+        assert_eq!(task.poll_stats.polls, 1);
+        assert!(task.dropped_at.is_some());
+    }
+
+    #[test]
+    fn drop_task_closes_the_task() {
+        let builder = ConsoleLayer::builder().overflow_policy(OverflowPolicy::DropTask);
+        let (dispatch, mut aggregator) = recording(builder);
+        let task = tracing::dispatcher::with_default(&dispatch, || {
+            let task = TaskSpan::new(Some("task"));
+            for _ in 0..20 {
+                task.waker_ops().woken_by_ref();
+            }
+            task
+        });
+        let snapshot = only_task(&mut aggregator);
+        assert!(snapshot.events_dropped);
+        assert!(snapshot.dropped_at.is_some());
+
+        // The task isn't recorded any more, even once there is room again.
+        drop(task.enter());
+        assert_eq!(only_task(&mut aggregator).poll_stats.polls, 0);
+    }
+
+    /// Returns how long recording 20 wakes takes under
+    /// [`OverflowPolicy::Block`], while the aggregator is registered as
+    /// running on `aggregator_thread`, but never drains the event buffer.
+    fn blocked_for(aggregator_thread: Option<std::thread::ThreadId>) -> Duration {
+        let builder = ConsoleLayer::builder()
+            .overflow_policy(OverflowPolicy::Block(Duration::from_millis(10)));
+        let (dispatch, mut aggregator) = recording(builder);
+        *aggregator.shared.aggregator_thread.lock() = aggregator_thread;
+        let blocked = tracing::dispatcher::with_default(&dispatch, || {
+            let task = TaskSpan::new(Some("task"));
+            let started = Instant::now();
+            for _ in 0..20 {
+                task.waker_ops().woken_by_ref();
+            }
+            started.elapsed()
+        });
+        assert!(only_task(&mut aggregator).events_dropped);
+        blocked
+    }
+
+    #[test]
+    fn block_waits_for_an_aggregator_on_another_thread() {
+        let other = std::thread::spawn(|| std::thread::current().id())
+            .join()
+            .unwrap();
+        assert!(blocked_for(Some(other)) >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn block_drops_events_without_an_aggregator_thread() {
+        assert!(blocked_for(None) < Duration::from_millis(10));
+        let this = std::thread::current().id();
+        assert!(blocked_for(Some(this)) < Duration::from_millis(10));
+    }
+
+    #[test]
+    fn wake_edges_keep_the_most_frequent_wakers() {
+        let mut edges = HashMap::new();
+        for _ in 0..3 {
+            TaskStats::count_wake(&mut edges, 1);
+        }
+        for peer in 2..100 {
+            TaskStats::count_wake(&mut edges, peer);
+        }
+        assert_eq!(edges.len(), MAX_WAKE_EDGES);
+        assert_eq!(edges[&1], 3);
+    }
+
+    #[test]
+    fn wake_edges_to_dropped_tasks_are_pruned() {
+        let builder = ConsoleLayer::builder().retention(Duration::ZERO);
+        let mut aggregator = new_aggregator(builder);
+        spawn_task(&mut aggregator, 1);
+        spawn_task(&mut aggregator, 2);
+        aggregator.update_state(Event::Waker {
+            id: span::Id::from_u64(2),
+            op: WakeOp::WakeByRef { self_wake: false },
+            at: Instant::now(),
+            woken_by: Some(span::Id::from_u64(1)),
+        });
+        let waker = aggregator.ids.get(&span::Id::from_u64(1)).unwrap();
+        let woken = aggregator.ids.get(&span::Id::from_u64(2)).unwrap();
+        assert_eq!(
+            aggregator.task_stats.get(&woken).unwrap().woken_by[&waker],
+            1
+        );
+
+        aggregator.update_state(Event::Close {
+            id: span::Id::from_u64(1),
+            at: Instant::now(),
+        });
+        std::thread::sleep(Duration::from_millis(1));
+        aggregator.cleanup_closed();
+        assert!(aggregator.task_stats.get(&waker).is_none());
+        assert!(aggregator
+            .task_stats
+            .get(&woken)
+            .unwrap()
+            .woken_by
+            .is_empty());
+    }
+
+    #[test]
+    fn capabilities_follow_tracking_settings() {
+        use proto::instrument::Capability;
+
+        let mut aggregator = new_aggregator(ConsoleLayer::builder());
+        let mut rx = subscribe(&mut aggregator, None);
+        let first = received(&mut rx).pop().unwrap();
+        let capabilities = first.protocol.unwrap().capabilities().collect::<Vec<_>>();
+        assert!(capabilities.contains(&Capability::Resources));
+        assert!(capabilities.contains(&Capability::AsyncOps));
+
+        let update = SettingsUpdate {
+            track_resources: Some(false),
+            ..no_changes()
+        };
+        update_settings(&mut aggregator, update).unwrap();
+        aggregator.publish();
+        aggregator.publish();
+
+        let updates = received(&mut rx);
+        assert_eq!(updates.len(), 2);
+        let capabilities = updates[0]
+            .protocol
+            .as_ref()
+            .unwrap()
+            .capabilities()
+            .collect::<Vec<_>>();
+        assert!(capabilities.contains(&Capability::TaskDetails));
+        assert!(!capabilities.contains(&Capability::Resources));
+        assert!(!capabilities.contains(&Capability::AsyncOps));
+        assert!(updates[1].protocol.is_none());
+    }
+
+    fn no_changes() -> SettingsUpdate {
+        SettingsUpdate {
+            publish_interval: None,
+            retention: None,
+            recording: None,
+            track_resources: None,
+            track_async_ops: None,
+        }
+    }
+
+    /// Applies `update` like the aggregator's run loop does, waiting for any
+    /// recording it starts to start.
+    fn update_settings(
+        aggregator: &mut Aggregator,
+        update: SettingsUpdate,
+    ) -> io::Result<proto::instrument::Settings> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (tx, rx) = oneshot::channel();
+            aggregator.update_settings(update, tx);
+            if aggregator.starting_recording.is_some() {
+                let started = wait_for_recording(&mut aggregator.starting_recording).await;
+                aggregator.recording_started(started);
+            }
+            rx.await.unwrap()
+        })
+    }
+
+    fn start_recording(path: &str) -> SettingsUpdate {
+        SettingsUpdate {
+            recording: Some(RecordingChange::Start(path.into())),
+            retention: Some(Duration::from_secs(1)),
+            ..no_changes()
+        }
+    }
+
+    #[test]
+    fn recordings_are_only_replaced_once_the_new_one_starts() {
+        let dir = std::env::temp_dir().join(format!(
+            "console-subscriber-recording-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let builder = ConsoleLayer::builder().recording_dir(&dir);
+        let mut aggregator = new_aggregator(builder);
+        let retention = aggregator.retention;
+
+        let settings = update_settings(&mut aggregator, start_recording("a.rec")).unwrap();
+        assert_eq!(
+            settings.recording_path,
+            Some(dir.join("a.rec").display().to_string())
+        );
+        assert_eq!(aggregator.retention, Duration::from_secs(1));
+        aggregator.retention = retention;
+
+        // A recording which can't be started leaves the one in progress, and
+        // every other setting, as it was.
+        let error = update_settings(&mut aggregator, start_recording("missing/b.rec"));
+        assert!(error.is_err());
+        assert_eq!(
+            aggregator.recorder.as_ref().unwrap().path(),
+            dir.join("a.rec")
+        );
+        assert_eq!(aggregator.retention, retention);
+
+        // A recording may replace the file of the one in progress.
+        let settings = update_settings(&mut aggregator, start_recording("a.rec")).unwrap();
+        assert_eq!(
+            settings.recording_path,
+            Some(dir.join("a.rec").display().to_string())
+        );
+        assert!(!dir.join(".a.rec.partial").exists());
+
+        let settings = update_settings(
+            &mut aggregator,
+            SettingsUpdate {
+                recording: Some(RecordingChange::Stop),
+                ..no_changes()
+            },
+        )
+        .unwrap();
+        assert_eq!(settings.recording_path, None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn snapshots_are_taken_by_the_running_aggregator() {
+        use proto::instrument::{instrument_server::Instrument, SnapshotRequest};
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // The aggregator stops once the layer is dropped.
+            let (_layer, mut server) = ConsoleLayer::builder().build();
+            let mut aggregator = server.aggregator.take().unwrap();
+            spawn_task(&mut aggregator, 1);
+            let aggregator = tokio::spawn(aggregator.run());
+            let snapshot = |include: Vec<SnapshotKind>| {
+                let request = SnapshotRequest {
+                    include: include.into_iter().map(|kind| kind as i32).collect(),
+                };
+                server.get_snapshot(tonic::Request::new(request))
+            };
+
+            let tasks = snapshot(vec![SnapshotKind::Tasks])
+                .await
+                .unwrap()
+                .into_inner();
+            let task_update = tasks.task_update.unwrap();
+            assert_eq!(task_update.new_tasks.len(), 1);
+            assert_eq!(task_update.stats_update.len(), 1);
+            assert!(tasks.resource_update.is_none());
+            assert!(tasks.async_op_update.is_none());
+            assert!(tasks.new_metadata.is_some());
+
+            let everything = snapshot(Vec::new()).await.unwrap().into_inner();
+            assert!(everything.task_update.is_some());
+            assert!(everything.resource_update.is_some());
+            assert!(everything.async_op_update.is_some());
+
+            aggregator.abort();
+            assert!(aggregator.await.unwrap_err().is_cancelled());
+            let error = snapshot(Vec::new()).await.unwrap_err();
+            assert_eq!(error.code(), tonic::Code::Internal);
+        });
+    }
+}
@@ -0,0 +1,118 @@
+use console_api as proto;
+use std::time::SystemTime;
+use tracing_core::Metadata;
+
+/// Returns the information about this process which is sent to clients,
+/// without the Tokio version, which is only learned later.
+///
+/// `recording_since` is when the aggregator started recording, which is
+/// reported as when the process started if that can't be found.
+pub(super) fn process_info(
+    builder: &crate::Builder,
+    recording_since: SystemTime,
+) -> proto::instrument::ProcessInfo {
+    // The process can't have started after it started recording, even if
+    // the system clock has been changed since.
+    let started_at = started_at().map_or(recording_since, |started_at| {
+        started_at.min(recording_since)
+    });
+    proto::instrument::ProcessInfo {
+        pid: std::process::id(),
+        executable: std::env::current_exe()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+        args: std::env::args_os()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        started_at: Some(started_at.into()),
+        subscriber_version: env!("CARGO_PKG_VERSION").to_string(),
+        tokio_version: String::new(),
+        labels: builder
+            .process_labels
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    }
+}
+
+/// Returns when this process started, if that can be found.
+#[cfg(target_os = "linux")]
+fn started_at() -> Option<SystemTime> {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // The process's start time is the 22nd field of its stat file, in clock
+    // ticks since the system booted. The 2nd field is the name of the
+    // binary, in parentheses, which may itself contain spaces or parentheses.
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    let ticks = fields.split_whitespace().nth(22 - 3)?.parse::<u64>().ok()?;
+    // Safety: `sysconf` has no side effects.
+    let ticks_per_second = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) }).ok()?;
+    if ticks_per_second == 0 {
+        return None;
+    }
+
+    // When the system booted, in seconds since the Unix epoch.
+    let boot_time = std::fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    let since_boot = Duration::from_secs(ticks / ticks_per_second)
+        + Duration::from_nanos((ticks % ticks_per_second) * 1_000_000_000 / ticks_per_second);
+    Some(UNIX_EPOCH + Duration::from_secs(boot_time) + since_boot)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn started_at() -> Option<SystemTime> {
+    None
+}
+
+/// Returns the version of Tokio which recorded the callsite `meta`, if it can
+/// be found.
+///
+/// Tokio doesn't record its version anywhere, but crates built from a registry
+/// are unpacked into directories named after their versions, such as
+/// `tokio-1.15.0`, and the callsite's file is in that directory.
+pub(super) fn tokio_version(meta: &Metadata<'_>) -> Option<String> {
+    if !meta.target().starts_with("tokio") {
+        return None;
+    }
+    meta.file()?
+        .split(&['/', '\\'][..])
+        .filter_map(|component| component.strip_prefix("tokio-"))
+        .find(|version| {
+            let mut parts = version.splitn(3, '.');
+            let is_number = |part: Option<&str>| {
+                part.map_or(false, |part| {
+                    !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())
+                })
+            };
+            is_number(parts.next())
+                && is_number(parts.next())
+                && parts.next().map_or(false, |patch| {
+                    patch.starts_with(|c: char| c.is_ascii_digit())
+                })
+        })
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn process_started_before_recording() {
+        let recording_since = SystemTime::now();
+        let info = process_info(&crate::Builder::default(), recording_since);
+        let started_at = SystemTime::try_from(info.started_at.unwrap()).unwrap();
+        assert!(started_at <= recording_since);
+        // The test binary started a little while ago.
+        assert!(recording_since.duration_since(started_at).unwrap() < Duration::from_secs(60 * 60));
+        #[cfg(target_os = "linux")]
+        assert!(super::started_at().is_some());
+    }
+}
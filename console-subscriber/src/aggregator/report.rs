@@ -0,0 +1,242 @@
+use super::{dump::task_warnings, Aggregator};
+use crate::snapshot;
+use std::{
+    fmt::{self, Write},
+    fs, io,
+    path::Path,
+};
+
+/// The number of tasks listed by busy time.
+const TOP_TASKS: usize = 10;
+
+/// The poll time percentiles listed for each task.
+const PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+impl Aggregator {
+    /// Writes a summary of the current state to the file at `path`: the
+    /// tasks which were busy for longest, their poll times, and the warnings
+    /// the console would show about any task.
+    pub(super) fn write_report(&mut self, path: &Path) -> io::Result<()> {
+        let snapshot = self.local_snapshot();
+        fs::write(path, report(&snapshot))
+    }
+}
+
+fn report(snapshot: &snapshot::Snapshot) -> String {
+    let mut report = String::new();
+    // Writing to a `String` never fails.
+    let _ = write_report(&mut report, snapshot);
+    report
+}
+
+fn write_report(f: &mut String, snapshot: &snapshot::Snapshot) -> fmt::Result {
+    let completed = snapshot
+        .tasks
+        .iter()
+        .filter(|task| task.is_completed())
+        .count();
+    writeln!(
+        f,
+        "tokio-console report for process {}, at {}",
+        std::process::id(),
+        humantime::format_rfc3339_seconds(snapshot.now)
+    )?;
+    writeln!(
+        f,
+        "{} tasks: {} running, {} completed",
+        snapshot.tasks.len(),
+        snapshot.tasks.len() - completed,
+        completed
+    )?;
+    let dropped = &snapshot.dropped_events;
+    if dropped.tasks + dropped.resources + dropped.async_ops > 0 {
+        writeln!(
+            f,
+            "dropped events: {} task, {} resource, {} async op (this report may be incomplete)",
+            dropped.tasks, dropped.resources, dropped.async_ops
+        )?;
+    }
+
+    let mut by_busy_time = snapshot.tasks.iter().collect::<Vec<_>>();
+    by_busy_time.sort_by_key(|task| std::cmp::Reverse(task.poll_stats.busy_time));
+    writeln!(f, "\ntop tasks by busy time:")?;
+    for task in by_busy_time.into_iter().take(TOP_TASKS) {
+        write!(f, "  ")?;
+        write_task(f, task)?;
+        write!(
+            f,
+            "\n    busy {:.2?} over {} polls; poll times",
+            task.poll_stats.busy_time, task.poll_stats.polls
+        )?;
+        for percentile in PERCENTILES {
+            write!(
+                f,
+                " p{} {:.2?},",
+                percentile,
+                task.poll_times.value_at_percentile(percentile)
+            )?;
+        }
+        writeln!(f, " max {:.2?}", task.poll_times.max())?;
+    }
+
+    writeln!(f, "\nwarnings:")?;
+    let mut warned = false;
+    for task in &snapshot.tasks {
+        for warning in task_warnings(task) {
+            warned = true;
+            write!(f, "  ")?;
+            write_task(f, task)?;
+            writeln!(f, ": {}", warning)?;
+        }
+    }
+    if !warned {
+        writeln!(f, "  none")?;
+    }
+    Ok(())
+}
+
+fn write_task(f: &mut String, task: &snapshot::Task) -> fmt::Result {
+    write!(f, "task {}", task.id)?;
+    if let Some(name) = &task.name {
+        write!(f, " ({})", name)?;
+    }
+    if let Some(location) = &task.location {
+        write!(f, " spawned at {}", location)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use console_api as proto;
+    use hdrhistogram::Histogram;
+    use std::time::{Duration, SystemTime};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// Returns a completed task which was busy for `busy` over one poll per
+    /// entry in `poll_times`.
+    fn task(id: u64, busy: Duration, poll_times: &[Duration]) -> snapshot::Task {
+        let mut histogram = Histogram::<u64>::new(2).unwrap();
+        for poll_time in poll_times {
+            histogram.record(poll_time.as_nanos() as u64).unwrap();
+        }
+        snapshot::Task {
+            id,
+            name: None,
+            target: "tokio::task",
+            location: None,
+            fields: Vec::new(),
+            created_at: Some(SystemTime::UNIX_EPOCH),
+            dropped_at: Some(SystemTime::UNIX_EPOCH + busy),
+            poll_stats: snapshot::PollStats {
+                polls: poll_times.len() as u64,
+                busy_time: busy,
+                ..Default::default()
+            },
+            poll_times: snapshot::PollTimes::new(histogram),
+            wakes: 0,
+            self_wakes: 0,
+            waker_clones: 0,
+            waker_drops: 0,
+            last_wake: None,
+            blocking_poll: None,
+            events_dropped: false,
+        }
+    }
+
+    fn snapshot(tasks: Vec<snapshot::Task>) -> snapshot::Snapshot {
+        snapshot::Snapshot {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+            tasks,
+            resources: Vec::new(),
+            async_ops: Vec::new(),
+            dropped_events: Default::default(),
+        }
+    }
+
+    /// Returns the lines of the section of `report` which follows the line
+    /// `heading`, up to the next blank line.
+    fn section<'a>(report: &'a str, heading: &str) -> Vec<&'a str> {
+        report
+            .lines()
+            .skip_while(|line| *line != heading)
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn report_lists_the_busiest_tasks_and_warnings() {
+        let mut tasks = (1..=12)
+            .map(|id| task(id, MS * id as u32, &[MS * id as u32]))
+            .collect::<Vec<_>>();
+
+        // Most of the busiest task's polls are quick, but a few aren't.
+        let mut poll_times = vec![Duration::from_micros(100); 98];
+        poll_times.extend([10 * MS; 2]);
+        let busiest = &mut tasks[11];
+        *busiest = task(12, 12 * MS, &poll_times);
+        busiest.name = Some("busiest".into());
+        busiest.location = Some("src/main.rs:12:5".into());
+
+        // Task 3 is still blocking in its first poll.
+        let blocking = &mut tasks[2];
+        blocking.dropped_at = None;
+        blocking.poll_stats.last_poll_started = Some(SystemTime::UNIX_EPOCH);
+        blocking.blocking_poll = Some(250 * MS);
+
+        // Task 4 yielded without keeping a waker.
+        let lost = &mut tasks[3];
+        lost.dropped_at = None;
+        lost.poll_stats.last_poll_started = Some(SystemTime::UNIX_EPOCH);
+        lost.poll_stats.last_poll_ended = Some(SystemTime::UNIX_EPOCH + 4 * MS);
+
+        let mut snapshot = snapshot(tasks);
+        snapshot.dropped_events.tasks = 3;
+        let report = report(&snapshot);
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "12 tasks: 2 running, 10 completed");
+        assert_eq!(
+            lines[2],
+            "dropped events: 3 task, 0 resource, 0 async op (this report may be incomplete)"
+        );
+
+        let top = section(&report, "top tasks by busy time:");
+        let listed = top
+            .iter()
+            .filter_map(|line| line.strip_prefix("  task "))
+            .map(|task| task.split(' ').next().unwrap().parse().unwrap())
+            .collect::<Vec<u64>>();
+        assert_eq!(listed, (3..=12).rev().collect::<Vec<_>>());
+        assert_eq!(top[0], "  task 12 (busiest) spawned at src/main.rs:12:5");
+        let stats = top[1];
+        assert!(
+            stats.starts_with("    busy 12.00ms over 100 polls; poll times p50 100."),
+            "{}",
+            stats
+        );
+        assert!(stats.contains(" p99 10.0"), "{}", stats);
+        assert!(stats.contains(" max 10.0"), "{}", stats);
+
+        assert_eq!(
+            section(&report, "warnings:"),
+            [
+                format!("  task 3: {}", proto::warnings::blocking_poll(250 * MS)),
+                format!("  task 4: {}", proto::warnings::LOST_WAKER),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_without_warnings_says_none() {
+        let report = report(&snapshot(vec![task(1, MS, &[MS])]));
+        assert_eq!(
+            report.lines().nth(1),
+            Some("1 tasks: 0 running, 1 completed")
+        );
+        assert!(!report.contains("dropped events"));
+        assert_eq!(section(&report, "warnings:"), ["  none"]);
+    }
+}
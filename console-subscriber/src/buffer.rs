@@ -0,0 +1,212 @@
+use crate::{sync::Mutex, Event};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering::*},
+        Arc,
+    },
+    time::Duration,
+};
+use thread_local::ThreadLocal;
+
+/// What a [`ConsoleLayer`] does with an event when its event buffer is full.
+///
+/// Whichever policy is chosen, the tasks, resources, and async ops which lose
+/// events are marked, so that the console can show that their stats may be
+/// wrong.
+///
+/// See [`Builder::overflow_policy`].
+///
+/// [`ConsoleLayer`]: crate::ConsoleLayer
+/// [`Builder::overflow_policy`]: crate::Builder::overflow_policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OverflowPolicy {
+    /// Drop the event.
+    ///
+    /// This is the default.
+    DropEvent,
+
+    /// Keep the last quarter of the buffer for events which spawn, poll, and
+    /// complete tasks, and which create and drop resources and async ops.
+    ///
+    /// Waker events, poll ops, and resource state updates are dropped once
+    /// the buffer is three quarters full, since losing them only makes the
+    /// stats less precise, whereas losing a poll or a completion leaves a
+    /// task which looks busy or running forever.
+    PrioritizeLifecycle,
+
+    /// Wait for up to the given duration for the aggregator to make room in
+    /// the buffer, and drop the event if it doesn't.
+    ///
+    /// This blocks the thread which recorded the event, which may be running
+    /// other tasks. Waiting can only make room if the aggregator keeps running
+    /// meanwhile, so this only waits when the aggregator has a thread of its
+    /// own, as it does when the layer is set up by [`init`] or [`spawn`], and
+    /// the event wasn't recorded on that thread. Otherwise, such as when the
+    /// aggregator is run by [`Server::serve`] on a runtime which also runs
+    /// the instrumented tasks, the event is dropped.
+    ///
+    /// [`Server::serve`]: crate::Server::serve
+    /// [`init`]: crate::init
+    /// [`spawn`]: crate::spawn
+    Block(Duration),
+
+    /// Drop the event, and stop tracking the task it belongs to.
+    ///
+    /// The task is shown as completed when its event was dropped, rather than
+    /// with stats which are wrong from then on.
+    DropTask,
+}
+
+/// Buffers the events recorded by a [`ConsoleLayer`] until the aggregator
+/// drains them.
+///
+/// Each thread records events into its own buffer, so recording an event only
+/// locks a mutex which is uncontended, unless the aggregator happens to be
+/// draining that thread's buffer at the same time. Every event is tagged with a
+/// sequence number, so that the aggregator can process the events recorded on
+/// all threads in the order in which they were recorded.
+///
+/// [`ConsoleLayer`]: crate::ConsoleLayer
+pub(crate) struct EventBuffer {
+    threads: ThreadLocal<Mutex<Vec<(u64, Event)>>>,
+
+    /// The sequence number of the next event to be recorded.
+    next_seq: AtomicU64,
+
+    /// Every event with a sequence number lower than this has been drained.
+    drained: AtomicU64,
+
+    /// The maximum number of events which may be buffered at once.
+    capacity: u64,
+}
+
+/// Takes batches of events out of an [`EventBuffer`], in the order in which
+/// they were recorded.
+pub(crate) struct Batches {
+    buffer: Arc<EventBuffer>,
+
+    /// The current batch of events.
+    batch: Vec<(u64, Event)>,
+
+    /// Events which were drained, but which may have been recorded after an
+    /// event that has not been drained yet.
+    deferred: Vec<(u64, Event)>,
+}
+
+pub(crate) fn buffer(capacity: usize) -> (Arc<EventBuffer>, Batches) {
+    let buffer = Arc::new(EventBuffer {
+        threads: ThreadLocal::new(),
+        next_seq: AtomicU64::new(0),
+        drained: AtomicU64::new(0),
+        capacity: capacity as u64,
+    });
+    let batches = Batches {
+        buffer: buffer.clone(),
+        batch: Vec::new(),
+        deferred: Vec::new(),
+    };
+    (buffer, batches)
+}
+
+// === impl OverflowPolicy ===
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        Self::DropEvent
+    }
+}
+
+// === impl EventBuffer ===
+
+impl EventBuffer {
+    /// Records an event in the current thread's buffer, if fewer than `limit`
+    /// events are buffered.
+    ///
+    /// Returns the number of events which are currently buffered, or the
+    /// event back if the buffer was at the limit.
+    // The event is handed back so that it can be retried, or reported as
+    // lost, rather than boxed.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn push(&self, event: Event, limit: u64) -> Result<u64, Event> {
+        let mut buf = self.threads.get_or_default().lock();
+        // Check the limit before taking a sequence number, so that events
+        // which don't fit don't count against the limit until the next drain.
+        // Other threads may push events in between, so the limit may be
+        // exceeded by a few events.
+        let buffered = self
+            .next_seq
+            .load(Acquire)
+            .saturating_sub(self.drained.load(Acquire))
+            + 1;
+        if buffered > limit {
+            return Err(event);
+        }
+        // The sequence number must be taken while the buffer is locked. This
+        // ensures that once the aggregator has locked every buffer, it has
+        // seen every event with a lower sequence number than it saw before
+        // it started draining.
+        let seq = self.next_seq.fetch_add(1, AcqRel);
+        buf.push((seq, event));
+        Ok(buffered)
+    }
+
+    /// Returns the maximum number of events which may be buffered at once.
+    pub(crate) fn capacity(&self) -> u64 {
+        self.capacity
+    }
+}
+
+impl fmt::Debug for EventBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let next_seq = self.next_seq.load(Acquire);
+        let drained = self.drained.load(Acquire);
+        f.debug_struct("EventBuffer")
+            .field("buffered", &next_seq.saturating_sub(drained))
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+// === impl Batches ===
+
+impl Batches {
+    /// Drains every event which was recorded before this method was called
+    /// into `events`, in the order in which they were recorded.
+    ///
+    /// Returns `false` once every [`ConsoleLayer`] has been dropped and every
+    /// event they recorded has been drained.
+    ///
+    /// [`ConsoleLayer`]: crate::ConsoleLayer
+    pub(crate) fn drain_into(&mut self, events: &mut Vec<Event>) -> bool {
+        let closed = Arc::strong_count(&self.buffer) == 1;
+        let drained = self.buffer.next_seq.load(Acquire);
+
+        self.batch.clear();
+        self.batch.append(&mut self.deferred);
+        for buf in self.buffer.threads.iter() {
+            // `append` leaves the thread's buffer empty, but doesn't shrink
+            // it, so threads can keep recording events without reallocating.
+            self.batch.append(&mut *buf.lock());
+        }
+
+        // Each thread's events, and the deferred events, are already in
+        // order, so the stable sort only has to merge them.
+        self.batch.sort_by_key(|&(seq, _)| seq);
+
+        // Any events recorded after we started draining may have been recorded
+        // after events in buffers that were already drained. Hold on to them
+        // until the next batch.
+        let split = self.batch.partition_point(|&(seq, _)| seq < drained);
+        self.deferred.extend(self.batch.drain(split..));
+        self.buffer.drained.store(drained, Release);
+
+        if closed && self.batch.is_empty() && self.deferred.is_empty() {
+            return false;
+        }
+
+        events.extend(self.batch.drain(..).map(|(_, event)| event));
+        true
+    }
+}
@@ -1,5 +1,7 @@
-use super::{ConsoleLayer, Server};
+use super::{discovery, filter, ConsoleLayer, OverflowPolicy, Server};
+use std::sync::Arc;
 use std::{
+    collections::BTreeMap,
     net::{SocketAddr, ToSocketAddrs},
     path::PathBuf,
     thread,
@@ -8,7 +10,7 @@ use std::{
 use tokio::runtime;
 use tracing::Subscriber;
 use tracing_subscriber::{
-    filter::{FilterFn, LevelFilter, Targets},
+    filter::{LevelFilter, Targets},
     layer::{Layer, SubscriberExt},
     prelude::*,
     registry::LookupSpan,
@@ -17,10 +19,13 @@ use tracing_subscriber::{
 /// Builder for configuring [`ConsoleLayer`]s.
 #[derive(Clone, Debug)]
 pub struct Builder {
-    /// The maximum capacity for the channel of events from the subscriber to
-    /// the aggregator task.
+    /// The maximum number of events buffered by the subscriber before they are
+    /// drained by the aggregator task.
     pub(super) event_buffer_capacity: usize,
 
+    /// What to do with events when the event buffer is full.
+    pub(super) overflow_policy: OverflowPolicy,
+
     /// The maximum number of updates to buffer per-client before the client is
     /// dropped.
     pub(super) client_buffer_capacity: usize,
@@ -31,31 +36,114 @@ pub struct Builder {
     /// How long to retain data for completed events.
     pub(crate) retention: Duration,
 
+    /// How long a single poll may run for before the task is reported as
+    /// blocking its worker thread.
+    pub(crate) blocking_poll_threshold: Duration,
+
+    /// The maximum number of completed task summaries to keep.
+    pub(crate) completed_task_history_capacity: usize,
+
+    /// How long to keep summaries of completed tasks.
+    pub(crate) completed_task_history_retention: Duration,
+
+    /// The maximum number of tasks to track at once, if there is one.
+    pub(crate) max_tasks: Option<usize>,
+
+    /// The maximum number of resources to track at once, if there is one.
+    pub(crate) max_resources: Option<usize>,
+
+    /// The maximum number of async ops to track at once, if there is one.
+    pub(crate) max_async_ops: Option<usize>,
+
+    /// The maximum number of poll ops to keep for new clients.
+    pub(crate) max_poll_ops: usize,
+
     /// The address on which to serve the RPC server.
     pub(super) server_addr: SocketAddr,
 
+    /// If and where to write a discovery file for the RPC server.
+    pub(super) discovery_dir: Option<PathBuf>,
+
     /// If and where to save a recording of the events.
     pub(super) recording_path: Option<PathBuf>,
+
+    /// The directory in which clients may start recordings, if they may.
+    pub(crate) recording_dir: Option<PathBuf>,
+
+    /// Whether the layer starts out dormant.
+    pub(super) dormant: bool,
+
+    /// Whether spans entered by user code inside of tasks are tracked.
+    pub(super) track_user_spans: bool,
+
+    /// The signal which enables a dormant layer, if any.
+    #[cfg(unix)]
+    pub(super) enable_signal: Option<tokio::signal::unix::SignalKind>,
+
+    /// The directory in which to write dumps of the current state.
+    pub(crate) dump_dir: PathBuf,
+
+    /// Whether to dump the current state when a thread panics.
+    pub(super) dump_on_panic: bool,
+
+    /// The signal which dumps the current state, if any.
+    #[cfg(unix)]
+    pub(super) dump_signal: Option<tokio::signal::unix::SignalKind>,
+
+    /// If and where to write a summary report when shutting down.
+    pub(crate) shutdown_report_path: Option<PathBuf>,
+
+    /// The address on which to serve Prometheus metrics, if any.
+    pub(super) prometheus_addr: Option<SocketAddr>,
+
+    /// The predicates which choose the tasks and resources to track.
+    pub(super) filters: filter::Filters,
+
+    /// Labels describing this process, which are sent to clients.
+    pub(crate) process_labels: BTreeMap<String, String>,
 }
 
 impl Default for Builder {
     fn default() -> Self {
         Self {
             event_buffer_capacity: ConsoleLayer::DEFAULT_EVENT_BUFFER_CAPACITY,
+            overflow_policy: OverflowPolicy::default(),
             client_buffer_capacity: ConsoleLayer::DEFAULT_CLIENT_BUFFER_CAPACITY,
             publish_interval: ConsoleLayer::DEFAULT_PUBLISH_INTERVAL,
             retention: ConsoleLayer::DEFAULT_RETENTION,
+            blocking_poll_threshold: ConsoleLayer::DEFAULT_BLOCKING_POLL_THRESHOLD,
+            completed_task_history_capacity: ConsoleLayer::DEFAULT_COMPLETED_TASK_HISTORY_CAPACITY,
+            completed_task_history_retention:
+                ConsoleLayer::DEFAULT_COMPLETED_TASK_HISTORY_RETENTION,
+            max_tasks: None,
+            max_resources: None,
+            max_async_ops: None,
+            max_poll_ops: ConsoleLayer::DEFAULT_MAX_POLL_OPS,
             server_addr: SocketAddr::new(Server::DEFAULT_IP, Server::DEFAULT_PORT),
+            discovery_dir: None,
             recording_path: None,
+            recording_dir: None,
+            dormant: false,
+            track_user_spans: false,
+            #[cfg(unix)]
+            enable_signal: None,
+            dump_dir: std::env::temp_dir(),
+            dump_on_panic: false,
+            #[cfg(unix)]
+            dump_signal: None,
+            shutdown_report_path: None,
+            prometheus_addr: None,
+            filters: filter::Filters::default(),
+            process_labels: BTreeMap::new(),
         }
     }
 }
 
 impl Builder {
-    /// Sets the maximum capacity for the channel of events sent from subscriber
-    /// layers to the aggregator task.
+    /// Sets the maximum number of events recorded by subscriber layers which
+    /// may be buffered until the aggregator task drains them.
     ///
-    /// When this channel is at capacity, additional events will be dropped.
+    /// When the buffer is at capacity, additional events will be dropped.
     ///
     /// By default, this is [`ConsoleLayer::DEFAULT_EVENT_BUFFER_CAPACITY`].
     pub fn event_buffer_capacity(self, event_buffer_capacity: usize) -> Self {
@@ -65,6 +153,22 @@ impl Builder {
         }
     }
 
+    /// Sets what subscriber layers do with events when the event buffer is
+    /// full.
+    ///
+    /// Dropping an event which polls or completes a task leaves the task's
+    /// stats wrong for the rest of its life, so if the buffer overflows
+    /// regularly, it may be better to give up other events, to wait, or to
+    /// give up on the task entirely. See [`OverflowPolicy`] for the options.
+    ///
+    /// By default, this is [`OverflowPolicy::DropEvent`].
+    pub fn overflow_policy(self, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            overflow_policy,
+            ..self
+        }
+    }
+
     /// Sets the maximum capacity of updates to buffer for each subscribed
     /// client, if that client is not reading from the RPC stream.
     ///
@@ -113,6 +217,120 @@ impl Builder {
         Self { retention, ..self }
     }
 
+    /// Sets how long a single poll of a task may run for before the task is
+    /// reported to clients as blocking its worker thread.
+    ///
+    /// Tasks whose current poll has been running for longer than this are
+    /// reported on every update, while they are still being polled, rather
+    /// than only once the poll completes.
+    ///
+    /// By default, this is [`ConsoleLayer::DEFAULT_BLOCKING_POLL_THRESHOLD`].
+    /// Methods like [`init`][`crate::init`] and [`spawn`][`crate::spawn`] will
+    /// take the value from the `TOKIO_CONSOLE_BLOCKING_POLL_THRESHOLD`
+    /// [environment variable] before falling back on that default.
+    ///
+    /// [environment variable]: `Builder::with_default_env`
+    pub fn blocking_poll_threshold(self, blocking_poll_threshold: Duration) -> Self {
+        Self {
+            blocking_poll_threshold,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of completed tasks whose summaries are kept.
+    ///
+    /// Once a completed task is no longer [retained], clients can still list a
+    /// summary of it, including its name, location, timestamps and poll
+    /// statistics. When this many summaries are kept, the oldest is discarded
+    /// to make room for the next. Setting this to zero disables keeping
+    /// summaries of completed tasks.
+    ///
+    /// By default, this is
+    /// [`ConsoleLayer::DEFAULT_COMPLETED_TASK_HISTORY_CAPACITY`].
+    ///
+    /// [retained]: Builder::retention
+    pub fn completed_task_history_capacity(self, completed_task_history_capacity: usize) -> Self {
+        Self {
+            completed_task_history_capacity,
+            ..self
+        }
+    }
+
+    /// Sets how long summaries of completed tasks are kept after the tasks
+    /// complete.
+    ///
+    /// By default, this is
+    /// [`ConsoleLayer::DEFAULT_COMPLETED_TASK_HISTORY_RETENTION`].
+    pub fn completed_task_history_retention(
+        self,
+        completed_task_history_retention: Duration,
+    ) -> Self {
+        Self {
+            completed_task_history_retention,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of tasks which are tracked at once.
+    ///
+    /// Completed tasks are normally tracked until they have been [retained]
+    /// for long enough. When this many tasks are tracked, the task which
+    /// completed longest ago is evicted to make room for each newly spawned
+    /// task, and clients are told to forget it. If none of the tracked tasks
+    /// have completed, the new task isn't tracked at all, and clients are
+    /// told how many tasks weren't tracked.
+    ///
+    /// By default, there is no limit.
+    ///
+    /// [retained]: Builder::retention
+    pub fn max_tasks(self, max_tasks: usize) -> Self {
+        Self {
+            max_tasks: Some(max_tasks),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of resources which are tracked at once.
+    ///
+    /// Resources are evicted, or left untracked, in the same way as tasks are
+    /// when there are [too many of them](Builder::max_tasks).
+    ///
+    /// By default, there is no limit.
+    pub fn max_resources(self, max_resources: usize) -> Self {
+        Self {
+            max_resources: Some(max_resources),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of async ops which are tracked at once.
+    ///
+    /// Async ops are evicted, or left untracked, in the same way as tasks are
+    /// when there are [too many of them](Builder::max_tasks).
+    ///
+    /// By default, there is no limit.
+    pub fn max_async_ops(self, max_async_ops: usize) -> Self {
+        Self {
+            max_async_ops: Some(max_async_ops),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of poll ops which are kept to send to new
+    /// clients.
+    ///
+    /// Every poll of a resource's async op is recorded as a poll op, which
+    /// is kept for as long as the async op is tracked. When this many poll
+    /// ops are kept, the oldest are discarded to make room for new ones.
+    ///
+    /// By default, this is [`ConsoleLayer::DEFAULT_MAX_POLL_OPS`].
+    pub fn max_poll_ops(self, max_poll_ops: usize) -> Self {
+        Self {
+            max_poll_ops,
+            ..self
+        }
+    }
+
     /// Sets the socket address on which to serve the RPC server.
     ///
     /// By default, the server is bound on the IP address [`Server::DEFAULT_IP`]
@@ -122,7 +340,12 @@ impl Builder {
     /// before falling back on constructing a socket address from those
     /// defaults.
     ///
+    /// If the port is 0, the server is bound on a port chosen by the
+    /// operating system. The console can still find such a server if it is
+    /// made [discoverable].
+    ///
     /// [environment variable]: `Builder::with_default_env`
+    /// [discoverable]: Builder::discoverable
     pub fn server_addr(self, server_addr: impl Into<SocketAddr>) -> Self {
         Self {
             server_addr: server_addr.into(),
@@ -130,6 +353,53 @@ impl Builder {
         }
     }
 
+    /// Sets whether the server writes a discovery file, from which the
+    /// console can find this process without being told its address.
+    ///
+    /// The file records the process's ID, the name of its binary, and the
+    /// address the server is bound on, and is removed when the server stops.
+    /// It is written to the [discovery directory], which by default is
+    /// `tokio-console` in `$XDG_RUNTIME_DIR`, or in the temporary directory if
+    /// that isn't set. Running `tokio-console list` lists the processes found
+    /// there, and `tokio-console --pid <PID>` connects to one of them.
+    ///
+    /// On Unix, the directory is created so that only the current user can
+    /// access it, and no file is written if it is owned by another user. The
+    /// console only reads the files which are owned by the user running it.
+    ///
+    /// By default, no discovery file is written.
+    ///
+    /// [discovery directory]: Builder::discovery_dir
+    pub fn discoverable(self, discoverable: bool) -> Self {
+        let discovery_dir = if discoverable {
+            Some(self.discovery_dir.unwrap_or_else(discovery::default_dir))
+        } else {
+            None
+        };
+        Self {
+            discovery_dir,
+            ..self
+        }
+    }
+
+    /// Makes the server [discoverable], writing its discovery file into
+    /// `dir` rather than the default directory.
+    ///
+    /// The console only finds the file if it is told to look in the same
+    /// directory. Methods like [`init`][`crate::init`] and
+    /// [`spawn`][`crate::spawn`] will take the value from the
+    /// `TOKIO_CONSOLE_DISCOVERY_DIR` [environment variable], which the
+    /// console also reads.
+    ///
+    /// [discoverable]: Builder::discoverable
+    /// [environment variable]: `Builder::with_default_env`
+    pub fn discovery_dir(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            discovery_dir: Some(dir.into()),
+            ..self
+        }
+    }
+
     /// Sets the path to record the events to the file system.
     ///
     /// By default, this is initially `None`. Methods like
@@ -145,19 +415,310 @@ impl Builder {
         }
     }
 
+    /// Allows clients to start recordings while the process is running,
+    /// writing them to files in `dir`.
+    ///
+    /// Clients start a recording by sending an `UpdateSettings` RPC, such as
+    /// with `tokio-console settings --record <PATH>`, where the path is
+    /// relative to `dir`. Paths which are absolute, or which contain `.` or
+    /// `..`, are rejected. Anyone who can connect to the RPC server can start
+    /// a recording, and replace any file in `dir`, so `dir` shouldn't contain
+    /// anything else.
+    ///
+    /// By default, clients cannot start recordings. Clients can always stop
+    /// a recording, including one started by [`Builder::recording_path`].
+    pub fn recording_dir(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            recording_dir: Some(dir.into()),
+            ..self
+        }
+    }
+
+    /// Sets whether the [`ConsoleLayer`] starts out dormant.
+    ///
+    /// A dormant layer records nothing, so that it costs next to nothing to
+    /// leave installed, until it is enabled: when a client starts watching
+    /// updates, when an `Enable` RPC is received, or when the signal set by
+    /// [`Builder::enable_on_signal`] is received. Once enabled, it stays
+    /// enabled. While it is dormant, the layer's [filter] disables everything
+    /// but the spans representing tasks for it, and it only notes when each
+    /// task was spawned. The filter is added by [`Builder::spawn`] and
+    /// [`init`]; a layer made by [`Builder::build`] needs it added as well to
+    /// cost next to nothing while dormant.
+    ///
+    /// [filter]: ConsoleLayer::filter
+    ///
+    /// Tasks which were spawned while the layer was dormant are reported from
+    /// the first time they are polled after it is enabled, without their
+    /// names, fields, or locations, which weren't recorded. Resources and
+    /// async ops are only reported if they are created after it is enabled.
+    ///
+    /// By default, the layer is not dormant.
+    pub fn dormant(self, dormant: bool) -> Self {
+        Self { dormant, ..self }
+    }
+
+    /// Sets whether the spans which user code enters while a task is polled
+    /// are tracked.
+    ///
+    /// When they are, each task reports the spans it was inside of when it
+    /// last yielded, as a logical async backtrace, and how much of its busy
+    /// time was spent in each of them. Tracking them means that every span in
+    /// the process, not only the runtime's, is recorded by the
+    /// [`ConsoleLayer`] each time it is entered and exited, which adds to the
+    /// cost of every span.
+    ///
+    /// By default, user spans are not tracked.
+    pub fn track_user_spans(self, track_user_spans: bool) -> Self {
+        Self {
+            track_user_spans,
+            ..self
+        }
+    }
+
+    /// Sets a signal which enables a [dormant] [`ConsoleLayer`] when the
+    /// process receives it.
+    ///
+    /// Once a handler for the signal is installed, the signal no longer has
+    /// its default effect, such as terminating the process.
+    ///
+    /// By default, no signal enables the layer.
+    ///
+    /// [dormant]: Builder::dormant
+    #[cfg(unix)]
+    pub fn enable_on_signal(self, signal: tokio::signal::unix::SignalKind) -> Self {
+        Self {
+            enable_signal: Some(signal),
+            ..self
+        }
+    }
+
+    /// Sets the directory in which dumps of the current state are written.
+    ///
+    /// Each dump is written to a new file named
+    /// `tokio-console-{pid}-{timestamp}.dump`, which can be opened with
+    /// `tokio-console open`. A dump contains the complete state of every task,
+    /// resource, and async op, the details of every task, and any warnings
+    /// about them.
+    ///
+    /// By default, this is the system's temporary directory.
+    pub fn dump_dir(self, dump_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dump_dir: dump_dir.into(),
+            ..self
+        }
+    }
+
+    /// Sets whether to dump the current state to a file in the [dump
+    /// directory] when any thread panics.
+    ///
+    /// The panic hook which was installed before the [`ConsoleLayer`] was
+    /// built still runs first. The panicking thread then waits for up to five
+    /// seconds for the dump to be written.
+    ///
+    /// Panics on the aggregator's own thread, which [`Builder::spawn`] and
+    /// [`init`] start, are never dumped, and the hook returns straight away
+    /// on it. When the [`Server`] is run on a runtime of your own instead, a
+    /// panic on the thread which happens to be running the aggregator task
+    /// waits the full five seconds and is not dumped either.
+    ///
+    /// The hook runs for every panic, including those which are caught, so
+    /// after dumping the state it ignores further panics for a minute.
+    ///
+    /// By default, the state is not dumped on panic.
+    ///
+    /// [dump directory]: Builder::dump_dir
+    pub fn dump_on_panic(self, dump_on_panic: bool) -> Self {
+        Self {
+            dump_on_panic,
+            ..self
+        }
+    }
+
+    /// Sets a signal which dumps the current state to a file in the [dump
+    /// directory] each time the process receives it, such as
+    /// `SignalKind::user_defined1()` for `SIGUSR1`.
+    ///
+    /// Once a handler for the signal is installed, the signal no longer has
+    /// its default effect, such as terminating the process.
+    ///
+    /// By default, no signal dumps the state.
+    ///
+    /// [dump directory]: Builder::dump_dir
+    #[cfg(unix)]
+    pub fn dump_on_signal(self, signal: tokio::signal::unix::SignalKind) -> Self {
+        Self {
+            dump_signal: Some(signal),
+            ..self
+        }
+    }
+
+    /// Sets the path of a summary report to write when the [`Server`] is
+    /// [shut down].
+    ///
+    /// The report lists the tasks which were busy for longest, with the
+    /// percentiles of their poll times, and the warnings the console would
+    /// show about any task.
+    ///
+    /// By default, no report is written.
+    ///
+    /// [shut down]: crate::ConsoleHandle::shutdown
+    pub fn shutdown_report_path(self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            shutdown_report_path: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Sets an address on which to serve metrics over HTTP, at `/metrics`, in
+    /// the Prometheus text exposition format.
+    ///
+    /// The metrics include the number of tasks in each state, their busy and
+    /// idle time, histograms of their poll durations, and the operations on
+    /// their wakers, the number of resources of each kind, and the number of
+    /// dropped events. Task metrics are aggregated by the location each task
+    /// was spawned at, rather than reported per task, so the number of series
+    /// stays bounded.
+    ///
+    /// By default, this is initially `None`, and no metrics are served.
+    /// Methods like [`init`][`crate::init`] and [`spawn`][`crate::spawn`] will
+    /// take the value from the `TOKIO_CONSOLE_PROMETHEUS_BIND` [environment
+    /// variable] before falling back on that default.
+    ///
+    /// [environment variable]: `Builder::with_default_env`
+    pub fn prometheus_addr(self, prometheus_addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            prometheus_addr: Some(prometheus_addr.into()),
+            ..self
+        }
+    }
+
+    /// Sets a predicate which chooses the tasks to track by the target of the
+    /// span representing them.
+    ///
+    /// Tasks spawned from a target for which the predicate returns `false`
+    /// are ignored by the console layer entirely: the layer's
+    /// [filter](ConsoleLayer::filter) disables their spawn callsites for the
+    /// console layer, though not for any other layers, so it never sees them.
+    /// This is the cheapest way to exclude a task, but Tokio spawns all tasks
+    /// from the same few callsites, so it can only tell apart tasks spawned
+    /// through different instrumentation.
+    ///
+    /// By default, tasks from every target are tracked.
+    pub fn filter_task_targets<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.filters.task_targets = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Sets a predicate which chooses the tasks to track by their name and
+    /// the location they were spawned from.
+    ///
+    /// The predicate is called once for each spawned task. Tasks for which it
+    /// returns `false` never appear in the console, nor in recordings, dumps,
+    /// or metrics. Tasks spawned while the layer was [dormant] are passed to
+    /// it without a name or a location, which weren't recorded.
+    ///
+    /// For example, to ignore the connection tasks which a `hyper` server
+    /// spawns:
+    ///
+    /// ```rust
+    /// let builder = console_subscriber::ConsoleLayer::builder().filter_tasks(|task| {
+    ///     !task.file.map_or(false, |file| file.contains("/hyper-"))
+    /// });
+    /// ```
+    ///
+    /// By default, every task is tracked.
+    ///
+    /// [dormant]: Builder::dormant
+    pub fn filter_tasks<F>(mut self, predicate: F) -> Self
+    where
+        F: for<'a> Fn(&filter::Task<'a>) -> bool + Send + Sync + 'static,
+    {
+        self.filters.tasks = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Sets a predicate which chooses the resources to track by their kind
+    /// and concrete type.
+    ///
+    /// Resources for which the predicate returns `false` are ignored, as are
+    /// the async operations on them. The decision is made once for each
+    /// callsite which creates resources, and then the layer's
+    /// [filter](ConsoleLayer::filter) disables the callsite for the console
+    /// layer, so the
+    /// predicate should only depend on the fields of [`filter::Resource`],
+    /// which are the same for every resource created at a callsite. Resources
+    /// created through the [`resource`](crate::resource) module share a
+    /// callsite, so the decision is made for each of them instead. For
+    /// example, to ignore timers:
+    ///
+    /// ```rust
+    /// let builder = console_subscriber::ConsoleLayer::builder()
+    ///     .filter_resources(|resource| resource.kind != "Timer");
+    /// ```
+    ///
+    /// By default, every resource is tracked.
+    pub fn filter_resources<F>(mut self, predicate: F) -> Self
+    where
+        F: for<'a> Fn(&filter::Resource<'a>) -> bool + Send + Sync + 'static,
+    {
+        self.filters.resources = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Adds a label describing this process, such as the name of the service
+    /// it runs or the commit it was built from.
+    ///
+    /// Labels are sent to clients along with the process's ID, its command
+    /// line, and the versions of `console-subscriber` and Tokio it uses, and
+    /// are shown by the console. Setting a label which was already set
+    /// replaces its value. For example:
+    ///
+    /// ```rust
+    /// let builder = console_subscriber::ConsoleLayer::builder()
+    ///     .process_label("service", "checkout")
+    ///     .process_label("version", env!("CARGO_PKG_VERSION"));
+    /// ```
+    ///
+    /// By default, there are no labels.
+    pub fn process_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.process_labels.insert(key.into(), value.into());
+        self
+    }
+
     /// Completes the builder, returning a [`ConsoleLayer`] and [`Server`] task.
+    ///
+    /// The layer should be added with the per-layer filter returned by
+    /// [`ConsoleLayer::filter`], which disables the callsites it doesn't
+    /// record for it alone. Without the filter, the layer still records the
+    /// same things, but every span and event is dispatched to it first:
+    ///
+    /// ```rust
+    /// use tracing_subscriber::prelude::*;
+    ///
+    /// let (layer, server) = console_subscriber::ConsoleLayer::builder().build();
+    /// let filter = layer.filter();
+    /// let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter));
+    /// # drop((subscriber, server));
+    /// ```
     pub fn build(self) -> (ConsoleLayer, Server) {
         ConsoleLayer::build(self)
     }
 
     /// Configures this builder from a standard set of environment variables:
     ///
-    /// | **Environment Variable**         | **Purpose**                                                  | **Default Value** |
-    /// |----------------------------------|--------------------------------------------------------------|-------------------|
-    /// | `TOKIO_CONSOLE_RETENTION`        | The duration of seconds to accumulate completed tracing data | 3600s (1h)        |
-    /// | `TOKIO_CONSOLE_BIND`             | a HOST:PORT description, such as `localhost:1234`            | `127.0.0.1:6669`  |
-    /// | `TOKIO_CONSOLE_PUBLISH_INTERVAL` | The duration to wait between sending updates to the console  | 1000ms (1s)       |
-    /// | `TOKIO_CONSOLE_RECORD_PATH`      | The file path to save a recording                            | None              |
+    /// | **Environment Variable**                | **Purpose**                                                          | **Default Value** |
+    /// |-----------------------------------------|----------------------------------------------------------------------|-------------------|
+    /// | `TOKIO_CONSOLE_RETENTION`               | The duration of seconds to accumulate completed tracing data         | 3600s (1h)        |
+    /// | `TOKIO_CONSOLE_BIND`                    | a HOST:PORT description, such as `localhost:1234`                    | `127.0.0.1:6669`  |
+    /// | `TOKIO_CONSOLE_PUBLISH_INTERVAL`        | The duration to wait between sending updates to the console          | 1000ms (1s)       |
+    /// | `TOKIO_CONSOLE_RECORD_PATH`             | The file path to save a recording                                    | None              |
+    /// | `TOKIO_CONSOLE_BLOCKING_POLL_THRESHOLD` | The duration a single poll may run before it is reported as blocking | 100ms             |
+    /// | `TOKIO_CONSOLE_PROMETHEUS_BIND`         | a HOST:PORT on which to serve Prometheus metrics                     | None              |
+    /// | `TOKIO_CONSOLE_DISCOVERY_DIR`           | A directory in which to write a discovery file                       | None              |
     pub fn with_default_env(mut self) -> Self {
         if let Some(retention) = duration_from_env("TOKIO_CONSOLE_RETENTION") {
             self.retention = retention;
@@ -179,6 +740,23 @@ impl Builder {
             self.recording_path = Some(path.into());
         }
 
+        if let Some(threshold) = duration_from_env("TOKIO_CONSOLE_BLOCKING_POLL_THRESHOLD") {
+            self.blocking_poll_threshold = threshold;
+        }
+
+        if let Ok(bind) = std::env::var("TOKIO_CONSOLE_PROMETHEUS_BIND") {
+            self.prometheus_addr = Some(
+                bind.to_socket_addrs()
+                    .expect("TOKIO_CONSOLE_PROMETHEUS_BIND must be formatted as HOST:PORT, such as localhost:9090")
+                    .next()
+                    .expect("tokio console could not resolve TOKIO_CONSOLE_PROMETHEUS_BIND"),
+            );
+        }
+
+        if let Some(dir) = std::env::var_os("TOKIO_CONSOLE_DISCOVERY_DIR") {
+            self.discovery_dir = Some(dir.into());
+        }
+
         self
     }
 
@@ -214,13 +792,16 @@ impl Builder {
     /// use cases. If you need to tune these parameters, several environmental
     /// configuration variables are available:
     ///
-    /// | **Environment Variable**            | **Purpose**                                                               | **Default Value** |
-    /// |-------------------------------------|---------------------------------------------------------------------------|-------------------|
-    /// | `TOKIO_CONSOLE_RETENTION`           | The number of seconds to accumulate completed tracing data                | 3600s (1h)        |
-    /// | `TOKIO_CONSOLE_BIND`                | A HOST:PORT description, such as `localhost:1234`                         | `127.0.0.1:6669`  |
-    /// | `TOKIO_CONSOLE_PUBLISH_INTERVAL`    | The number of milliseconds to wait between sending updates to the console | 1000ms (1s)       |
-    /// | `TOKIO_CONSOLE_RECORD_PATH`         | The file path to save a recording                                         | None              |
-    /// | `RUST_LOG`                          | Configures what events are logged events. See [`Targets`] for details.    | "error"           |
+    /// | **Environment Variable**                | **Purpose**                                                               | **Default Value** |
+    /// |-----------------------------------------|---------------------------------------------------------------------------|-------------------|
+    /// | `TOKIO_CONSOLE_RETENTION`               | The number of seconds to accumulate completed tracing data                | 3600s (1h)        |
+    /// | `TOKIO_CONSOLE_BIND`                    | A HOST:PORT description, such as `localhost:1234`                         | `127.0.0.1:6669`  |
+    /// | `TOKIO_CONSOLE_PUBLISH_INTERVAL`        | The number of milliseconds to wait between sending updates to the console | 1000ms (1s)       |
+    /// | `TOKIO_CONSOLE_RECORD_PATH`             | The file path to save a recording                                         | None              |
+    /// | `TOKIO_CONSOLE_BLOCKING_POLL_THRESHOLD` | The duration a single poll may run before it is reported as blocking      | 100ms             |
+    /// | `TOKIO_CONSOLE_PROMETHEUS_BIND`         | A HOST:PORT on which to serve Prometheus metrics                          | None              |
+    /// | `TOKIO_CONSOLE_DISCOVERY_DIR`           | A directory in which to write a discovery file                            | None              |
+    /// | `RUST_LOG`                              | Configures what events are logged events. See [`Targets`] for details.    | "error"           |
     ///
     /// # Further customization
     ///
@@ -320,22 +901,10 @@ impl Builder {
     where
         S: Subscriber + for<'a> LookupSpan<'a>,
     {
-        fn console_filter(meta: &tracing::Metadata<'_>) -> bool {
-            // events will have *targets* beginning with "runtime"
-            if meta.is_event() {
-                return meta.target().starts_with("runtime") || meta.target().starts_with("tokio");
-            }
-
-            // spans will have *names* beginning with "runtime". for backwards
-            // compatibility with older Tokio versions, enable anything with the `tokio`
-            // target as well.
-            meta.name().starts_with("runtime.") || meta.target().starts_with("tokio")
-        }
-
-        let (layer, server) = self.build();
-        let filter =
-            FilterFn::new(console_filter as for<'r, 's> fn(&'r tracing::Metadata<'s>) -> bool);
+        let (layer, mut server) = self.build();
+        let filter = layer.filter();
         let layer = layer.with_filter(filter);
+        server.set_aggregator_owns_thread();
 
         thread::Builder::new()
             .name("console_subscriber".into())
@@ -391,13 +960,16 @@ impl Builder {
 /// use cases. If you need to tune these parameters, several environmental
 /// configuration variables are available:
 ///
-/// | **Environment Variable**            | **Purpose**                                                               | **Default Value** |
-/// |-------------------------------------|---------------------------------------------------------------------------|-------------------|
-/// | `TOKIO_CONSOLE_RETENTION`           | The number of seconds to accumulate completed tracing data                | 3600s (1h)        |
-/// | `TOKIO_CONSOLE_BIND`                | A HOST:PORT description, such as `localhost:1234`                         | `127.0.0.1:6669`  |
-/// | `TOKIO_CONSOLE_PUBLISH_INTERVAL`    | The number of milliseconds to wait between sending updates to the console | 1000ms (1s)       |
-/// | `TOKIO_CONSOLE_RECORD_PATH`         | The file path to save a recording                                         | None              |
-/// | `RUST_LOG`                          | Configures what events are logged events. See [`Targets`] for details.    | "error"           |
+/// | **Environment Variable**                | **Purpose**                                                               | **Default Value** |
+/// |-----------------------------------------|---------------------------------------------------------------------------|-------------------|
+/// | `TOKIO_CONSOLE_RETENTION`               | The number of seconds to accumulate completed tracing data                | 3600s (1h)        |
+/// | `TOKIO_CONSOLE_BIND`                    | A HOST:PORT description, such as `localhost:1234`                         | `127.0.0.1:6669`  |
+/// | `TOKIO_CONSOLE_PUBLISH_INTERVAL`        | The number of milliseconds to wait between sending updates to the console | 1000ms (1s)       |
+/// | `TOKIO_CONSOLE_RECORD_PATH`             | The file path to save a recording                                         | None              |
+/// | `TOKIO_CONSOLE_BLOCKING_POLL_THRESHOLD` | The duration a single poll may run before it is reported as blocking      | 100ms             |
+/// | `TOKIO_CONSOLE_PROMETHEUS_BIND`         | A HOST:PORT on which to serve Prometheus metrics                          | None              |
+/// | `TOKIO_CONSOLE_DISCOVERY_DIR`           | A directory in which to write a discovery file                            | None              |
+/// | `RUST_LOG`                              | Configures what events are logged events. See [`Targets`] for details.    | "error"           |
 ///
 /// # Further customization
 ///
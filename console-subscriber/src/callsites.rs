@@ -41,12 +41,12 @@ impl<const MAX_CALLSITES: usize> Callsites<MAX_CALLSITES> {
         }
     }
 
-    pub(crate) fn contains(&self, callsite: &'static Metadata<'static>) -> bool {
+    pub(crate) fn contains(&self, callsite: &Metadata<'_>) -> bool {
         let mut start = 0;
         let mut len = self.len.load(Ordering::Acquire);
         loop {
             for cs in &self.ptrs[start..len] {
-                if ptr::eq(cs.load(Ordering::Acquire), callsite) {
+                if ptr::eq(cs.load(Ordering::Acquire).cast(), callsite) {
                     return true;
                 }
             }
@@ -73,7 +73,7 @@ impl<const MAX_CALLSITES: usize> Callsites<MAX_CALLSITES> {
     }
 
     #[cold]
-    fn check_spill(&self, callsite: &'static Metadata<'static>) -> bool {
+    fn check_spill(&self, callsite: &Metadata<'_>) -> bool {
         self.spill.read().contains(&callsite.callsite())
     }
 }
@@ -0,0 +1,127 @@
+use std::time::{Instant, SystemTime};
+
+/// Converts the monotonic [`Instant`]s at which events are recorded into
+/// wall-clock [`SystemTime`]s.
+///
+/// Events are timestamped using the monotonic clock, which never goes
+/// backwards, and is cheaper to read than the system clock on some platforms.
+/// They are converted into wall-clock times by adding the time elapsed since
+/// the anchor was created to the wall-clock time at which it was created.
+///
+/// The system clock is only read when the anchor is created, so durations
+/// between converted times stay correct if the system clock is later stepped
+/// backwards or forwards.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Anchor {
+    instant: Instant,
+    system: SystemTime,
+}
+
+impl Anchor {
+    pub(crate) fn new() -> Self {
+        Self::from_parts(Instant::now(), SystemTime::now())
+    }
+
+    /// Returns an anchor which converts `instant` into the wall-clock time
+    /// `system`.
+    pub(crate) fn from_parts(instant: Instant, system: SystemTime) -> Self {
+        Self { instant, system }
+    }
+
+    /// Returns the wall-clock time at which the monotonic clock was anchored.
+    pub(crate) fn base(self) -> SystemTime {
+        self.system
+    }
+
+    /// Returns the wall-clock time corresponding to `instant`.
+    pub(crate) fn to_system_time(self, instant: Instant) -> SystemTime {
+        self.system + instant.saturating_duration_since(self.instant)
+    }
+
+    /// Returns the current wall-clock time, according to the monotonic clock.
+    pub(crate) fn now(self) -> SystemTime {
+        self.to_system_time(Instant::now())
+    }
+}
+
+/// A clock whose monotonic and wall-clock times are set by tests, and whose
+/// wall-clock time may be stepped independently of its monotonic time.
+#[cfg(test)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct TestClock {
+    instant: Instant,
+    system: SystemTime,
+}
+
+#[cfg(test)]
+impl TestClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000),
+        }
+    }
+
+    /// Returns an anchor created by reading this clock.
+    pub(crate) fn anchor(&self) -> Anchor {
+        Anchor::from_parts(self.instant, self.system)
+    }
+
+    /// Returns the current monotonic time.
+    pub(crate) fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// Returns the current wall-clock time.
+    pub(crate) fn system(&self) -> SystemTime {
+        self.system
+    }
+
+    /// Lets `by` pass on both clocks.
+    pub(crate) fn advance(&mut self, by: std::time::Duration) {
+        self.instant += by;
+        self.system += by;
+    }
+
+    /// Steps the wall clock to `to`, as an NTP step or a manual change of the
+    /// system clock would, without affecting the monotonic clock.
+    pub(crate) fn set_system(&mut self, to: SystemTime) {
+        self.system = to;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn converted_times_ignore_wall_clock_steps() {
+        for backwards in [true, false] {
+            let mut clock = TestClock::new();
+            let anchor = clock.anchor();
+            clock.advance(Duration::from_millis(1));
+            let enter = clock.instant();
+            clock.advance(Duration::from_millis(5));
+            let stepped = if backwards {
+                clock.system() - HOUR
+            } else {
+                clock.system() + HOUR
+            };
+            clock.set_system(stepped);
+            let exit = clock.instant();
+
+            let elapsed = anchor
+                .to_system_time(exit)
+                .duration_since(anchor.to_system_time(enter))
+                .unwrap();
+            assert_eq!(elapsed, Duration::from_millis(5));
+            assert_eq!(
+                anchor.to_system_time(exit),
+                anchor.base() + Duration::from_millis(6)
+            );
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use serde::Serialize;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+/// A discovery file, which tells the console where this process serves the
+/// console API.
+///
+/// The file is removed when this is dropped.
+#[derive(Debug)]
+pub(crate) struct DiscoveryFile {
+    path: PathBuf,
+}
+
+/// The contents of a discovery file.
+///
+/// The console reads these, so changing them may break it.
+#[derive(Serialize)]
+struct Process {
+    pid: u32,
+    name: String,
+    addr: SocketAddr,
+}
+
+/// Returns the directory which discovery files are written to by default, and
+/// which the console looks for them in.
+///
+/// This is `tokio-console` in the user's runtime directory, if there is one,
+/// and in the temporary directory otherwise.
+pub(crate) fn default_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("tokio-console")
+}
+
+// === impl DiscoveryFile ===
+
+impl DiscoveryFile {
+    /// Writes a discovery file for the server listening on `addr` into `dir`.
+    pub(crate) fn write(dir: &Path, addr: SocketAddr) -> io::Result<Self> {
+        create_private_dir(dir)?;
+        let process = Process {
+            pid: std::process::id(),
+            name: process_name(),
+            addr,
+        };
+        // A process may serve the console API more than once.
+        let file_name = format!("{}-{}.json", process.pid, addr.port());
+        let path = dir.join(&file_name);
+        // Write the file under another name first, so that the console never
+        // reads a partially written file.
+        let partial = dir.join(format!(".{}", file_name));
+        fs::write(&partial, serde_json::to_vec(&process)?)?;
+        fs::rename(&partial, &path)?;
+        Ok(Self { path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for DiscoveryFile {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_file(&self.path) {
+            tracing::warn!(%error, path = %self.path.display(), "failed to remove discovery file");
+        }
+    }
+}
+
+/// Creates `dir`, if it doesn't exist, so that only the current user can
+/// access it.
+///
+/// The default directory may be in a temporary directory which all users
+/// share, so this fails if `dir` is owned by another user.
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    // Safety: `geteuid` is always successful, and has no side effects.
+    if fs::metadata(dir)?.uid() != unsafe { libc::geteuid() } {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is owned by another user", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+/// Returns the name of the running binary.
+fn process_name() -> String {
+    let exe = std::env::current_exe()
+        .ok()
+        .or_else(|| std::env::args_os().next().map(PathBuf::from));
+    exe.as_deref()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_file_is_written_and_removed() {
+        let root = std::env::temp_dir().join(format!(
+            "console-subscriber-discovery-test-{}",
+            std::process::id()
+        ));
+        let dir = root.join("tokio-console");
+        let addr = SocketAddr::from(([127, 0, 0, 1], 6669));
+
+        let file = DiscoveryFile::write(&dir, addr).unwrap();
+        let path = file.path().to_owned();
+        let process: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(process["pid"], std::process::id());
+        assert_eq!(process["addr"], "127.0.0.1:6669");
+        assert_eq!(process["name"], process_name());
+        // Only the discovery file itself is left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        drop(file);
+        assert!(!path.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
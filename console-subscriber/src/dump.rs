@@ -0,0 +1,101 @@
+//! Triggers for dumping the aggregator's state to a file.
+use crate::{Command, Shared};
+use std::{
+    panic,
+    sync::{mpsc as blocking, Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+/// How long a panicking thread waits for its dump to be written.
+const PANIC_DUMP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long after dumping the state because of a panic the hook ignores
+/// further panics, so that a process which repeatedly panics and catches the
+/// panics isn't slowed down by dumping each time.
+const PANIC_DUMP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Installs a panic hook which dumps the current state, after running the
+/// previously installed hook.
+///
+/// The hook only holds a weak reference to `subscribe`, so that it doesn't
+/// keep the aggregator running once the [`Server`] has been dropped.
+///
+/// Panics on the aggregator's own thread aren't dumped, since the aggregator
+/// can't write the dump while the thread waits for it.
+///
+/// [`Server`]: crate::Server
+pub(crate) fn install_panic_hook(subscribe: &Arc<mpsc::Sender<Command>>, shared: &Arc<Shared>) {
+    let subscribe = Arc::downgrade(subscribe);
+    let shared = Arc::downgrade(shared);
+    let last_dump = Mutex::new(None::<Instant>);
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+
+        if shared
+            .upgrade()
+            .map_or(false, |shared| shared.is_aggregator_thread())
+        {
+            return;
+        }
+        {
+            // If another thread is panicking at the same time, it is already
+            // dumping the state.
+            let mut last_dump = match last_dump.try_lock() {
+                Ok(last_dump) => last_dump,
+                Err(_) => return,
+            };
+            let now = Instant::now();
+            if matches!(*last_dump, Some(at) if now.duration_since(at) < PANIC_DUMP_INTERVAL) {
+                return;
+            }
+            *last_dump = Some(now);
+        }
+        dump_on_panic(&subscribe, info.to_string());
+    }));
+}
+
+fn dump_on_panic(subscribe: &Weak<mpsc::Sender<Command>>, reason: String) {
+    let (tx, rx) = blocking::channel();
+    let sent = subscribe.upgrade().map_or(false, |subscribe| {
+        subscribe.try_send(Command::Dump(reason, tx)).is_ok()
+    });
+    if !sent {
+        eprintln!("tokio-console: could not dump state: the aggregator is not accepting commands");
+        return;
+    }
+    match rx.recv_timeout(PANIC_DUMP_TIMEOUT) {
+        Ok(Ok(path)) => eprintln!("tokio-console: dumped state to {}", path.display()),
+        Ok(Err(error)) => eprintln!("tokio-console: could not dump state: {}", error),
+        Err(blocking::RecvTimeoutError::Timeout) => {
+            eprintln!("tokio-console: timed out waiting for the state to be dumped")
+        }
+        Err(blocking::RecvTimeoutError::Disconnected) => {
+            eprintln!("tokio-console: could not dump state: the aggregator has stopped")
+        }
+    }
+}
+
+/// Dumps the current state each time `signal` is received.
+#[cfg(unix)]
+pub(crate) async fn dump_on_signal(
+    signal: tokio::signal::unix::SignalKind,
+    subscribe: mpsc::Sender<Command>,
+) {
+    let mut signals = match tokio::signal::unix::signal(signal) {
+        Ok(signals) => signals,
+        Err(error) => {
+            tracing::warn!(%error, "cannot listen for the signal to dump the console state");
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        // The aggregator logs where the state was dumped to.
+        let (tx, _) = blocking::channel();
+        let reason = "received signal".to_string();
+        if subscribe.send(Command::Dump(reason, tx)).await.is_err() {
+            return;
+        }
+    }
+}
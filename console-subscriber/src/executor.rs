@@ -0,0 +1,174 @@
+//! Instrumentation for executors other than Tokio's, so that the console
+//! shows the tasks they run.
+//!
+//! A [`ConsoleLayer`] learns about tasks from the spans and events that Tokio
+//! records when it spawns and polls tasks, and when their wakers are used.
+//! Any executor may record the same spans and events, and its tasks are then
+//! shown alongside Tokio's.
+//!
+//! # The instrumentation contract
+//!
+//! * **Spawning.** Each task is represented by a span named `runtime.spawn`,
+//!   which is created when the task is spawned. The task's name may be
+//!   recorded in a `task.name` field, and where it was spawned from in
+//!   `loc.file`, `loc.line`, and `loc.col` fields. Any other fields are shown
+//!   as the task's fields.
+//! * **Polling.** The task's span is entered whenever the task is polled, and
+//!   exited when the poll returns.
+//! * **Wakers.** Each operation on one of the task's wakers is recorded by an
+//!   event with the target `runtime::waker`, with an `op` field which is one
+//!   of `waker.clone`, `waker.wake`, `waker.wake_by_ref`, or `waker.drop`,
+//!   and a `task.id` field holding the ID of the task's span, as a `u64`.
+//!   Waking a waker by value doesn't also record dropping it. Wakers which
+//!   are only lent to a task while it is polled, rather than cloned, aren't
+//!   recorded.
+//! * **Completion.** The task completes when its span is closed, once every
+//!   handle to it has been dropped.
+//!
+//! [`TaskSpan`] records these spans, and [`WakerOps`] records the waker
+//! events. The spans and events are recorded at the `TRACE` level.
+//!
+//! # Examples
+//!
+//! The core of an executor's poll loop, instrumented:
+//!
+//! ```rust
+//! use console_subscriber::executor::TaskSpan;
+//! use std::{
+//!     future::Future,
+//!     pin::Pin,
+//!     task::{Context, Waker},
+//! };
+//!
+//! struct Task {
+//!     future: Pin<Box<dyn Future<Output = ()> + Send>>,
+//!     span: TaskSpan,
+//! }
+//!
+//! impl Task {
+//!     #[track_caller]
+//!     fn new(name: &str, future: impl Future<Output = ()> + Send + 'static) -> Self {
+//!         Self {
+//!             future: Box::pin(future),
+//!             span: TaskSpan::new(Some(name)),
+//!         }
+//!     }
+//!
+//!     /// Polls the task, returning `true` once it has completed.
+//!     fn poll(&mut self, waker: &Waker) -> bool {
+//!         let _polling = self.span.enter();
+//!         let mut cx = Context::from_waker(waker);
+//!         self.future.as_mut().poll(&mut cx).is_ready()
+//!     }
+//! }
+//! ```
+//!
+//! The executor's wakers then report what is done with them, such as in the
+//! functions of a [`RawWakerVTable`]:
+//!
+//! ```rust
+//! # use console_subscriber::executor::TaskSpan;
+//! let span = TaskSpan::new(Some("simulation"));
+//! let ops = span.waker_ops();
+//! // In the vtable's `clone` function:
+//! ops.cloned();
+//! // In its `wake` function:
+//! ops.woken();
+//! ```
+//!
+//! [`ConsoleLayer`]: crate::ConsoleLayer
+//! [`RawWakerVTable`]: std::task::RawWakerVTable
+use std::panic::Location;
+use tracing::{span::Entered, Span};
+
+/// The span representing a task, which is created when the task is spawned.
+///
+/// The task completes, as far as the console is concerned, when every clone
+/// of this is dropped, so an executor should drop it once the task's future
+/// has completed, or when the task is cancelled.
+#[derive(Clone, Debug)]
+pub struct TaskSpan {
+    span: Span,
+}
+
+/// Records what is done with the wakers of a task.
+///
+/// This is returned by [`TaskSpan::waker_ops`], and may be copied into each
+/// of the task's wakers.
+#[derive(Clone, Copy, Debug)]
+pub struct WakerOps {
+    /// The ID of the task's span, or `None` if the span is disabled.
+    task_id: Option<u64>,
+}
+
+// === impl TaskSpan ===
+
+impl TaskSpan {
+    /// Returns the span representing a task with the given `name`, which is
+    /// being spawned.
+    ///
+    /// The task's location is where this is called from.
+    #[track_caller]
+    pub fn new(name: Option<&str>) -> Self {
+        let location = Location::caller();
+        let span = tracing::trace_span!(
+            "runtime.spawn",
+            kind = "task",
+            task.name = name,
+            loc.file = location.file(),
+            loc.line = location.line(),
+            loc.col = location.column(),
+        );
+        Self { span }
+    }
+
+    /// Marks that the task is being polled, until the returned guard is
+    /// dropped.
+    pub fn enter(&self) -> Entered<'_> {
+        self.span.enter()
+    }
+
+    /// Returns a [`WakerOps`] which records what is done with the task's
+    /// wakers.
+    pub fn waker_ops(&self) -> WakerOps {
+        WakerOps {
+            task_id: self.span.id().map(|id| id.into_u64()),
+        }
+    }
+
+    /// Returns the [`Span`] representing the task.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+// === impl WakerOps ===
+
+impl WakerOps {
+    /// Records that one of the task's wakers was cloned.
+    pub fn cloned(&self) {
+        self.record("waker.clone");
+    }
+
+    /// Records that one of the task's wakers was woken by value, which
+    /// consumes it.
+    pub fn woken(&self) {
+        self.record("waker.wake");
+    }
+
+    /// Records that one of the task's wakers was woken by reference.
+    pub fn woken_by_ref(&self) {
+        self.record("waker.wake_by_ref");
+    }
+
+    /// Records that one of the task's wakers was dropped.
+    pub fn dropped(&self) {
+        self.record("waker.drop");
+    }
+
+    fn record(&self, op: &str) {
+        if let Some(task_id) = self.task_id {
+            tracing::trace!(target: "runtime::waker", op, task.id = task_id);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Descriptions of tasks and resources, which are passed to the predicates
+//! that choose which of them a [`ConsoleLayer`] tracks, and the per-layer
+//! filter which applies them.
+//!
+//! See [`Builder::filter_task_targets`], [`Builder::filter_tasks`],
+//! [`Builder::filter_resources`], and [`ConsoleLayer::filter`].
+//!
+//! [`ConsoleLayer`]: crate::ConsoleLayer
+//! [`ConsoleLayer::filter`]: crate::ConsoleLayer::filter
+//! [`Builder::filter_task_targets`]: crate::Builder::filter_task_targets
+//! [`Builder::filter_tasks`]: crate::Builder::filter_tasks
+//! [`Builder::filter_resources`]: crate::Builder::filter_resources
+use crate::callsites::Callsites;
+use console_api as proto;
+use std::{fmt, sync::Arc};
+use tracing_core::{subscriber::Interest, Metadata};
+use tracing_subscriber::layer;
+
+/// A task which is being spawned.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Task<'a> {
+    /// The target of the span representing the task.
+    pub target: &'a str,
+
+    /// The task's name, if it was given one.
+    pub name: Option<&'a str>,
+
+    /// The file the task was spawned from, if known.
+    pub file: Option<&'a str>,
+
+    /// The line the task was spawned from, if known.
+    pub line: Option<u32>,
+}
+
+/// A resource which is being created, such as a timer, a lock, or a channel.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Resource<'a> {
+    /// The target of the span representing the resource.
+    pub target: &'a str,
+
+    /// The kind of resource, such as `"Timer"`.
+    pub kind: &'a str,
+
+    /// The type of the resource, such as `"Sleep"`.
+    pub concrete_type: &'a str,
+}
+
+/// The per-layer filter which enables the spans and events that a
+/// [`ConsoleLayer`] records, and disables everything else for it alone.
+///
+/// Callsites of tasks and resources which the [`Builder`] excludes are disabled
+/// here, rather than by the layer itself: a [`Filtered`] layer's own
+/// [`Interest`] is ignored, and an unfiltered layer's would disable the
+/// callsites for every other layer as well.
+///
+/// This is returned by [`ConsoleLayer::filter`].
+///
+/// [`Builder`]: crate::Builder
+/// [`ConsoleLayer`]: crate::ConsoleLayer
+/// [`ConsoleLayer::filter`]: crate::ConsoleLayer::filter
+/// [`Filtered`]: tracing_subscriber::filter::Filtered
+#[derive(Debug)]
+pub struct ConsoleFilter {
+    pub(crate) filters: Filters,
+
+    /// Callsites whose resources were excluded by the resource filter.
+    pub(crate) excluded_callsites: Arc<Callsites<32>>,
+
+    /// Whether spans entered by user code inside of tasks are enabled.
+    pub(crate) track_user_spans: bool,
+
+    /// State shared with the layer, which says whether it is dormant.
+    pub(crate) shared: Arc<crate::Shared>,
+}
+
+type TargetPredicate = dyn Fn(&str) -> bool + Send + Sync;
+type TaskPredicate = dyn for<'a> Fn(&Task<'a>) -> bool + Send + Sync;
+type ResourcePredicate = dyn for<'a> Fn(&Resource<'a>) -> bool + Send + Sync;
+
+/// The predicates set on a [`Builder`](crate::Builder).
+#[derive(Clone, Default)]
+pub(crate) struct Filters {
+    pub(crate) task_targets: Option<Arc<TargetPredicate>>,
+    pub(crate) tasks: Option<Arc<TaskPredicate>>,
+    pub(crate) resources: Option<Arc<ResourcePredicate>>,
+}
+
+// === impl Filters ===
+
+impl Filters {
+    /// Returns `true` if tasks spawned from a callsite with `target` should
+    /// be tracked.
+    pub(crate) fn tracks_task_target(&self, target: &str) -> bool {
+        self.task_targets
+            .as_ref()
+            .map_or(true, |predicate| predicate(target))
+    }
+
+    /// Returns `true` if the task spawned from a callsite with `target`, with
+    /// `fields` and `location`, should be tracked.
+    pub(crate) fn tracks_task(
+        &self,
+        target: &str,
+        fields: &[proto::Field],
+        location: Option<&proto::Location>,
+    ) -> bool {
+        let predicate = match &self.tasks {
+            Some(predicate) => predicate,
+            None => return true,
+        };
+        let name =
+            fields.iter().find_map(
+                |field| match (field.name.as_ref()?, field.value.as_ref()?) {
+                    (
+                        proto::field::Name::StrName(name),
+                        proto::field::Value::StrVal(value) | proto::field::Value::DebugVal(value),
+                    ) if name == "task.name" => Some(value.as_str()),
+                    _ => None,
+                },
+            );
+        let task = Task {
+            target,
+            // Tokio records an empty name for tasks which weren't given one.
+            name: name.filter(|name| !name.is_empty()),
+            file: location.and_then(|location| location.file.as_deref()),
+            line: location.and_then(|location| location.line),
+        };
+        predicate(&task)
+    }
+
+    /// Returns `true` if resources like the given one should be tracked.
+    pub(crate) fn tracks_resource(&self, resource: &Resource<'_>) -> bool {
+        self.resources
+            .as_ref()
+            .map_or(true, |predicate| predicate(resource))
+    }
+
+    /// Returns `true` if any tasks may be excluded after they are spawned.
+    pub(crate) fn filters_tasks(&self) -> bool {
+        self.tasks.is_some()
+    }
+}
+
+// === impl ConsoleFilter ===
+
+impl ConsoleFilter {
+    /// Returns `true` if spans and events from the callsite described by
+    /// `meta` are recorded, regardless of which resources have been excluded.
+    fn enables_callsite(&self, meta: &Metadata<'_>) -> bool {
+        // events will have *targets* beginning with "runtime"
+        if meta.is_event() {
+            return meta.target().starts_with("runtime") || meta.target().starts_with("tokio");
+        }
+
+        if crate::is_spawn_span(meta) {
+            return self.filters.tracks_task_target(meta.target());
+        }
+
+        if crate::is_runtime_span(meta) {
+            return true;
+        }
+
+        // user spans are only enabled if the console records which of them
+        // a task was inside of when it yielded.
+        self.track_user_spans && meta.is_span()
+    }
+}
+
+impl<S> layer::Filter<S> for ConsoleFilter {
+    fn enabled(&self, meta: &Metadata<'_>, _: &layer::Context<'_, S>) -> bool {
+        // A dormant layer only notes which tasks are spawned.
+        if self.shared.is_dormant() && !crate::is_spawn_span(meta) {
+            return false;
+        }
+        self.enables_callsite(meta) && !self.excluded_callsites.contains(meta)
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        if !self.enables_callsite(meta) || self.excluded_callsites.contains(meta) {
+            return Interest::never();
+        }
+        // While the layer is dormant, everything but tasks is disabled, until
+        // the layer is enabled and the callsite is registered again.
+        if self.shared.is_dormant() && !crate::is_spawn_span(meta) {
+            return Interest::never();
+        }
+        // The resource filter can only exclude a callsite once it has seen a
+        // resource created there, so until then, resource callsites are
+        // checked each time.
+        if self.filters.resources.is_some()
+            && meta.name() == crate::visitors::ResourceVisitor::RES_SPAN_NAME
+        {
+            return Interest::sometimes();
+        }
+        Interest::always()
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Filters")
+            .field("task_targets", &self.task_targets.is_some())
+            .field("tasks", &self.tasks.is_some())
+            .field("resources", &self.resources.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing, ConsoleLayer};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[test]
+    fn excluded_resources_are_not_recorded_or_evaluated_again() {
+        let evaluated = Arc::new(AtomicUsize::new(0));
+        let builder = ConsoleLayer::builder().filter_resources({
+            let evaluated = evaluated.clone();
+            move |resource| {
+                if resource.concrete_type != "Sleep" {
+                    return true;
+                }
+                evaluated.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        });
+        let report = testing::run_with(builder, async {
+            for _ in 0..10 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            let mutex = tokio::sync::Mutex::new(());
+            drop(mutex.lock().await);
+        });
+
+        let resources = &report.snapshot().resources;
+        assert!(resources
+            .iter()
+            .all(|resource| resource.concrete_type != "Sleep"));
+        assert!(resources
+            .iter()
+            .any(|resource| resource.concrete_type == "Mutex"));
+        assert_eq!(evaluated.load(Ordering::Relaxed), 1);
+    }
+}
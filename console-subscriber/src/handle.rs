@@ -0,0 +1,96 @@
+use crate::{snapshot::Snapshot, Command};
+use std::{error::Error, fmt};
+use tokio::sync::{mpsc, oneshot};
+
+/// A handle for querying a [`ConsoleLayer`]'s state from inside the
+/// instrumented process, without a gRPC client.
+///
+/// A `ConsoleHandle` is returned by [`Server::handle`]. It can be cloned
+/// freely, and used from any async runtime. Its methods are answered by the
+/// aggregator task, so they only complete once the [`Server`] is running.
+///
+/// # Examples
+///
+/// ```rust
+/// # async fn docs() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+/// let (layer, server) = console_subscriber::ConsoleLayer::builder().build();
+/// let handle = server.handle();
+/// tokio::spawn(server.serve());
+/// # drop(layer);
+///
+/// let snapshot = handle.snapshot().await?;
+/// for task in &snapshot.tasks {
+///     println!("{:?}: {} polls", task.name, task.poll_stats.polls);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ConsoleLayer`]: crate::ConsoleLayer
+/// [`Server`]: crate::Server
+/// [`Server::handle`]: crate::Server::handle
+#[derive(Clone)]
+pub struct ConsoleHandle {
+    subscribe: mpsc::Sender<Command>,
+}
+
+/// The error returned by [`ConsoleHandle`] methods when the aggregator task
+/// has stopped, or was never started.
+#[derive(Debug)]
+pub struct AggregatorStopped {
+    _p: (),
+}
+
+impl ConsoleHandle {
+    pub(crate) fn new(subscribe: mpsc::Sender<Command>) -> Self {
+        Self { subscribe }
+    }
+
+    /// Returns a snapshot of the current state of every task, resource, and
+    /// async op.
+    pub async fn snapshot(&self) -> Result<Snapshot, AggregatorStopped> {
+        let (tx, rx) = oneshot::channel();
+        self.subscribe
+            .send(Command::LocalSnapshot(tx))
+            .await
+            .map_err(|_| AggregatorStopped { _p: () })?;
+        rx.await.map_err(|_| AggregatorStopped { _p: () })
+    }
+
+    /// Shuts down the [`Server`] gracefully.
+    ///
+    /// Every event recorded so far is aggregated and published to the
+    /// clients which are watching, the recording is ended, once everything
+    /// recorded has been written to it, and the [shutdown report] is written,
+    /// if there is one. The server then stops, and this returns.
+    ///
+    /// Call this before the process exits, so that no data is lost. Once the
+    /// server has stopped, the [`ConsoleLayer`] can no longer be queried, and
+    /// the events it records are discarded.
+    ///
+    /// [`Server`]: crate::Server
+    /// [`ConsoleLayer`]: crate::ConsoleLayer
+    /// [shutdown report]: crate::Builder::shutdown_report_path
+    pub async fn shutdown(&self) -> Result<(), AggregatorStopped> {
+        let (tx, rx) = oneshot::channel();
+        self.subscribe
+            .send(Command::Shutdown(tx))
+            .await
+            .map_err(|_| AggregatorStopped { _p: () })?;
+        rx.await.map_err(|_| AggregatorStopped { _p: () })
+    }
+}
+
+impl fmt::Debug for ConsoleHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsoleHandle").finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for AggregatorStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the console aggregator task is not running")
+    }
+}
+
+impl Error for AggregatorStopped {}
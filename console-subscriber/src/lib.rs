@@ -1,16 +1,20 @@
 #![doc = include_str!("../README.md")]
 use console_api as proto;
-use proto::resources::resource;
 use serde::Serialize;
 use std::{
     cell::RefCell,
-    fmt,
+    collections::HashMap,
+    convert::TryFrom,
+    fmt, io,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Component, PathBuf},
+    pin::Pin,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
-    time::{Duration, SystemTime},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
 };
 use thread_local::ThreadLocal;
 use tokio::sync::{mpsc, oneshot};
@@ -20,20 +24,41 @@ use tracing_core::{
     subscriber::{self, NoSubscriber, Subscriber},
     Metadata,
 };
-use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use tracing_subscriber::{
+    layer::Context,
+    registry::{LookupSpan, SpanRef},
+    Layer,
+};
 
 mod aggregator;
+mod buffer;
 mod builder;
 mod callsites;
+mod clock;
+mod discovery;
+mod dump;
+pub mod executor;
+pub mod filter;
+mod handle;
+mod prometheus;
 mod record;
+pub mod resource;
+mod service;
+pub mod snapshot;
 mod stack;
 pub(crate) mod sync;
+pub mod testing;
 mod visitors;
 
 use aggregator::Aggregator;
+use buffer::EventBuffer;
+pub use buffer::OverflowPolicy;
 pub use builder::Builder;
 use callsites::Callsites;
-use stack::SpanStack;
+use discovery::DiscoveryFile;
+pub use handle::{AggregatorStopped, ConsoleHandle};
+use service::{Encoded, InstrumentService, WatchUpdates};
+use stack::{SpanKind, SpanStack};
 use visitors::{AsyncOpVisitor, ResourceVisitor, ResourceVisitorResult, TaskVisitor, WakerVisitor};
 
 pub use builder::{init, spawn};
@@ -53,11 +78,14 @@ use crate::visitors::{PollOpVisitor, StateUpdateVisitor};
 /// [`tracing`]: https://docs.rs/tracing
 pub struct ConsoleLayer {
     current_spans: ThreadLocal<RefCell<SpanStack>>,
-    tx: mpsc::Sender<Event>,
+    events: Arc<EventBuffer>,
     shared: Arc<Shared>,
-    /// When the channel capacity goes under this number, a flush in the aggregator
-    /// will be triggered.
-    flush_under_capacity: usize,
+    /// When the number of buffered events reaches this number, a flush in the
+    /// aggregator will be triggered.
+    flush_at_len: u64,
+
+    /// What to do with events when the event buffer is full.
+    overflow_policy: OverflowPolicy,
 
     /// Set of callsites for spans representing spawned tasks.
     ///
@@ -103,6 +131,18 @@ pub struct ConsoleLayer {
     /// TODO: Take some time to determine more reasonable numbers
     async_op_state_update_callsites: Callsites<32>,
 
+    /// Set of callsites whose resources are excluded by the resource filter.
+    ///
+    /// This is shared with the layer's [`filter::ConsoleFilter`], which
+    /// disables them.
+    excluded_callsites: Arc<Callsites<32>>,
+
+    /// The predicates which choose the tasks and resources to track.
+    filters: filter::Filters,
+
+    /// Whether spans entered by user code inside of tasks are tracked.
+    track_user_spans: bool,
+
     /// Used for unsetting the default dispatcher inside of span callbacks.
     no_dispatch: Dispatch,
 }
@@ -121,8 +161,20 @@ pub struct ConsoleLayer {
 pub struct Server {
     subscribe: mpsc::Sender<Command>,
     addr: SocketAddr,
+    discovery_dir: Option<PathBuf>,
     aggregator: Option<Aggregator>,
     client_buffer: usize,
+    #[cfg(unix)]
+    enable_signal: Option<tokio::signal::unix::SignalKind>,
+    #[cfg(unix)]
+    dump_signal: Option<tokio::signal::unix::SignalKind>,
+    prometheus_addr: Option<SocketAddr>,
+    /// The sender used by the panic hook which dumps the current state, if
+    /// there is one.
+    ///
+    /// The hook only has a weak reference to this, so that the aggregator
+    /// stops once the server is dropped.
+    _panic_dumps: Option<Arc<mpsc::Sender<Command>>>,
 }
 
 /// State shared between the `ConsoleLayer` and the `Aggregator` task.
@@ -143,17 +195,92 @@ struct Shared {
     /// A counter of how many resource events were dropped because the event buffer
     /// was at capacity.
     dropped_resources: AtomicUsize,
+
+    /// Set when resources, and the events describing them, should not be
+    /// recorded.
+    resources_disabled: AtomicBool,
+
+    /// Set when async ops, and the events describing them, should not be
+    /// recorded.
+    async_ops_disabled: AtomicBool,
+
+    /// Set while the layer is dormant, and records nothing.
+    ///
+    /// Once the layer has been enabled, it is never dormant again.
+    dormant: AtomicBool,
+
+    /// The tasks, resources, and async ops whose events were dropped since the
+    /// aggregator last drained the event buffer.
+    ///
+    /// These are kept outside of the event buffer, since the buffer is full
+    /// when they are recorded.
+    lost_events: sync::Mutex<HashMap<span::Id, LostEvents>>,
+
+    /// The thread which the aggregator runs on, if it has a thread of its own.
+    ///
+    /// [`OverflowPolicy::Block`] only waits for room in the event buffer on
+    /// other threads, as the aggregator can then make room while they wait.
+    aggregator_thread: sync::Mutex<Option<ThreadId>>,
+}
+
+/// Describes the events which were dropped for a task, resource, or async op.
+#[derive(Clone, Copy, Debug)]
+struct LostEvents {
+    /// When the first event was dropped.
+    at: Instant,
+
+    /// Whether the event closing the span was dropped.
+    closed: bool,
 }
 
 struct Watch<T>(mpsc::Sender<Result<T, tonic::Status>>);
 
 enum Command {
-    Instrument(Watch<proto::instrument::Update>),
+    Instrument(
+        Watch<Encoded<proto::instrument::Update>>,
+        Option<proto::instrument::ResumeCursor>,
+    ),
     WatchTaskDetail(WatchRequest<proto::tasks::TaskDetails>),
+    Snapshot(
+        proto::instrument::SnapshotRequest,
+        oneshot::Sender<proto::instrument::Update>,
+    ),
+    ListCompletedTasks(
+        proto::instrument::ListCompletedTasksRequest,
+        oneshot::Sender<proto::instrument::ListCompletedTasksResponse>,
+    ),
+    UpdateSettings(
+        SettingsUpdate,
+        oneshot::Sender<io::Result<proto::instrument::Settings>>,
+    ),
+    LocalSnapshot(oneshot::Sender<snapshot::Snapshot>),
+    Enable(oneshot::Sender<bool>),
+    /// Dumps the current state to a file, for the given reason.
+    ///
+    /// The reply is sent over a blocking channel, so that a panicking thread
+    /// can wait for it.
+    Dump(String, std::sync::mpsc::Sender<io::Result<PathBuf>>),
+    Metrics(oneshot::Sender<String>),
+    Shutdown(oneshot::Sender<()>),
     Pause,
     Resume,
 }
 
+/// Changes to the aggregator's settings, requested by an `UpdateSettings` RPC.
+struct SettingsUpdate {
+    publish_interval: Option<Duration>,
+    retention: Option<Duration>,
+    recording: Option<RecordingChange>,
+    track_resources: Option<bool>,
+    track_async_ops: Option<bool>,
+}
+
+enum RecordingChange {
+    /// Starts recording to a path relative to the recording directory.
+    Start(PathBuf),
+    Stop,
+}
+
 struct WatchRequest<T> {
     id: Id,
     stream_sender: oneshot::Sender<mpsc::Receiver<Result<T, tonic::Status>>>,
@@ -166,36 +293,46 @@ enum Event {
     Spawn {
         id: span::Id,
         metadata: &'static Metadata<'static>,
-        at: SystemTime,
+        at: Instant,
         fields: Vec<proto::Field>,
         location: Option<proto::Location>,
     },
     Enter {
         id: span::Id,
         parent_id: Option<span::Id>,
-        at: SystemTime,
+        at: Instant,
     },
     Exit {
         id: span::Id,
         parent_id: Option<span::Id>,
-        at: SystemTime,
+        at: Instant,
+        /// The user spans that were entered beneath this span when it was
+        /// exited, if it is a task's span.
+        async_backtrace: Vec<&'static Metadata<'static>>,
+        /// The time spent in each user span entered beneath this span since it
+        /// was entered, if it is a task's span.
+        span_busy_times: Vec<(&'static Metadata<'static>, Duration)>,
     },
     Close {
         id: span::Id,
-        at: SystemTime,
+        at: Instant,
     },
     Waker {
         id: span::Id,
         op: WakeOp,
-        at: SystemTime,
+        at: Instant,
+        /// The task that was running when the waker was woken, if any.
+        ///
+        /// This is only recorded for wake operations.
+        woken_by: Option<span::Id>,
     },
     Resource {
         id: span::Id,
         parent_id: Option<span::Id>,
         metadata: &'static Metadata<'static>,
-        at: SystemTime,
+        at: Instant,
         concrete_type: String,
-        kind: resource::Kind,
+        kind: proto::resources::resource::Kind,
         location: Option<proto::Location>,
         is_internal: bool,
         inherit_child_attrs: bool,
@@ -218,7 +355,7 @@ enum Event {
         parent_id: Option<span::Id>,
         resource_id: span::Id,
         metadata: &'static Metadata<'static>,
-        at: SystemTime,
+        at: Instant,
         source: String,
         inherit_child_attrs: bool,
     },
@@ -256,6 +393,16 @@ enum WakeOp {
 #[derive(Debug)]
 struct Tracked {}
 
+/// Marks a task spawned while the layer was dormant, so that the task can be
+/// reported if it is polled once the layer has been enabled.
+///
+/// Only when the task was spawned is noted, since recording its fields would
+/// cost as much as tracking it.
+#[derive(Debug)]
+struct DormantTask {
+    at: Instant,
+}
+
 impl ConsoleLayer {
     /// Returns a `ConsoleLayer` built with the default settings.
     ///
@@ -289,30 +436,58 @@ impl ConsoleLayer {
             config.client_buffer_capacity,
             ?config.publish_interval,
             ?config.retention,
+            ?config.blocking_poll_threshold,
             ?config.server_addr,
+            ?config.discovery_dir,
             ?config.recording_path,
+            ?config.recording_dir,
+            config.dormant,
+            config.track_user_spans,
+            ?config.dump_dir,
+            config.dump_on_panic,
+            ?config.filters,
+            ?config.overflow_policy,
+            ?config.shutdown_report_path,
+            ?config.prometheus_addr,
             "configured console subscriber"
         );
 
-        let (tx, events) = mpsc::channel(config.event_buffer_capacity);
+        let (events, batches) = buffer::buffer(config.event_buffer_capacity);
         let (subscribe, rpcs) = mpsc::channel(256);
-        let shared = Arc::new(Shared::default());
-        let aggregator = Aggregator::new(events, rpcs, &config, shared.clone());
-        // Conservatively, start to trigger a flush when half the channel is full.
-        // This tries to reduce the chance of losing events to a full channel.
-        let flush_under_capacity = config.event_buffer_capacity / 2;
+        let shared = Arc::new(Shared {
+            dormant: AtomicBool::new(config.dormant),
+            ..Shared::default()
+        });
+        let aggregator = Aggregator::new(batches, rpcs, &config, shared.clone());
+        // Conservatively, start to trigger a flush when half the buffer is full.
+        // This tries to reduce the chance of losing events to a full buffer.
+        let flush_at_len = (config.event_buffer_capacity / 2) as u64;
+
+        let panic_dumps = config.dump_on_panic.then(|| {
+            let panic_dumps = Arc::new(subscribe.clone());
+            dump::install_panic_hook(&panic_dumps, &shared);
+            panic_dumps
+        });
 
         let server = Server {
             aggregator: Some(aggregator),
             addr: config.server_addr,
+            discovery_dir: config.discovery_dir,
             subscribe,
             client_buffer: config.client_buffer_capacity,
+            #[cfg(unix)]
+            enable_signal: config.enable_signal,
+            #[cfg(unix)]
+            dump_signal: config.dump_signal,
+            prometheus_addr: config.prometheus_addr,
+            _panic_dumps: panic_dumps,
         };
         let layer = Self {
             current_spans: ThreadLocal::new(),
-            tx,
+            events,
             shared,
-            flush_under_capacity,
+            flush_at_len,
+            overflow_policy: config.overflow_policy,
             spawn_callsites: Callsites::default(),
             waker_callsites: Callsites::default(),
             resource_callsites: Callsites::default(),
@@ -321,6 +496,9 @@ impl ConsoleLayer {
             poll_op_callsites: Callsites::default(),
             resource_state_update_callsites: Callsites::default(),
             async_op_state_update_callsites: Callsites::default(),
+            excluded_callsites: Arc::default(),
+            filters: config.filters,
+            track_user_spans: config.track_user_spans,
             no_dispatch: Dispatch::new(NoSubscriber::default()),
         };
         (layer, server)
@@ -328,7 +506,7 @@ impl ConsoleLayer {
 }
 
 impl ConsoleLayer {
-    /// Default maximum capacity for the channel of events sent from a
+    /// Default maximum capacity for the buffer of events sent from a
     /// [`ConsoleLayer`] to a [`Server`].
     ///
     /// When this capacity is exhausted, additional events will be dropped.
@@ -337,6 +515,10 @@ impl ConsoleLayer {
     ///
     /// See also [`Builder::event_buffer_capacity`].
     pub const DEFAULT_EVENT_BUFFER_CAPACITY: usize = 1024 * 100;
+
+    /// How long to sleep between attempts to record an event, while waiting
+    /// for room in the buffer under [`OverflowPolicy::Block`].
+    const BLOCK_INTERVAL: Duration = Duration::from_micros(100);
     /// Default maximum capacity for th echannel of events sent from a
     /// [`Server`] to each subscribed client.
     ///
@@ -369,6 +551,35 @@ impl ConsoleLayer {
     /// [environment variable]: `Builder::with_default_env`
     pub const DEFAULT_RETENTION: Duration = Duration::from_secs(60 * 60);
 
+    /// By default, a task is reported as blocking its worker thread once a
+    /// single poll has been running for 100 milliseconds.
+    ///
+    /// Note that methods like [`init`][`crate::init`] and
+    /// [`spawn`][`crate::spawn`] will take the value from the
+    /// `TOKIO_CONSOLE_BLOCKING_POLL_THRESHOLD` [environment variable] before
+    /// falling back on this default.
+    ///
+    /// See also [`Builder::blocking_poll_threshold`].
+    ///
+    /// [environment variable]: `Builder::with_default_env`
+    pub const DEFAULT_BLOCKING_POLL_THRESHOLD: Duration = Duration::from_millis(100);
+
+    /// By default, summaries of up to 10,000 completed tasks are kept.
+    ///
+    /// See also [`Builder::completed_task_history_capacity`].
+    pub const DEFAULT_COMPLETED_TASK_HISTORY_CAPACITY: usize = 10_000;
+
+    /// By default, summaries of completed tasks are kept for one day.
+    ///
+    /// See also [`Builder::completed_task_history_retention`].
+    pub const DEFAULT_COMPLETED_TASK_HISTORY_RETENTION: Duration =
+        Duration::from_secs(60 * 60 * 24);
+
+    /// By default, up to 10,000 poll ops are kept to send to new clients.
+    ///
+    /// See also [`Builder::max_poll_ops`].
+    pub const DEFAULT_MAX_POLL_OPS: usize = 10_000;
+
     fn is_spawn(&self, meta: &'static Metadata<'static>) -> bool {
         self.spawn_callsites.contains(meta)
     }
@@ -381,6 +592,15 @@ impl ConsoleLayer {
         self.async_op_callsites.contains(meta)
     }
 
+    /// Returns `true` if `meta` describes a span that was created by user
+    /// code, rather than by the async runtime, and user spans are tracked.
+    fn is_user_span(&self, meta: &'static Metadata<'static>) -> bool {
+        self.track_user_spans
+            && meta.is_span()
+            && !is_runtime_span(meta)
+            && !self.async_op_poll_callsites.contains(meta)
+    }
+
     fn is_id_spawned<S>(&self, id: &span::Id, cx: &Context<'_, S>) -> bool
     where
         S: Subscriber + for<'a> LookupSpan<'a>,
@@ -408,6 +628,50 @@ impl ConsoleLayer {
             .unwrap_or(false)
     }
 
+    /// Returns `true` if resources like the one being created should be
+    /// tracked.
+    ///
+    /// Once the filter has excluded a resource, the callsite which created it
+    /// is excluded too, since its resources will all have the same kind and
+    /// type, and the layer's filter disables it. Resources created by the
+    /// [`resource`] module all share a callsite, though, so they are filtered
+    /// one at a time.
+    fn tracks_resource(
+        &self,
+        metadata: &'static Metadata<'static>,
+        kind: &proto::resources::resource::Kind,
+        concrete_type: &str,
+    ) -> bool {
+        if self.filters.resources.is_none() {
+            return true;
+        }
+        let kind = aggregator::resource_kind(kind);
+        let tracked = self.filters.tracks_resource(&filter::Resource {
+            target: metadata.target(),
+            kind: &kind,
+            concrete_type,
+        });
+        if !tracked && metadata.target() != resource::TARGET {
+            self.excluded_callsites.insert(metadata);
+        }
+        tracked
+    }
+
+    /// Returns the per-layer filter which enables the spans and events this
+    /// layer records, and disables everything else for it alone.
+    ///
+    /// [`Builder::spawn`] and [`init`] add this filter to the layer. A layer
+    /// made by [`Builder::build`] should be added with it too; see there for
+    /// an example.
+    pub fn filter(&self) -> filter::ConsoleFilter {
+        filter::ConsoleFilter {
+            filters: self.filters.clone(),
+            excluded_callsites: self.excluded_callsites.clone(),
+            track_user_spans: self.track_user_spans,
+            shared: self.shared.clone(),
+        }
+    }
+
     fn is_id_tracked<S>(&self, id: &span::Id, cx: &Context<'_, S>) -> bool
     where
         S: Subscriber + for<'a> LookupSpan<'a>,
@@ -430,36 +694,153 @@ impl ConsoleLayer {
             .cloned()
     }
 
-    fn send(&self, dropped: &AtomicUsize, event: Event) -> bool {
-        use mpsc::error::TrySendError;
+    /// Starts tracking a task which was spawned while the layer was dormant.
+    ///
+    /// The task's fields and location weren't recorded when it was spawned,
+    /// so it is reported without them, and the task filter is applied as if
+    /// it had neither a name nor a location.
+    ///
+    /// Returns `true` if `span` is such a task, and the event reporting its
+    /// spawn was sent.
+    fn adopt_dormant_task<S>(&self, span: &SpanRef<'_, S>) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut extensions = span.extensions_mut();
+        let DormantTask { at } = match extensions.remove::<DormantTask>() {
+            Some(task) => task,
+            None => return false,
+        };
+        let metadata = span.metadata();
+        if !self.filters.tracks_task(metadata.target(), &[], None) {
+            return false;
+        }
+        let sent = self.send(
+            &self.shared.dropped_tasks,
+            Event::Spawn {
+                id: span.id(),
+                metadata,
+                at,
+                fields: Vec::new(),
+                location: None,
+            },
+        );
+        if sent {
+            extensions.insert(Tracked {});
+        }
+        sent
+    }
 
+    fn send(&self, dropped: &AtomicUsize, event: Event) -> bool {
         // Return whether or not we actually sent the event.
-        let sent = match self.tx.try_reserve() {
-            Ok(permit) => {
-                permit.send(event);
-                true
-            }
-            Err(TrySendError::Closed(_)) => {
-                // we should warn here eventually, but nop for now because we
-                // can't trigger tracing events...
+        match self.try_send(dropped, event) {
+            Ok(()) => true,
+            Err(event) => {
+                self.mark_lost(event);
                 false
             }
-            Err(TrySendError::Full(_)) => {
+        }
+    }
+
+    /// Sends an event belonging to the tracked span `span`.
+    ///
+    /// If the event is dropped and the overflow policy is
+    /// [`OverflowPolicy::DropTask`], the layer stops tracking the span if it is
+    /// a task, as long as the aggregator will find out why.
+    fn send_for<S>(&self, span: &SpanRef<'_, S>, event: Event) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        match self.try_send(&self.shared.dropped_tasks, event) {
+            Ok(()) => true,
+            Err(event) => {
+                if self.mark_lost(event)
+                    && self.overflow_policy == OverflowPolicy::DropTask
+                    && self.is_spawn(span.metadata())
+                {
+                    span.extensions_mut().remove::<Tracked>();
+                }
+                false
+            }
+        }
+    }
+
+    /// Records an event, or returns it if it was dropped.
+    #[allow(clippy::result_large_err)]
+    fn try_send(&self, dropped: &AtomicUsize, event: Event) -> Result<(), Event> {
+        let capacity = self.events.capacity();
+        let limit = match (self.overflow_policy, &event) {
+            (
+                OverflowPolicy::PrioritizeLifecycle,
+                Event::Waker { .. } | Event::PollOp { .. } | Event::StateUpdate { .. },
+            ) => capacity - capacity / 4,
+            _ => capacity,
+        };
+        let mut result = self.events.push(event, limit);
+        if let OverflowPolicy::Block(timeout) = self.overflow_policy {
+            // The aggregator can't make room while its own thread is waiting,
+            // or if it shares a runtime's threads with the waiting thread.
+            if result.is_err() && self.shared.can_wait_for_aggregator() {
+                let deadline = Instant::now() + timeout;
+                while let Err(event) = result {
+                    if Instant::now() >= deadline {
+                        result = Err(event);
+                        break;
+                    }
+                    self.shared.flush.trigger();
+                    thread::sleep(Self::BLOCK_INTERVAL);
+                    result = self.events.push(event, limit);
+                }
+            }
+        }
+
+        match result {
+            Ok(buffered) => {
+                if buffered >= self.flush_at_len {
+                    self.shared.flush.trigger();
+                }
+                Ok(())
+            }
+            Err(event) => {
                 // this shouldn't happen, since we trigger a flush when
                 // approaching the high water line...but if the executor wait
                 // time is very high, maybe the aggregator task hasn't been
                 // polled yet. so... eek?!
                 dropped.fetch_add(1, Ordering::Release);
-                false
+                self.shared.flush.trigger();
+                Err(event)
             }
-        };
-
-        let capacity = self.tx.capacity();
-        if capacity <= self.flush_under_capacity {
-            self.shared.flush.trigger();
         }
+    }
 
-        sent
+    /// Marks the task, resource, or async op which a dropped event belonged
+    /// to as having lost events.
+    ///
+    /// Returns `false` if there was no room to record the marker.
+    fn mark_lost(&self, event: Event) -> bool {
+        let (id, closed) = match event {
+            Event::Enter { id, .. } | Event::Exit { id, .. } | Event::Waker { id, .. } => {
+                (id, false)
+            }
+            Event::Close { id, .. } => (id, true),
+            Event::PollOp { resource_id, .. } => (resource_id, false),
+            Event::StateUpdate { update_id, .. } => (update_id, false),
+            // If the event creating an entity is dropped, the entity is never
+            // tracked at all.
+            _ => return false,
+        };
+        let mut lost_events = self.shared.lost_events.lock();
+        // Don't let the markers grow without bound if the aggregator has
+        // stopped draining them.
+        if lost_events.len() as u64 >= self.events.capacity() && !lost_events.contains_key(&id) {
+            return false;
+        }
+        let lost = lost_events.entry(id).or_insert(LostEvents {
+            at: Instant::now(),
+            closed: false,
+        });
+        lost.closed |= closed;
+        true
     }
 }
 
@@ -468,9 +849,15 @@ where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
     fn register_callsite(&self, meta: &'static Metadata<'static>) -> subscriber::Interest {
+        // Callsites are only disabled by the layer's filter, since the
+        // `Interest` returned here would disable them for every layer.
         let dropped = match (meta.name(), meta.target()) {
-            ("runtime.spawn", _) | ("task", "tokio::task") => {
-                self.spawn_callsites.insert(meta);
+            _ if is_spawn_span(meta) => {
+                // Tasks from excluded targets are ignored, in case the layer
+                // isn't filtered.
+                if self.filters.tracks_task_target(meta.target()) {
+                    self.spawn_callsites.insert(meta);
+                }
                 &self.shared.dropped_tasks
             }
             (_, "runtime::waker") | (_, "tokio::task::waker") => {
@@ -504,28 +891,49 @@ where
             (_, _) => &self.shared.dropped_tasks,
         };
 
-        self.send(dropped, Event::Metadata(meta));
+        // While dormant, nothing is sent. Every callsite is registered again
+        // when the layer is enabled, and its metadata is sent then.
+        if !self.shared.is_dormant() {
+            self.send(dropped, Event::Metadata(meta));
+        }
         subscriber::Interest::always()
     }
 
     fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
         let metadata = attrs.metadata();
+        if self.shared.is_dormant() {
+            // Only tasks are noted while dormant, so that the ones which are
+            // still around once the layer is enabled can be reported.
+            if self.is_spawn(metadata) {
+                if let Some(span) = ctx.span(id) {
+                    span.extensions_mut()
+                        .insert(DormantTask { at: Instant::now() });
+                }
+            }
+            return;
+        }
+
         let sent = if self.is_spawn(metadata) {
-            let at = SystemTime::now();
+            let at = Instant::now();
             let mut task_visitor = TaskVisitor::new(metadata.into());
             attrs.record(&mut task_visitor);
             let (fields, location) = task_visitor.result();
-            self.send(
-                &self.shared.dropped_tasks,
-                Event::Spawn {
-                    id: id.clone(),
-                    at,
-                    metadata,
-                    fields,
-                    location,
-                },
-            )
-        } else if self.is_resource(metadata) {
+            self.filters
+                .tracks_task(metadata.target(), &fields, location.as_ref())
+                && self.send(
+                    &self.shared.dropped_tasks,
+                    Event::Spawn {
+                        id: id.clone(),
+                        at,
+                        metadata,
+                        fields,
+                        location,
+                    },
+                )
+        } else if self.is_resource(metadata)
+            && self.shared.tracks_resources()
+            && !self.excluded_callsites.contains(metadata)
+        {
             let mut resource_visitor = ResourceVisitor::default();
             attrs.record(&mut resource_visitor);
             if let Some(result) = resource_visitor.result() {
@@ -536,7 +944,10 @@ where
                     is_internal,
                     inherit_child_attrs,
                 } = result;
-                let at = SystemTime::now();
+                if !self.tracks_resource(metadata, &kind, &concrete_type) {
+                    return;
+                }
+                let at = Instant::now();
                 let parent_id = self.current_spans.get().and_then(|stack| {
                     self.first_entered(&stack.borrow(), |id| self.is_id_resource(id, &ctx))
                 });
@@ -558,14 +969,19 @@ where
                 // else unknown resource span format
                 false
             }
-        } else if self.is_async_op(metadata) {
+        } else if self.is_async_op(metadata) && self.shared.tracks_async_ops() {
             let mut async_op_visitor = AsyncOpVisitor::default();
             attrs.record(&mut async_op_visitor);
             if let Some((source, inherit_child_attrs)) = async_op_visitor.result() {
-                let at = SystemTime::now();
-                let resource_id = self.current_spans.get().and_then(|stack| {
-                    self.first_entered(&stack.borrow(), |id| self.is_id_resource(id, &ctx))
-                });
+                let at = Instant::now();
+                // Async ops on resources which aren't tracked aren't either.
+                let resource_id = self
+                    .current_spans
+                    .get()
+                    .and_then(|stack| {
+                        self.first_entered(&stack.borrow(), |id| self.is_id_resource(id, &ctx))
+                    })
+                    .filter(|id| self.is_id_tracked(id, &ctx));
 
                 let parent_id = self.current_spans.get().and_then(|stack| {
                     self.first_entered(&stack.borrow(), |id| self.is_id_async_op(id, &ctx))
@@ -611,32 +1027,60 @@ where
     }
 
     fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        if self.shared.is_dormant() {
+            return;
+        }
+
         let metadata = event.metadata();
         if self.waker_callsites.contains(metadata) {
-            let at = SystemTime::now();
+            let at = Instant::now();
             let mut visitor = WakerVisitor::default();
             event.record(&mut visitor);
             if let Some((id, mut op)) = visitor.result() {
+                // If some tasks aren't tracked, don't send their waker ops.
+                if self.filters.filters_tasks() && !self.is_id_tracked(&id, &ctx) {
+                    return;
+                }
+                let mut woken_by = None;
                 if op.is_wake() {
-                    // Are we currently inside the task's span? If so, the task
-                    // has woken itself.
-                    let self_wake = self
-                        .current_spans
-                        .get()
-                        .map(|spans| spans.borrow().iter().any(|span| span == &id))
-                        .unwrap_or(false);
-                    op = op.self_wake(self_wake);
+                    if let Some(spans) = self.current_spans.get() {
+                        let spans = spans.borrow();
+                        // Are we currently inside the task's span? If so, the
+                        // task has woken itself.
+                        let self_wake = spans.iter().any(|span| span == &id);
+                        op = op.self_wake(self_wake);
+                        // Otherwise, record which task (if any) woke it.
+                        if !self_wake {
+                            woken_by = spans.current_task().cloned();
+                        }
+                    }
                 }
-                self.send(&self.shared.dropped_tasks, Event::Waker { id, op, at });
+                let event = Event::Waker {
+                    id: id.clone(),
+                    op,
+                    at,
+                    woken_by,
+                };
+                match ctx.span(&id) {
+                    Some(span) => self.send_for(&span, event),
+                    None => self.send(&self.shared.dropped_tasks, event),
+                };
             }
             // else unknown waker event... what to do? can't trace it from here...
             return;
         }
 
         if self.poll_op_callsites.contains(metadata) {
-            let resource_id = self.current_spans.get().and_then(|stack| {
-                self.first_entered(&stack.borrow(), |id| self.is_id_resource(id, &ctx))
-            });
+            if !self.shared.tracks_async_ops() {
+                return;
+            }
+            let resource_id = self
+                .current_spans
+                .get()
+                .and_then(|stack| {
+                    self.first_entered(&stack.borrow(), |id| self.is_id_resource(id, &ctx))
+                })
+                .filter(|id| self.is_id_tracked(id, &ctx));
             // poll op event should have a resource span parent
             if let Some(resource_id) = resource_id {
                 let mut poll_op_visitor = PollOpVisitor::default();
@@ -671,6 +1115,9 @@ where
         }
 
         if self.resource_state_update_callsites.contains(metadata) {
+            if !self.shared.tracks_resources() {
+                return;
+            }
             // state update event should have a resource span parent
             let resource_id = self.current_spans.get().and_then(|stack| {
                 self.first_entered(&stack.borrow(), |id| self.is_id_resource(id, &ctx))
@@ -695,6 +1142,9 @@ where
         }
 
         if self.async_op_state_update_callsites.contains(metadata) {
+            if !self.shared.tracks_async_ops() {
+                return;
+            }
             let async_op_id = self.current_spans.get().and_then(|stack| {
                 self.first_entered(&stack.borrow(), |id| self.is_id_async_op(id, &ctx))
             });
@@ -717,15 +1167,33 @@ where
     }
 
     fn on_enter(&self, id: &span::Id, cx: Context<'_, S>) {
-        if !self.is_id_tracked(id, &cx) {
+        if self.shared.is_dormant() {
             return;
         }
+
+        let span = match cx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let metadata = span.metadata();
+        if span.extensions().get::<Tracked>().is_none() && !self.adopt_dormant_task(&span) {
+            // User spans are not reported to the aggregator, but they are
+            // tracked so that tasks can report where they were last polled.
+            if self.is_user_span(metadata) {
+                self.current_spans
+                    .get_or_default()
+                    .borrow_mut()
+                    .push(id.clone(), SpanKind::User(metadata));
+            }
+            return;
+        }
+
         let _default = dispatcher::set_default(&self.no_dispatch);
-        let parent_id = cx.span(id).and_then(|s| s.parent().map(|p| p.id()));
-        let sent = self.send(
-            &self.shared.dropped_tasks,
+        let parent_id = span.parent().map(|p| p.id());
+        let sent = self.send_for(
+            &span,
             Event::Enter {
-                at: SystemTime::now(),
+                at: Instant::now(),
                 id: id.clone(),
                 parent_id,
             },
@@ -734,42 +1202,80 @@ where
         // if we were able to record the send successfully, track entering the
         // span. if not, ignore the enter, to avoid inconsistent data.
         if sent {
+            let kind = if self.is_spawn(metadata) {
+                SpanKind::Task
+            } else {
+                SpanKind::Tracked
+            };
             self.current_spans
                 .get_or_default()
                 .borrow_mut()
-                .push(id.clone());
+                .push(id.clone(), kind);
         }
     }
 
     fn on_exit(&self, id: &span::Id, cx: Context<'_, S>) {
-        if !self.is_id_tracked(id, &cx) {
+        // Nothing is entered while dormant, and the layer cannot become dormant
+        // again once it has been enabled, so there is nothing to exit either.
+        if self.shared.is_dormant() {
             return;
         }
 
-        let _default = dispatcher::set_default(&self.no_dispatch);
-        if let Some(spans) = self.current_spans.get() {
-            if !spans.borrow_mut().pop(id) {
-                // we did not actually pop the span --- entering it may not have
-                // been successfully recorded. in this case, ignore the exit,
-                // since the aggregator was never informed of the entry.
-                return;
+        let span = match cx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        if span.extensions().get::<Tracked>().is_none() {
+            if let Some(spans) = self.current_spans.get() {
+                spans.borrow_mut().pop(id);
             }
+            return;
+        }
+
+        let _default = dispatcher::set_default(&self.no_dispatch);
+        // If nothing was ever entered on this thread, entering the span can't
+        // have been recorded either.
+        let mut spans = match self.current_spans.get() {
+            Some(spans) => spans.borrow_mut(),
+            None => return,
+        };
+        if !spans.pop(id) {
+            // we did not actually pop the span --- entering it may not have
+            // been successfully recorded. in this case, ignore the exit,
+            // since the aggregator was never informed of the entry.
+            return;
         }
 
-        let parent_id = cx.span(id).and_then(|s| s.parent().map(|p| p.id()));
+        let (async_backtrace, span_busy_times) = if self.is_spawn(span.metadata()) {
+            (spans.take_backtrace(), spans.take_busy_times())
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        drop(spans);
+
+        let parent_id = span.parent().map(|p| p.id());
 
-        self.send(
-            &self.shared.dropped_tasks,
+        self.send_for(
+            &span,
             Event::Exit {
                 id: id.clone(),
                 parent_id,
-                at: SystemTime::now(),
+                at: Instant::now(),
+                async_backtrace,
+                span_busy_times,
             },
         );
     }
 
     fn on_close(&self, id: span::Id, cx: Context<'_, S>) {
+        if self.shared.is_dormant() {
+            return;
+        }
+
         if !self.is_id_tracked(&id, &cx) {
+            if let Some(spans) = self.current_spans.get() {
+                spans.borrow_mut().close(&id);
+            }
             return;
         }
 
@@ -777,19 +1283,58 @@ where
         self.send(
             &self.shared.dropped_tasks,
             Event::Close {
-                at: SystemTime::now(),
+                at: Instant::now(),
                 id,
             },
         );
     }
 }
 
+impl Shared {
+    fn is_dormant(&self) -> bool {
+        self.dormant.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the current thread may wait for the aggregator to make
+    /// room in the event buffer.
+    fn can_wait_for_aggregator(&self) -> bool {
+        let aggregator_thread = *self.aggregator_thread.lock();
+        aggregator_thread.map_or(false, |aggregator| aggregator != thread::current().id())
+    }
+
+    /// Returns whether the current thread is the aggregator's own thread.
+    fn is_aggregator_thread(&self) -> bool {
+        *self.aggregator_thread.lock() == Some(thread::current().id())
+    }
+
+    /// Enables the layer if it is dormant, returning whether it was.
+    fn enable(&self) -> bool {
+        let was_dormant = self.dormant.swap(false, Ordering::Relaxed);
+        if was_dormant {
+            // The layer's filter only checks whether it is dormant for
+            // callsites registered while it was, and the layer sends their
+            // metadata when they're registered again.
+            tracing_core::callsite::rebuild_interest_cache();
+            tracing::info!("console layer enabled");
+        }
+        was_dormant
+    }
+
+    fn tracks_resources(&self) -> bool {
+        !self.resources_disabled.load(Ordering::Relaxed)
+    }
+
+    /// Async ops belong to resources, so they are only tracked while resources
+    /// are tracked as well.
+    fn tracks_async_ops(&self) -> bool {
+        self.tracks_resources() && !self.async_ops_disabled.load(Ordering::Relaxed)
+    }
+}
+
 impl fmt::Debug for ConsoleLayer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ConsoleLayer")
-            // mpsc::Sender debug impl is not very useful
-            .field("tx", &format_args!("<...>"))
-            .field("tx.capacity", &self.tx.capacity())
+            .field("events", &self.events)
             .field("shared", &self.shared)
             .field("spawn_callsites", &self.spawn_callsites)
             .field("waker_callsites", &self.waker_callsites)
@@ -825,6 +1370,27 @@ impl Server {
     /// [environment variable]: `Builder::with_default_env`
     pub const DEFAULT_PORT: u16 = 6669;
 
+    /// Returns a [`ConsoleHandle`] for querying the state recorded by the
+    /// [`ConsoleLayer`] from inside this process.
+    ///
+    /// The handle's methods only complete while this server is running.
+    pub fn handle(&self) -> ConsoleHandle {
+        ConsoleHandle::new(self.subscribe.clone())
+    }
+
+    /// Marks that this server's aggregator will run on a thread of its own,
+    /// on a current-thread runtime, as it does when it is spawned by
+    /// [`Builder::spawn`].
+    pub(crate) fn set_aggregator_owns_thread(&mut self) {
+        if let Some(aggregator) = self.aggregator.as_mut() {
+            aggregator.set_owns_thread();
+        }
+    }
+
+    fn watch_updates_service(&self) -> WatchUpdates {
+        WatchUpdates::new(self.subscribe.clone(), self.client_buffer)
+    }
+
     /// Starts the gRPC service with the default gRPC settings.
     ///
     /// To configure gRPC server settings before starting the server, use
@@ -849,52 +1415,165 @@ impl Server {
     /// prior to starting the server.
     ///
     /// This spawns both the server task and the event aggregation worker
-    /// task on the current async runtime.
+    /// task on the current async runtime. Both run until the server is [shut
+    /// down].
     ///
+    /// If the server is [discoverable], and the operating system chooses its
+    /// port, the server binds its own listener, so that it knows which port
+    /// to write to the discovery file. The `builder`'s TCP settings can't be
+    /// read, so that listener enables [`tcp_nodelay`], as
+    /// [`tonic::transport::Server::builder`] does by default, and doesn't
+    /// enable TCP keepalive. Otherwise, the `builder` binds the listener, with
+    /// its own TCP settings.
+    ///
+    /// [discoverable]: crate::Builder::discoverable
     /// [`tonic`]: https://docs.rs/tonic/
+    /// [shut down]: crate::ConsoleHandle::shutdown
+    /// [`tcp_nodelay`]: tonic::transport::Server::tcp_nodelay
     pub async fn serve_with(
         mut self,
         mut builder: tonic::transport::Server,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+        use hyper::server::accept::Accept;
+
         let aggregate = self
             .aggregator
             .take()
             .expect("cannot start server multiple times");
-        let aggregate = spawn_named(aggregate.run(), "console::aggregate");
-        let addr = self.addr;
-        let serve = builder
-            .add_service(proto::instrument::instrument_server::InstrumentServer::new(
-                self,
-            ))
-            .serve(addr);
+        let discovery_dir = self.discovery_dir.take();
+        let incoming = match discovery_dir {
+            Some(_) if self.addr.port() == 0 => {
+                let mut incoming = hyper::server::conn::AddrIncoming::bind(&self.addr)?;
+                incoming.set_nodelay(true);
+                Some(incoming)
+            }
+            _ => None,
+        };
+        let addr = incoming
+            .as_ref()
+            .map_or(self.addr, |incoming| incoming.local_addr());
+        tracing::debug!(%addr, "serving console API");
+        let discovery_file = discovery_dir.and_then(|dir| match DiscoveryFile::write(&dir, addr) {
+            Ok(file) => {
+                tracing::debug!(path = %file.path().display(), "wrote discovery file");
+                Some(file)
+            }
+            Err(error) => {
+                tracing::warn!(%error, dir = %dir.display(), "failed to write discovery file");
+                None
+            }
+        });
+        // Stop serving once the aggregator has shut down, so that clients see
+        // their streams end, rather than hang.
+        let (stopped_tx, stopped) = oneshot::channel::<()>();
+        let aggregate = spawn_named(
+            async move {
+                aggregate.run().await;
+                drop(stopped_tx);
+            },
+            "console::aggregate",
+        );
+        #[cfg(unix)]
+        let enable_on_signal = self.enable_signal.map(|signal| {
+            spawn_named(
+                enable_on_signal(signal, self.subscribe.clone()),
+                "console::enable_on_signal",
+            )
+        });
+        #[cfg(unix)]
+        let dump_on_signal = self.dump_signal.map(|signal| {
+            spawn_named(
+                dump::dump_on_signal(signal, self.subscribe.clone()),
+                "console::dump_on_signal",
+            )
+        });
+        let prometheus = self.prometheus_addr.map(|addr| {
+            spawn_named(
+                prometheus::serve(addr, self.subscribe.clone()),
+                "console::prometheus",
+            )
+        });
+        let router = builder.add_service(InstrumentService::new(self));
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        let serve = async move {
+            match incoming {
+                Some(mut incoming) => {
+                    let incoming =
+                        futures::stream::poll_fn(move |cx| Pin::new(&mut incoming).poll_accept(cx));
+                    router
+                        .serve_with_incoming_shutdown(incoming, shutdown)
+                        .await
+                }
+                None => router.serve_with_shutdown(addr, shutdown).await,
+            }
+        };
         let res = spawn_named(serve, "console::serve").await;
         aggregate.abort();
+        #[cfg(unix)]
+        if let Some(enable_on_signal) = enable_on_signal {
+            enable_on_signal.abort();
+        }
+        #[cfg(unix)]
+        if let Some(dump_on_signal) = dump_on_signal {
+            dump_on_signal.abort();
+        }
+        if let Some(prometheus) = prometheus {
+            prometheus.abort();
+        }
+        // The server has stopped, so it can no longer be discovered.
+        drop(discovery_file);
         res?.map_err(Into::into)
     }
 }
 
+/// Enables the layer the first time `signal` is received.
+#[cfg(unix)]
+async fn enable_on_signal(
+    signal: tokio::signal::unix::SignalKind,
+    subscribe: mpsc::Sender<Command>,
+) {
+    let mut signals = match tokio::signal::unix::signal(signal) {
+        Ok(signals) => signals,
+        Err(error) => {
+            tracing::warn!(%error, "cannot listen for the signal to enable the console layer");
+            return;
+        }
+    };
+    // The layer stays enabled once it has been enabled, so only the first
+    // signal matters. Later ones are still caught, rather than terminating the
+    // process.
+    if signals.recv().await.is_some() {
+        let (tx, _) = oneshot::channel();
+        let _ = subscribe.send(Command::Enable(tx)).await;
+    }
+}
+
 #[tonic::async_trait]
 impl proto::instrument::instrument_server::Instrument for Server {
-    type WatchUpdatesStream =
-        tokio_stream::wrappers::ReceiverStream<Result<proto::instrument::Update, tonic::Status>>;
+    type WatchUpdatesStream = std::pin::Pin<
+        Box<
+            dyn futures::Stream<Item = Result<proto::instrument::Update, tonic::Status>>
+                + Send
+                + 'static,
+        >,
+    >;
     type WatchTaskDetailsStream =
         tokio_stream::wrappers::ReceiverStream<Result<proto::tasks::TaskDetails, tonic::Status>>;
     async fn watch_updates(
         &self,
         req: tonic::Request<proto::instrument::InstrumentRequest>,
     ) -> Result<tonic::Response<Self::WatchUpdatesStream>, tonic::Status> {
-        match req.remote_addr() {
-            Some(addr) => tracing::debug!(client.addr = %addr, "starting a new watch"),
-            None => tracing::debug!(client.addr = %"<unknown>", "starting a new watch"),
-        }
-        let permit = self.subscribe.reserve().await.map_err(|_| {
-            tonic::Status::internal("cannot start new watch, aggregation task is not running")
-        })?;
-        let (tx, rx) = mpsc::channel(self.client_buffer);
-        permit.send(Command::Instrument(Watch(tx)));
-        tracing::debug!("watch started");
-        let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
-        Ok(tonic::Response::new(stream))
+        use futures::TryStreamExt;
+
+        // When the server is started by `Server::serve_with`, `WatchUpdates`
+        // requests are handled by `InstrumentService`, which sends the encoded
+        // updates as-is. This is only used if the server is served some other
+        // way, so the shared updates must be decoded for each client.
+        let stream = self.watch_updates_service().watch(req).await?;
+        let stream = stream.map_ok(|update| update.decode());
+        Ok(tonic::Response::new(Box::pin(stream)))
     }
 
     async fn watch_task_details(
@@ -927,6 +1606,141 @@ impl proto::instrument::instrument_server::Instrument for Server {
         Ok(tonic::Response::new(stream))
     }
 
+    async fn get_snapshot(
+        &self,
+        req: tonic::Request<proto::instrument::SnapshotRequest>,
+    ) -> Result<tonic::Response<proto::instrument::Update>, tonic::Status> {
+        let (tx, rx) = oneshot::channel();
+        self.subscribe
+            .send(Command::Snapshot(req.into_inner(), tx))
+            .await
+            .map_err(|_| {
+                tonic::Status::internal("cannot take snapshot, aggregation task is not running")
+            })?;
+        let snapshot = rx.await.map_err(|_| {
+            tonic::Status::internal("cannot take snapshot, aggregation task is not running")
+        })?;
+        Ok(tonic::Response::new(snapshot))
+    }
+
+    async fn list_completed_tasks(
+        &self,
+        req: tonic::Request<proto::instrument::ListCompletedTasksRequest>,
+    ) -> Result<tonic::Response<proto::instrument::ListCompletedTasksResponse>, tonic::Status> {
+        let (tx, rx) = oneshot::channel();
+        self.subscribe
+            .send(Command::ListCompletedTasks(req.into_inner(), tx))
+            .await
+            .map_err(|_| {
+                tonic::Status::internal(
+                    "cannot list completed tasks, aggregation task is not running",
+                )
+            })?;
+        let tasks = rx.await.map_err(|_| {
+            tonic::Status::internal("cannot list completed tasks, aggregation task is not running")
+        })?;
+        Ok(tonic::Response::new(tasks))
+    }
+
+    async fn update_settings(
+        &self,
+        req: tonic::Request<proto::instrument::UpdateSettingsRequest>,
+    ) -> Result<tonic::Response<proto::instrument::Settings>, tonic::Status> {
+        let req = req.into_inner();
+        let publish_interval = req
+            .publish_interval
+            .map(Duration::try_from)
+            .transpose()
+            .map_err(|_| {
+                tonic::Status::invalid_argument("publish interval must not be negative")
+            })?;
+        if publish_interval == Some(Duration::from_secs(0)) {
+            return Err(tonic::Status::invalid_argument(
+                "publish interval must be greater than zero",
+            ));
+        }
+        let retention = req
+            .retention
+            .map(Duration::try_from)
+            .transpose()
+            .map_err(|_| tonic::Status::invalid_argument("retention must not be negative"))?;
+        let recording = match (&req.start_recording, req.stop_recording) {
+            (Some(_), true) => {
+                return Err(tonic::Status::invalid_argument(
+                    "cannot both start and stop recording",
+                ))
+            }
+            (Some(path), false) => {
+                let path = PathBuf::from(path);
+                // Recordings may only be written inside of the recording
+                // directory.
+                if path.as_os_str().is_empty()
+                    || !path
+                        .components()
+                        .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    return Err(tonic::Status::invalid_argument(
+                        "recording paths must be relative, and must not contain `.` or `..`",
+                    ));
+                }
+                Some(RecordingChange::Start(path))
+            }
+            (None, true) => Some(RecordingChange::Stop),
+            (None, false) => None,
+        };
+        let update = SettingsUpdate {
+            publish_interval,
+            retention,
+            recording,
+            track_resources: req.track_resources,
+            track_async_ops: req.track_async_ops,
+        };
+
+        let (tx, rx) = oneshot::channel();
+        self.subscribe
+            .send(Command::UpdateSettings(update, tx))
+            .await
+            .map_err(|_| {
+                tonic::Status::internal("cannot update settings, aggregation task is not running")
+            })?;
+        let settings = rx
+            .await
+            .map_err(|_| {
+                tonic::Status::internal("cannot update settings, aggregation task is not running")
+            })?
+            .map_err(|error| {
+                let message = format!(
+                    "cannot record to {}: {}",
+                    req.start_recording.unwrap_or_default(),
+                    error
+                );
+                match error.kind() {
+                    io::ErrorKind::PermissionDenied => tonic::Status::permission_denied(message),
+                    _ => tonic::Status::invalid_argument(message),
+                }
+            })?;
+        Ok(tonic::Response::new(settings))
+    }
+
+    async fn enable(
+        &self,
+        _req: tonic::Request<proto::instrument::EnableRequest>,
+    ) -> Result<tonic::Response<proto::instrument::EnableResponse>, tonic::Status> {
+        let (tx, rx) = oneshot::channel();
+        self.subscribe
+            .send(Command::Enable(tx))
+            .await
+            .map_err(|_| {
+                tonic::Status::internal("cannot enable, aggregation task is not running")
+            })?;
+        let was_dormant = rx.await.map_err(|_| {
+            tonic::Status::internal("cannot enable, aggregation task is not running")
+        })?;
+        Ok(tonic::Response::new(proto::instrument::EnableResponse {
+            was_dormant,
+        }))
+    }
+
     async fn pause(
         &self,
         _req: tonic::Request<proto::instrument::PauseRequest>,
@@ -963,6 +1777,23 @@ impl WakeOp {
     }
 }
 
+/// Returns `true` if `meta` describes the span representing a spawned task.
+pub(crate) fn is_spawn_span(meta: &tracing::Metadata<'_>) -> bool {
+    matches!(
+        (meta.name(), meta.target()),
+        ("runtime.spawn", _) | ("task", "tokio::task")
+    )
+}
+
+/// Returns `true` if `meta` describes a span or event emitted by an async
+/// runtime's instrumentation.
+pub(crate) fn is_runtime_span(meta: &tracing::Metadata<'_>) -> bool {
+    // spans will have *names* beginning with "runtime". for backwards
+    // compatibility with older Tokio versions, anything with the `tokio` target
+    // is considered runtime instrumentation as well.
+    meta.name().starts_with("runtime.") || meta.target().starts_with("tokio")
+}
+
 #[track_caller]
 pub(crate) fn spawn_named<T>(
     task: impl std::future::Future<Output = T> + Send + 'static,
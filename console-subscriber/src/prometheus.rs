@@ -0,0 +1,63 @@
+//! An HTTP endpoint serving metrics in the Prometheus text exposition format.
+use crate::Command;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use std::{convert::Infallible, net::SocketAddr};
+use tokio::sync::{mpsc, oneshot};
+
+/// The content type of the Prometheus text exposition format.
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// Serves the aggregator's metrics at `/metrics` on `addr`.
+pub(crate) async fn serve(addr: SocketAddr, subscribe: mpsc::Sender<Command>) {
+    let make_service = make_service_fn(move |_| {
+        let subscribe = subscribe.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                respond(request, subscribe.clone())
+            }))
+        }
+    });
+    let server = match hyper::Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(error) => {
+            tracing::warn!(%error, %addr, "cannot serve Prometheus metrics");
+            return;
+        }
+    };
+    tracing::debug!(%addr, "serving Prometheus metrics");
+    if let Err(error) = server.await {
+        tracing::warn!(%error, "Prometheus metrics server failed");
+    }
+}
+
+async fn respond(
+    request: Request<Body>,
+    subscribe: mpsc::Sender<Command>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+
+    let (tx, rx) = oneshot::channel();
+    if subscribe.send(Command::Metrics(tx)).await.is_err() {
+        return Ok(status(StatusCode::SERVICE_UNAVAILABLE));
+    }
+    let response = match rx.await {
+        Ok(metrics) => Response::builder()
+            .header(CONTENT_TYPE, TEXT_FORMAT)
+            .body(Body::from(metrics))
+            .expect("a response with a valid header is valid"),
+        Err(_) => status(StatusCode::SERVICE_UNAVAILABLE),
+    };
+    Ok(response)
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
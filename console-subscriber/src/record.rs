@@ -3,13 +3,15 @@ use serde::{
     Serialize,
 };
 use std::{
+    ffi::OsString,
     fs::File,
     io,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
     time::SystemTime,
 };
 
+use crate::clock::Anchor;
 use console_api as proto;
 
 /// This marks the currently understood version of the recording format. This
@@ -23,7 +25,14 @@ const DATA_FORMAT_VERSION: u8 = 1;
 pub(crate) struct Recorder {
     buf: Arc<Mutex<RecordBuf>>,
 
-    worker: std::thread::JoinHandle<()>,
+    /// The path of the file being recorded to.
+    path: PathBuf,
+
+    /// Converts the monotonic timestamps of events into wall-clock times.
+    anchor: Anchor,
+
+    /// The IO thread. This is only `None` once the recorder is being dropped.
+    worker: Option<std::thread::JoinHandle<()>>,
 }
 
 struct Io {
@@ -38,6 +47,9 @@ struct RecordBuf {
     /// current buffer. After flushing, the IO thread will put the buffer
     /// back in this slot, so the allocation can be reused.
     next: Vec<u8>,
+    /// Set when the recorder is dropped, so that the IO thread writes out any
+    /// remaining bytes and exits.
+    closed: bool,
 }
 
 #[derive(Serialize)]
@@ -76,7 +88,7 @@ struct SerializeFields<'a>(&'a [proto::Field]);
 struct SerializeField<'a>(&'a proto::Field);
 
 impl Recorder {
-    pub(crate) fn new(path: &Path) -> io::Result<Self> {
+    pub(crate) fn new(path: &Path, anchor: Anchor) -> io::Result<Self> {
         let buf = Arc::new(Mutex::new(RecordBuf::new()));
         let buf2 = buf.clone();
         let file = std::fs::File::create(path)?;
@@ -87,7 +99,12 @@ impl Recorder {
                 record_io(Io { buf: buf2, file });
             })?;
 
-        let recorder = Recorder { buf, worker };
+        let recorder = Recorder {
+            buf,
+            path: path.to_owned(),
+            anchor,
+            worker: Some(worker),
+        };
 
         recorder.write(&Header {
             v: DATA_FORMAT_VERSION,
@@ -96,28 +113,60 @@ impl Recorder {
         Ok(recorder)
     }
 
+    /// Starts recording to `path`, replacing any file there only once the
+    /// new recording has been started.
+    ///
+    /// The recording is started in a hidden file next to `path`, which is
+    /// then renamed, so that a recording which is already being made to
+    /// `path` is kept if the new one can't be started.
+    pub(crate) fn replacing(path: &Path, anchor: Anchor) -> io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "recording path has no file name",
+            )
+        })?;
+        let mut partial_name = OsString::from(".");
+        partial_name.push(name);
+        partial_name.push(".partial");
+        let partial = path.with_file_name(partial_name);
+
+        let mut recorder = Self::new(&partial, anchor)?;
+        if let Err(error) = std::fs::rename(&partial, path) {
+            drop(recorder);
+            let _ = std::fs::remove_file(&partial);
+            return Err(error);
+        }
+        recorder.path = path.to_owned();
+        Ok(recorder)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub(crate) fn record(&self, event: &crate::Event) {
         let event = match event {
             crate::Event::Spawn { id, at, fields, .. } => Event::Spawn {
                 id: id.into_u64(),
-                at: *at,
+                at: self.anchor.to_system_time(*at),
                 fields: SerializeFields(fields),
             },
             crate::Event::Enter { id, at, .. } => Event::Enter {
                 id: id.into_u64(),
-                at: *at,
+                at: self.anchor.to_system_time(*at),
             },
             crate::Event::Exit { id, at, .. } => Event::Exit {
                 id: id.into_u64(),
-                at: *at,
+                at: self.anchor.to_system_time(*at),
             },
             crate::Event::Close { id, at } => Event::Close {
                 id: id.into_u64(),
-                at: *at,
+                at: self.anchor.to_system_time(*at),
             },
-            crate::Event::Waker { id, op, at } => Event::Waker {
+            crate::Event::Waker { id, op, at, .. } => Event::Waker {
                 id: id.into_u64(),
-                at: *at,
+                at: self.anchor.to_system_time(*at),
                 op: *op,
             },
             _ => return,
@@ -131,7 +180,25 @@ impl Recorder {
         serde_json::to_writer(&mut buf.bytes, val).expect("json");
         buf.bytes.push(b'\n');
         drop(buf);
-        self.worker.thread().unpark();
+        self.unpark_worker();
+    }
+
+    fn unpark_worker(&self) {
+        if let Some(worker) = &self.worker {
+            worker.thread().unpark();
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.buf.lock().unwrap().closed = true;
+        self.unpark_worker();
+        // Wait for the IO thread to write out everything recorded so far, so
+        // that the recording is complete once the recorder is gone.
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
     }
 }
 
@@ -140,6 +207,7 @@ impl RecordBuf {
         Self {
             bytes: Vec::new(),
             next: Vec::new(),
+            closed: false,
         }
     }
 
@@ -166,7 +234,10 @@ fn record_io(mut dst: Io) {
         // Only lock the mutex to take the bytes out. The file write could
         // take a relatively long time, and we don't want to be blocking
         // the serialization end holding this lock.
-        let bytes = dst.buf.lock().unwrap().take();
+        let (bytes, closed) = {
+            let mut buf = dst.buf.lock().unwrap();
+            (buf.take(), buf.closed)
+        };
         match dst.file.write_all(&bytes) {
             Ok(()) => {
                 dst.buf.lock().unwrap().put(bytes);
@@ -175,6 +246,11 @@ fn record_io(mut dst: Io) {
                 // TODO: what to do if file error?
             }
         }
+
+        if closed {
+            let _ = dst.file.flush();
+            return;
+        }
     }
 }
 
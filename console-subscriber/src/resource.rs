@@ -0,0 +1,429 @@
+//! Instrumentation for resources implemented outside of Tokio, such as
+//! connection pools and rate limiters, so that the console shows them
+//! alongside Tokio's own resources.
+//!
+//! Tokio describes its resources with spans and events named according to
+//! conventions that the [`ConsoleLayer`] recognizes. The handles in this
+//! module record the same spans and events for resources of your own:
+//!
+//! * [`Resource::builder`] describes a new resource. The resource exists until
+//!   its [`Resource`] handle, and every [`AsyncOp`] on it, is dropped.
+//! * [`Resource::update`] and [`AsyncOp::update`] change an attribute of a
+//!   resource or an async op, such as the number of connections in a pool.
+//! * [`Resource::async_op`] starts an [`AsyncOp`], such as waiting to acquire
+//!   a connection, which is polled by a task.
+//! * [`AsyncOp::enter`] marks that the async op is being polled, and
+//!   [`Polling::poll_op`] records the result of polling it.
+//!
+//! Everything is recorded at the `TRACE` level. The spans describing
+//! resources and async ops have the target `console_subscriber::resource`,
+//! while attribute updates and poll ops are recorded as events with the
+//! targets which Tokio uses for its own: `runtime::resource::state_update`,
+//! `runtime::resource::async_op::state_update`, and
+//! `runtime::resource::poll_op`.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use console_subscriber::resource::{Resource, Update};
+//!
+//! let report = console_subscriber::testing::run(async {
+//!     let pool = Resource::builder("ConnectionPool", "pool").build();
+//!     pool.update("max_connections", Update::set(16u64));
+//!     pool.update("connections", Update::add(1u64).unit("conns"));
+//!
+//!     tokio::spawn(async move {
+//!         let acquire = pool.async_op("ConnectionPool::acquire");
+//!         let polling = acquire.enter();
+//!         polling.poll_op("poll_acquire", true);
+//!     })
+//!     .await
+//!     .unwrap();
+//! });
+//!
+//! let snapshot = report.snapshot();
+//! let pool = snapshot
+//!     .resources
+//!     .iter()
+//!     .find(|resource| resource.concrete_type == "ConnectionPool")
+//!     .unwrap();
+//! let connections = pool
+//!     .attributes
+//!     .iter()
+//!     .find(|attribute| attribute.name == "connections")
+//!     .unwrap();
+//! assert_eq!(connections.value, "1");
+//! assert_eq!(connections.unit.as_deref(), Some("conns"));
+//!
+//! let acquire = snapshot
+//!     .async_ops
+//!     .iter()
+//!     .find(|async_op| async_op.source == "ConnectionPool::acquire")
+//!     .unwrap();
+//! assert_eq!(acquire.resource_id, pool.id);
+//! ```
+//!
+//! [`ConsoleLayer`]: crate::ConsoleLayer
+use std::panic::Location;
+use tracing::{span::Entered, Span};
+
+/// The target of the spans recorded by this module.
+pub(crate) const TARGET: &str = module_path!();
+
+/// A handle to a resource, such as a connection pool or a rate limiter.
+///
+/// The resource is dropped, as far as the console is concerned, when every
+/// clone of this handle, and every [`AsyncOp`] on the resource, is dropped.
+#[derive(Clone, Debug)]
+pub struct Resource {
+    span: Span,
+}
+
+/// Describes a new [`Resource`].
+///
+/// This is returned by [`Resource::builder`].
+#[derive(Debug)]
+#[must_use = "a resource is only created by `ResourceBuilder::build`"]
+pub struct ResourceBuilder<'a> {
+    concrete_type: &'a str,
+    kind: &'a str,
+    is_internal: bool,
+    inherit_child_attrs: bool,
+    parent: Option<&'a Resource>,
+    location: &'static Location<'static>,
+}
+
+/// A handle to an asynchronous operation on a [`Resource`], such as waiting to
+/// acquire a connection from a pool.
+///
+/// The async op is dropped, as far as the console is concerned, when every
+/// clone of this handle is dropped.
+#[derive(Clone, Debug)]
+pub struct AsyncOp {
+    resource: Span,
+    span: Span,
+    poll: Span,
+}
+
+/// A guard which marks that an [`AsyncOp`] is being polled, until it is
+/// dropped.
+///
+/// This is returned by [`AsyncOp::enter`].
+#[derive(Debug)]
+#[must_use = "the async op is only marked as being polled until the guard is dropped"]
+pub struct Polling<'a> {
+    // These are dropped in the reverse order to the one they were entered in.
+    _poll: Entered<'a>,
+    _async_op: Entered<'a>,
+    _resource: Entered<'a>,
+}
+
+/// An update to an attribute of a [`Resource`] or an [`AsyncOp`].
+///
+/// Only numeric attributes can be added to or subtracted from. Updating any
+/// other attribute sets it to the update's value.
+#[derive(Clone, Debug)]
+pub struct Update {
+    value: Value,
+    op: Op,
+    unit: Option<String>,
+}
+
+/// The value of an attribute.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Value {
+    /// A boolean value.
+    Bool(bool),
+    /// An unsigned integer.
+    U64(u64),
+    /// A signed integer.
+    I64(i64),
+    /// A string.
+    Str(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Add,
+    Sub,
+    Override,
+}
+
+/// Records an update to an attribute, as an event with the given target.
+///
+/// Since the attribute's name isn't known when this is compiled, it's recorded
+/// in an `attribute.name` field, rather than as the name of the value's field.
+macro_rules! record_update {
+    ($target:tt, $name:expr, $update:expr) => {{
+        let update: &Update = $update;
+        let name: &str = $name;
+        let op = update.op.as_str();
+        let unit = update.unit.as_deref();
+        match update.value {
+            Value::Bool(value) => tracing::trace!(
+                target: $target,
+                value,
+                attribute.name = name,
+                value.op = op,
+                value.unit = unit,
+            ),
+            Value::U64(value) => tracing::trace!(
+                target: $target,
+                value,
+                attribute.name = name,
+                value.op = op,
+                value.unit = unit,
+            ),
+            Value::I64(value) => tracing::trace!(
+                target: $target,
+                value,
+                attribute.name = name,
+                value.op = op,
+                value.unit = unit,
+            ),
+            Value::Str(ref value) => tracing::trace!(
+                target: $target,
+                value = value.as_str(),
+                attribute.name = name,
+                value.op = op,
+                value.unit = unit,
+            ),
+        }
+    }};
+}
+
+// === impl Resource ===
+
+impl Resource {
+    /// Returns a [`ResourceBuilder`] describing a new resource of the Rust
+    /// type `concrete_type`, such as `"ConnectionPool"`.
+    ///
+    /// The `kind` of resource is shown in the console, and may be anything,
+    /// such as `"pool"` or `"sync"`. The kind `"timer"` is treated as a timer,
+    /// like Tokio's own timers.
+    ///
+    /// The resource's location is where this is called from.
+    #[track_caller]
+    pub fn builder<'a>(concrete_type: &'a str, kind: &'a str) -> ResourceBuilder<'a> {
+        ResourceBuilder {
+            concrete_type,
+            kind,
+            is_internal: false,
+            inherit_child_attrs: false,
+            parent: None,
+            location: Location::caller(),
+        }
+    }
+
+    /// Updates the attribute called `name`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use console_subscriber::resource::{Resource, Update};
+    ///
+    /// let limiter = Resource::builder("RateLimiter", "sync").build();
+    /// limiter.update("rate", Update::set(100u64).unit("req/s"));
+    /// limiter.update("tokens", Update::set(100u64));
+    /// limiter.update("tokens", Update::sub(1u64));
+    /// limiter.update("closed", Update::set(false));
+    /// ```
+    pub fn update(&self, name: &str, update: Update) {
+        self.span.in_scope(|| {
+            record_update!("runtime::resource::state_update", name, &update);
+        });
+    }
+
+    /// Starts an asynchronous operation on this resource, such as waiting to
+    /// acquire a connection from a pool.
+    ///
+    /// The `source` names the operation, such as `"ConnectionPool::acquire"`.
+    pub fn async_op(&self, source: &str) -> AsyncOp {
+        let span = self
+            .span
+            .in_scope(|| tracing::trace_span!("runtime.resource.async_op", source));
+        let poll = span.in_scope(|| tracing::trace_span!("runtime.resource.async_op.poll"));
+        AsyncOp {
+            resource: self.span.clone(),
+            span,
+            poll,
+        }
+    }
+}
+
+// === impl ResourceBuilder ===
+
+impl<'a> ResourceBuilder<'a> {
+    /// Sets whether the resource is part of a library's implementation,
+    /// rather than used directly by the application.
+    ///
+    /// By default, resources are not internal.
+    pub fn internal(self, is_internal: bool) -> Self {
+        Self {
+            is_internal,
+            ..self
+        }
+    }
+
+    /// Sets the resource which the new resource is part of, such as the pool
+    /// a connection belongs to.
+    pub fn parent(self, parent: &'a Resource) -> Self {
+        Self {
+            parent: Some(parent),
+            ..self
+        }
+    }
+
+    /// Sets whether updates to the attributes of resources which are part of
+    /// this resource also update this resource's attributes.
+    ///
+    /// By default, they don't.
+    pub fn inherit_child_attrs(self, inherit_child_attrs: bool) -> Self {
+        Self {
+            inherit_child_attrs,
+            ..self
+        }
+    }
+
+    /// Creates the resource.
+    pub fn build(self) -> Resource {
+        let new_span = || {
+            tracing::trace_span!(
+                "runtime.resource",
+                concrete_type = self.concrete_type,
+                kind = self.kind,
+                is_internal = self.is_internal,
+                inherits_child_attrs = self.inherit_child_attrs,
+                loc.file = self.location.file(),
+                loc.line = self.location.line(),
+                loc.col = self.location.column(),
+            )
+        };
+        // The resource's parent is the resource that was entered when it was
+        // created.
+        let span = match self.parent {
+            Some(parent) => parent.span.in_scope(new_span),
+            None => new_span(),
+        };
+        Resource { span }
+    }
+}
+
+// === impl AsyncOp ===
+
+impl AsyncOp {
+    /// Updates the attribute of this async op called `name`.
+    pub fn update(&self, name: &str, update: Update) {
+        self.span.in_scope(|| {
+            record_update!("runtime::resource::async_op::state_update", name, &update);
+        });
+    }
+
+    /// Marks that this async op is being polled, until the returned guard is
+    /// dropped.
+    ///
+    /// This should be called whenever a task polls the async op, and the guard
+    /// shouldn't be held across an `.await`.
+    pub fn enter(&self) -> Polling<'_> {
+        Polling {
+            _resource: self.resource.enter(),
+            _async_op: self.span.enter(),
+            _poll: self.poll.enter(),
+        }
+    }
+}
+
+// === impl Polling ===
+
+impl Polling<'_> {
+    /// Records that polling the operation `op_name`, such as `"poll_acquire"`,
+    /// returned `Poll::Ready` if `is_ready` is `true`, and `Poll::Pending`
+    /// otherwise.
+    ///
+    /// Poll ops are only recorded while a task is being polled.
+    pub fn poll_op(&self, op_name: &str, is_ready: bool) {
+        tracing::trace!(target: "runtime::resource::poll_op", op_name, is_ready);
+    }
+}
+
+// === impl Update ===
+
+impl Update {
+    /// Returns an update which sets the attribute to `value`.
+    pub fn set(value: impl Into<Value>) -> Self {
+        Self::new(value.into(), Op::Override)
+    }
+
+    /// Returns an update which adds `value` to a numeric attribute.
+    pub fn add(value: impl Into<Value>) -> Self {
+        Self::new(value.into(), Op::Add)
+    }
+
+    /// Returns an update which subtracts `value` from a numeric attribute.
+    pub fn sub(value: impl Into<Value>) -> Self {
+        Self::new(value.into(), Op::Sub)
+    }
+
+    /// Sets the unit of the attribute's value, such as `"ms"` or `"bytes"`.
+    pub fn unit(self, unit: impl Into<String>) -> Self {
+        Self {
+            unit: Some(unit.into()),
+            ..self
+        }
+    }
+
+    fn new(value: Value, op: Op) -> Self {
+        Self {
+            value,
+            op,
+            unit: None,
+        }
+    }
+}
+
+// === impl Value ===
+
+macro_rules! impl_from_for_value {
+    ($($ty:ty => $variant:ident),+ $(,)?) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(value: $ty) -> Self {
+                    Value::$variant(value.into())
+                }
+            }
+        )+
+    };
+}
+
+impl_from_for_value! {
+    bool => Bool,
+    u8 => U64,
+    u16 => U64,
+    u32 => U64,
+    u64 => U64,
+    i8 => I64,
+    i16 => I64,
+    i32 => I64,
+    i64 => I64,
+    &str => Str,
+    String => Str,
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::U64(value as u64)
+    }
+}
+
+// === impl Op ===
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Override => "override",
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use super::{Command, Server, Shared, Watch};
+use bytes::{BufMut, Bytes};
+use console_api as proto;
+use prost::Message;
+use proto::instrument::{
+    instrument_server::InstrumentServer, Capability, InstrumentRequest, Protocol, PROTOCOL_VERSION,
+};
+use std::{fmt, marker::PhantomData};
+use tokio::sync::mpsc;
+use tonic::{
+    codec::{Codec, EncodeBuf, Encoder, ProstCodec},
+    codegen::{http, Body, BoxFuture, Context, Never, Poll, Service, StdError},
+    server::{Grpc, ServerStreamingService},
+    transport::NamedService,
+    Status,
+};
+
+/// The optional parts of the protocol which are supported, which are
+/// advertised to clients.
+///
+/// Resources and async ops are only advertised while they are tracked.
+const CAPABILITIES: &[Capability] = &[
+    Capability::TaskDetails,
+    Capability::Resources,
+    Capability::AsyncOps,
+    Capability::Resume,
+    Capability::Snapshots,
+    Capability::CompletedTasks,
+    Capability::Settings,
+    Capability::ProcessInfo,
+];
+
+/// A protobuf message which has already been encoded.
+///
+/// Cloning an `Encoded` message only increments a reference count, so an
+/// update may be encoded once and then sent to every client that is watching
+/// for updates.
+pub(crate) struct Encoded<T> {
+    bytes: Bytes,
+    _message: PhantomData<fn() -> T>,
+}
+
+/// Encodes messages which have already been encoded as [`Encoded`], and
+/// decodes requests using `prost`.
+pub(crate) struct EncodedCodec<T, U>(PhantomData<fn() -> (T, U)>);
+
+pub(crate) struct EncodedEncoder<T>(PhantomData<fn() -> T>);
+
+/// The `Instrument` gRPC service.
+///
+/// `WatchUpdates` requests are handled here, so that each update is encoded
+/// once and shared between all the clients watching for updates, rather than
+/// being cloned and encoded separately for each of them. All other requests
+/// are handed off to the generated [`InstrumentServer`].
+#[derive(Clone)]
+pub(crate) struct InstrumentService {
+    watch_updates: WatchUpdates,
+    server: InstrumentServer<Server>,
+}
+
+/// Starts `WatchUpdates` streams by subscribing to the aggregator.
+#[derive(Clone, Debug)]
+pub(crate) struct WatchUpdates {
+    subscribe: mpsc::Sender<Command>,
+    client_buffer: usize,
+}
+
+type UpdateStream =
+    tokio_stream::wrappers::ReceiverStream<Result<Encoded<proto::instrument::Update>, Status>>;
+
+// === impl Encoded ===
+
+impl<T: Message> Encoded<T> {
+    pub(crate) fn new(message: &T) -> Self {
+        Self {
+            bytes: message.encode_to_vec().into(),
+            _message: PhantomData,
+        }
+    }
+
+    /// Decodes the message.
+    ///
+    /// This is only necessary when the message must be handed to code which
+    /// expects the message type, rather than its encoded bytes.
+    pub(crate) fn decode(&self) -> T
+    where
+        T: Default,
+    {
+        T::decode(self.bytes.clone()).expect("encoded messages are always valid")
+    }
+}
+
+impl<T> Clone for Encoded<T> {
+    fn clone(&self) -> Self {
+        Self {
+            bytes: self.bytes.clone(),
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Encoded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encoded")
+            .field("message", &std::any::type_name::<T>())
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+// === impl EncodedCodec ===
+
+impl<T, U> Default for EncodedCodec<T, U> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T, U> Codec for EncodedCodec<T, U>
+where
+    T: 'static,
+    U: Message + Default + Send + 'static,
+{
+    type Encode = Encoded<T>;
+    type Decode = U;
+
+    type Encoder = EncodedEncoder<T>;
+    type Decoder = <ProstCodec<U, U> as Codec>::Decoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        EncodedEncoder(PhantomData)
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        ProstCodec::<U, U>::default().decoder()
+    }
+}
+
+impl<T> Encoder for EncodedEncoder<T> {
+    type Item = Encoded<T>;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, buf: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        buf.put_slice(&item.bytes);
+        Ok(())
+    }
+}
+
+// === impl InstrumentService ===
+
+impl InstrumentService {
+    const WATCH_UPDATES: &'static str = "/rs.tokio.console.instrument.Instrument/WatchUpdates";
+
+    pub(crate) fn new(server: Server) -> Self {
+        Self {
+            watch_updates: server.watch_updates_service(),
+            server: InstrumentServer::new(server),
+        }
+    }
+}
+
+impl NamedService for InstrumentService {
+    const NAME: &'static str = <InstrumentServer<Server> as NamedService>::NAME;
+}
+
+impl<B> Service<http::Request<B>> for InstrumentService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Never;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<http::Request<B>>::poll_ready(&mut self.server, cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != Self::WATCH_UPDATES {
+            return self.server.call(req);
+        }
+
+        let watch_updates = self.watch_updates.clone();
+        Box::pin(async move {
+            let mut grpc = Grpc::new(EncodedCodec::<_, InstrumentRequest>::default());
+            Ok(grpc.server_streaming(watch_updates, req).await)
+        })
+    }
+}
+
+// === impl WatchUpdates ===
+
+impl WatchUpdates {
+    pub(crate) fn new(subscribe: mpsc::Sender<Command>, client_buffer: usize) -> Self {
+        Self {
+            subscribe,
+            client_buffer,
+        }
+    }
+
+    pub(crate) async fn watch(
+        &self,
+        req: tonic::Request<InstrumentRequest>,
+    ) -> Result<UpdateStream, Status> {
+        match req.remote_addr() {
+            Some(addr) => tracing::debug!(client.addr = %addr, "starting a new watch"),
+            None => tracing::debug!(client.addr = %"<unknown>", "starting a new watch"),
+        }
+        let InstrumentRequest { resume, protocol } = req.into_inner();
+        check_protocol(protocol.as_ref()).map_err(Status::failed_precondition)?;
+        let permit = self.subscribe.reserve().await.map_err(|_| {
+            Status::internal("cannot start new watch, aggregation task is not running")
+        })?;
+        let (tx, rx) = mpsc::channel(self.client_buffer);
+        permit.send(Command::Instrument(Watch(tx), resume));
+        tracing::debug!("watch started");
+        Ok(tokio_stream::wrappers::ReceiverStream::new(rx))
+    }
+}
+
+impl ServerStreamingService<InstrumentRequest> for WatchUpdates {
+    type Response = Encoded<proto::instrument::Update>;
+    type ResponseStream = UpdateStream;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, req: tonic::Request<InstrumentRequest>) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move { this.watch(req).await.map(tonic::Response::new) })
+    }
+}
+
+/// Returns the version of the protocol spoken by this crate, and the
+/// capabilities it currently supports, given what `shared` says is tracked.
+pub(crate) fn protocol(shared: &Shared) -> Protocol {
+    Protocol::new(
+        CAPABILITIES
+            .iter()
+            .copied()
+            .filter(|&capability| match capability {
+                Capability::Resources => shared.tracks_resources(),
+                Capability::AsyncOps => shared.tracks_async_ops(),
+                _ => true,
+            }),
+    )
+}
+
+/// Checks that a client speaking `protocol` can understand the updates sent
+/// to it, returning why not if it can't.
+///
+/// Clients which don't say which protocol they speak predate protocol
+/// versions, and are assumed to be compatible.
+fn check_protocol(protocol: Option<&Protocol>) -> Result<(), String> {
+    let protocol = match protocol {
+        Some(protocol) => protocol,
+        None => return Ok(()),
+    };
+    tracing::debug!(
+        client.protocol_version = protocol.version,
+        client.capabilities = ?protocol.capabilities().collect::<Vec<_>>(),
+        "client protocol"
+    );
+    if protocol.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "the console speaks protocol version {}, but this process speaks version {}",
+            protocol.version, PROTOCOL_VERSION
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,314 @@
+//! Typed snapshots of the state recorded by a [`ConsoleLayer`], which can be
+//! taken in-process with a [`ConsoleHandle`].
+//!
+//! A [`Snapshot`] contains the same data that is sent to clients over gRPC,
+//! without needing a client to decode it: every task, resource, and async op
+//! which is currently alive, or which completed within the [retention
+//! period][retention].
+//!
+//! [`ConsoleLayer`]: crate::ConsoleLayer
+//! [`ConsoleHandle`]: crate::ConsoleHandle
+//! [retention]: crate::Builder::retention
+use hdrhistogram::Histogram;
+use std::time::{Duration, SystemTime};
+
+/// The state recorded by a [`ConsoleLayer`] at a point in time.
+///
+/// [`ConsoleLayer`]: crate::ConsoleLayer
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// When the snapshot was taken.
+    pub now: SystemTime,
+
+    /// Every task, in the order in which they were spawned.
+    pub tasks: Vec<Task>,
+
+    /// Every resource, in the order in which they were created.
+    pub resources: Vec<Resource>,
+
+    /// Every async op, in the order in which they were created.
+    pub async_ops: Vec<AsyncOp>,
+
+    /// The number of events which were dropped because the event buffer was
+    /// full, so that the data in this snapshot may be incomplete.
+    pub dropped_events: DroppedEvents,
+}
+
+/// A spawned task.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Task {
+    /// The task's ID, which is the same as the ID reported to clients.
+    pub id: u64,
+
+    /// The task's name, if it was given one.
+    pub name: Option<String>,
+
+    /// The target of the span representing the task.
+    pub target: &'static str,
+
+    /// Where the task was spawned, if known.
+    pub location: Option<String>,
+
+    /// The task's other fields, as pairs of names and formatted values.
+    pub fields: Vec<(String, String)>,
+
+    /// When the task was spawned.
+    pub created_at: Option<SystemTime>,
+
+    /// When the task completed, if it has.
+    pub dropped_at: Option<SystemTime>,
+
+    /// How often, and for how long, the task has been polled.
+    pub poll_stats: PollStats,
+
+    /// The durations of the task's polls.
+    pub poll_times: PollTimes,
+
+    /// The number of times the task has been woken.
+    pub wakes: u64,
+
+    /// The number of times the task has woken itself.
+    pub self_wakes: u64,
+
+    /// The number of times the task's waker has been cloned.
+    pub waker_clones: u64,
+
+    /// The number of times the task's waker has been dropped.
+    pub waker_drops: u64,
+
+    /// When the task was last woken.
+    pub last_wake: Option<SystemTime>,
+
+    /// How long the task's current poll had been running for, if it has been
+    /// running for long enough that the task is blocking its worker thread.
+    pub blocking_poll: Option<Duration>,
+
+    /// Whether any of the task's events were dropped because the event
+    /// buffer was full, so that its stats may be wrong.
+    pub events_dropped: bool,
+}
+
+/// A resource, such as a timer, a lock, or a channel.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Resource {
+    /// The resource's ID, which is the same as the ID reported to clients.
+    pub id: u64,
+
+    /// The ID of the resource this resource is part of, if any.
+    pub parent_id: Option<u64>,
+
+    /// The kind of resource, such as `"Timer"`.
+    pub kind: String,
+
+    /// The type of the resource, such as `"Sleep"`.
+    pub concrete_type: String,
+
+    /// The target of the span representing the resource.
+    pub target: &'static str,
+
+    /// Where the resource was created, if known.
+    pub location: Option<String>,
+
+    /// Whether the resource is part of the runtime's implementation, rather
+    /// than created by the user.
+    pub is_internal: bool,
+
+    /// When the resource was created.
+    pub created_at: Option<SystemTime>,
+
+    /// When the resource was dropped, if it has been.
+    pub dropped_at: Option<SystemTime>,
+
+    /// The resource's current state.
+    pub attributes: Vec<Attribute>,
+
+    /// Whether any of the resource's events were dropped because the event
+    /// buffer was full, so that its state may be wrong.
+    pub events_dropped: bool,
+}
+
+/// An asynchronous operation on a resource, such as waiting for a timer or
+/// acquiring a lock.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct AsyncOp {
+    /// The async op's ID, which is the same as the ID reported to clients.
+    pub id: u64,
+
+    /// The ID of the async op this async op is part of, if any.
+    pub parent_id: Option<u64>,
+
+    /// The ID of the resource the operation is on.
+    pub resource_id: u64,
+
+    /// The ID of the task which most recently polled the operation, if any.
+    pub task_id: Option<u64>,
+
+    /// The name of the method which started the operation, such as
+    /// `"Sleep::new_timeout"`.
+    pub source: String,
+
+    /// The target of the span representing the async op.
+    pub target: &'static str,
+
+    /// When the operation was created.
+    pub created_at: Option<SystemTime>,
+
+    /// When the operation was dropped, if it has been.
+    pub dropped_at: Option<SystemTime>,
+
+    /// How often, and for how long, the operation has been polled.
+    pub poll_stats: PollStats,
+
+    /// The operation's current state.
+    pub attributes: Vec<Attribute>,
+
+    /// Whether any of the operation's events were dropped because the event
+    /// buffer was full, so that its state may be wrong.
+    pub events_dropped: bool,
+}
+
+/// How often, and for how long, a task or async op has been polled.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct PollStats {
+    /// The number of times it has been polled.
+    pub polls: u64,
+
+    /// When the first poll started.
+    pub first_poll: Option<SystemTime>,
+
+    /// When the most recent poll started.
+    pub last_poll_started: Option<SystemTime>,
+
+    /// When the most recent poll ended, unless it is the first poll and is
+    /// still running.
+    pub last_poll_ended: Option<SystemTime>,
+
+    /// The total time spent polling it.
+    pub busy_time: Duration,
+}
+
+/// An attribute describing the state of a resource or async op.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Attribute {
+    /// The attribute's name, such as `"duration"`.
+    pub name: String,
+
+    /// The attribute's value, formatted as a string.
+    pub value: String,
+
+    /// The unit of the value, if it has one, such as `"ms"`.
+    pub unit: Option<String>,
+}
+
+/// The number of events of each kind which were dropped because the event
+/// buffer was full.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct DroppedEvents {
+    /// Events describing tasks, including wakes.
+    pub tasks: u64,
+
+    /// Events describing resources.
+    pub resources: u64,
+
+    /// Events describing async ops, including their polls.
+    pub async_ops: u64,
+}
+
+/// A histogram of a task's poll durations.
+#[derive(Clone)]
+pub struct PollTimes {
+    histogram: Histogram<u64>,
+}
+
+// === impl Task ===
+
+impl Task {
+    /// Returns `true` if the task has completed.
+    pub fn is_completed(&self) -> bool {
+        self.dropped_at.is_some()
+    }
+
+    /// Returns `true` if the task is currently being polled.
+    pub fn is_running(&self) -> bool {
+        self.poll_stats.last_poll_started > self.poll_stats.last_poll_ended
+    }
+
+    /// Returns `true` if the task has been woken since it was last polled, or
+    /// has not been polled yet.
+    pub fn is_awakened(&self) -> bool {
+        self.poll_stats.polls == 0 || self.last_wake > self.poll_stats.last_poll_started
+    }
+
+    /// Returns the number of wakers for the task which currently exist.
+    pub fn waker_count(&self) -> u64 {
+        self.waker_clones.saturating_sub(self.waker_drops)
+    }
+
+    /// Returns the percentage of the task's wakes which were self-wakes.
+    pub fn self_wake_percent(&self) -> u64 {
+        if self.wakes == 0 {
+            return 0;
+        }
+        self.self_wakes * 100 / self.wakes
+    }
+
+    /// Returns `true` if the task has lost its waker: it has not completed, is
+    /// neither running nor waiting to run, and no waker exists which could
+    /// wake it, so it will never run again.
+    pub fn has_lost_waker(&self) -> bool {
+        !self.is_completed() && self.waker_count() == 0 && !self.is_running() && !self.is_awakened()
+    }
+}
+
+// === impl PollTimes ===
+
+impl PollTimes {
+    pub(crate) fn new(histogram: Histogram<u64>) -> Self {
+        Self { histogram }
+    }
+
+    /// Returns the number of polls recorded.
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    /// Returns the duration of the shortest poll, or zero if there were none.
+    pub fn min(&self) -> Duration {
+        Duration::from_nanos(self.histogram.min())
+    }
+
+    /// Returns the duration of the longest poll, or zero if there were none.
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.histogram.max())
+    }
+
+    /// Returns the mean poll duration, or zero if there were no polls.
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos(self.histogram.mean() as u64)
+    }
+
+    /// Returns the poll duration at `percentile`, which is between 0 and 100.
+    ///
+    /// Durations are recorded with a precision of two significant figures.
+    pub fn value_at_percentile(&self, percentile: f64) -> Duration {
+        Duration::from_nanos(self.histogram.value_at_percentile(percentile))
+    }
+}
+
+impl std::fmt::Debug for PollTimes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PollTimes")
+            .field("count", &self.count())
+            .field("min", &self.min())
+            .field("mean", &self.mean())
+            .field("max", &self.max())
+            .finish()
+    }
+}
@@ -1,4 +1,5 @@
-use tracing_core::span::Id;
+use std::time::{Duration, Instant};
+use tracing_core::{span::Id, Metadata};
 
 // This has been copied from tracing-subscriber. Once the library adds
 // the ability to iterate over entered spans, this code will
@@ -9,6 +10,31 @@ use tracing_core::span::Id;
 pub(crate) struct ContextId {
     id: Id,
     duplicate: bool,
+    kind: SpanKind,
+    /// Tracks how long a user span has been entered during a task's poll.
+    ///
+    /// This is `None` unless this is the first entry of a user span inside of
+    /// a task.
+    timing: Option<Timing>,
+}
+
+/// What a span on the [`SpanStack`] represents to the console.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SpanKind {
+    /// A span representing a spawned task.
+    Task,
+    /// Any other span tracked by the console, such as a resource or an async
+    /// op.
+    Tracked,
+    /// A user-defined span, such as one created by `#[instrument]`.
+    User(&'static Metadata<'static>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Timing {
+    entered_at: Instant,
+    /// Time spent inside of user spans nested within this one.
+    child_time: Duration,
 }
 
 impl ContextId {
@@ -24,13 +50,53 @@ impl ContextId {
 #[derive(Debug, Default)]
 pub(crate) struct SpanStack {
     stack: Vec<ContextId>,
+
+    /// The number of task spans currently entered on this thread.
+    tasks: usize,
+
+    /// The user spans that were entered beneath the innermost task span when
+    /// a user span was most recently entered, from outermost to innermost.
+    ///
+    /// When the task's span is exited, this is the logical async backtrace of
+    /// the point at which the task yielded.
+    backtrace: Vec<(Id, &'static Metadata<'static>)>,
+
+    /// The time spent in each user span during the current poll of the
+    /// innermost task, excluding time spent in the user spans nested inside of
+    /// it.
+    busy_times: Vec<(&'static Metadata<'static>, Duration)>,
 }
 
 impl SpanStack {
     #[inline]
-    pub(crate) fn push(&mut self, id: Id) -> bool {
+    pub(crate) fn push(&mut self, id: Id, kind: SpanKind) -> bool {
         let duplicate = self.stack.iter().any(|i| i.id == id);
-        self.stack.push(ContextId { id, duplicate });
+        let mut timing = None;
+        match kind {
+            SpanKind::Task if !duplicate => {
+                // A new poll of the task begins, so whatever was recorded for
+                // the previous poll no longer applies.
+                self.backtrace.clear();
+                self.busy_times.clear();
+                self.tasks += 1;
+            }
+            SpanKind::User(_) if !duplicate && self.tasks > 0 => {
+                timing = Some(Timing {
+                    entered_at: Instant::now(),
+                    child_time: Duration::ZERO,
+                });
+            }
+            _ => {}
+        }
+        self.stack.push(ContextId {
+            id,
+            duplicate,
+            kind,
+            timing,
+        });
+        if timing.is_some() {
+            self.record_backtrace();
+        }
         !duplicate
     }
 
@@ -46,7 +112,17 @@ impl SpanStack {
             .rev()
             .find(|(_, ctx_id)| ctx_id.id == *expected_id)
         {
-            let ContextId { id: _, duplicate } = self.stack.remove(idx);
+            let ContextId {
+                duplicate,
+                kind,
+                timing,
+                ..
+            } = self.stack.remove(idx);
+            match (kind, timing) {
+                (SpanKind::Task, _) if !duplicate => self.tasks -= 1,
+                (SpanKind::User(meta), Some(timing)) => self.record_busy_time(idx, meta, timing),
+                _ => {}
+            }
             return !duplicate;
         }
         false
@@ -55,10 +131,220 @@ impl SpanStack {
     pub(crate) fn iter(&self) -> impl Iterator<Item = &Id> {
         self.stack
             .iter()
-            .filter_map(|ContextId { id, duplicate }| if *duplicate { None } else { Some(id) })
+            .filter_map(|ContextId { id, duplicate, .. }| if *duplicate { None } else { Some(id) })
     }
 
     pub(crate) fn stack(&self) -> &Vec<ContextId> {
         &self.stack
     }
+
+    /// Returns the ID of the innermost task span currently entered on this
+    /// thread, if any.
+    pub(crate) fn current_task(&self) -> Option<&Id> {
+        if self.tasks == 0 {
+            return None;
+        }
+        self.stack
+            .iter()
+            .rev()
+            .find(|ctx_id| matches!(ctx_id.kind, SpanKind::Task) && !ctx_id.duplicate)
+            .map(ContextId::id)
+    }
+
+    /// Takes the logical async backtrace recorded for the current poll of the
+    /// innermost task.
+    pub(crate) fn take_backtrace(&mut self) -> Vec<&'static Metadata<'static>> {
+        self.backtrace.drain(..).map(|(_, meta)| meta).collect()
+    }
+
+    /// Takes the time spent in each user span during the current poll of the
+    /// innermost task.
+    pub(crate) fn take_busy_times(&mut self) -> Vec<(&'static Metadata<'static>, Duration)> {
+        std::mem::take(&mut self.busy_times)
+    }
+
+    /// Removes a user span that has been closed (and every span entered
+    /// beneath it) from the recorded backtrace.
+    ///
+    /// A span that closes while the task is being polled has completed, so the
+    /// task cannot be waiting inside of it.
+    pub(crate) fn close(&mut self, id: &Id) {
+        if let Some(idx) = self.backtrace.iter().position(|(span, _)| span == id) {
+            self.backtrace.truncate(idx);
+        }
+    }
+
+    fn record_backtrace(&mut self) {
+        let task = match self
+            .stack
+            .iter()
+            .rposition(|ctx_id| matches!(ctx_id.kind, SpanKind::Task))
+        {
+            Some(idx) => idx,
+            // Not inside of a task, so there is nothing to record.
+            None => return,
+        };
+
+        let user_spans = self.stack[task + 1..]
+            .iter()
+            .filter(|ctx_id| !ctx_id.duplicate)
+            .filter_map(|ctx_id| match ctx_id.kind {
+                SpanKind::User(meta) => Some((ctx_id.id.clone(), meta)),
+                _ => None,
+            });
+        self.backtrace.clear();
+        self.backtrace.extend(user_spans);
+    }
+
+    /// Records the time spent in a user span that was at index `idx` of the
+    /// stack, and charges it to the enclosing user span as time spent in a
+    /// child.
+    fn record_busy_time(&mut self, idx: usize, meta: &'static Metadata<'static>, timing: Timing) {
+        let elapsed = timing.entered_at.elapsed();
+        let busy = elapsed.saturating_sub(timing.child_time);
+        match self
+            .busy_times
+            .iter_mut()
+            .find(|(m, _)| std::ptr::eq(*m, meta))
+        {
+            Some((_, total)) => *total += busy,
+            None => self.busy_times.push((meta, busy)),
+        }
+
+        let parent = self.stack[..idx]
+            .iter_mut()
+            .rev()
+            .take_while(|ctx_id| !matches!(ctx_id.kind, SpanKind::Task))
+            .find_map(|ctx_id| ctx_id.timing.as_mut());
+        if let Some(parent) = parent {
+            parent.child_time += elapsed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{executor::TaskSpan, ConsoleLayer};
+    use tracing_core::{callsite::Callsite, metadata::Kind, subscriber::Interest, Level};
+    use tracing_subscriber::prelude::*;
+
+    macro_rules! user_span {
+        ($metadata:ident, $callsite:ident, $name:literal) => {
+            struct $callsite;
+
+            static $metadata: Metadata<'static> = tracing_core::metadata! {
+                name: $name,
+                target: "app",
+                level: Level::INFO,
+                fields: &[],
+                callsite: &$callsite,
+                kind: Kind::SPAN,
+            };
+
+            impl Callsite for $callsite {
+                fn set_interest(&self, _: Interest) {}
+
+                fn metadata(&self) -> &Metadata<'_> {
+                    &$metadata
+                }
+            }
+        };
+    }
+
+    user_span!(OUTER, OuterCallsite, "outer");
+    user_span!(INNER, InnerCallsite, "inner");
+
+    fn names(backtrace: Vec<&'static Metadata<'static>>) -> Vec<&'static str> {
+        backtrace.into_iter().map(Metadata::name).collect()
+    }
+
+    /// Polls the task with span ID 1 once, entering `spans` in order beneath
+    /// it and then exiting them all, as a task does when it yields.
+    fn poll(stack: &mut SpanStack, spans: &[(u64, SpanKind)]) {
+        stack.push(Id::from_u64(1), SpanKind::Task);
+        for (id, kind) in spans {
+            stack.push(Id::from_u64(*id), *kind);
+        }
+        for (id, _) in spans.iter().rev() {
+            stack.pop(&Id::from_u64(*id));
+        }
+        stack.pop(&Id::from_u64(1));
+    }
+
+    #[test]
+    fn backtrace_is_the_stack_at_yield_time() {
+        let mut stack = SpanStack::default();
+        poll(
+            &mut stack,
+            &[
+                (2, SpanKind::User(&OUTER)),
+                (3, SpanKind::Tracked),
+                (4, SpanKind::User(&INNER)),
+            ],
+        );
+        // Only user spans are frames; the resource span between them isn't.
+        assert_eq!(names(stack.take_backtrace()), ["outer", "inner"]);
+
+        // Each poll starts a new backtrace.
+        poll(&mut stack, &[(2, SpanKind::User(&OUTER))]);
+        assert_eq!(names(stack.take_backtrace()), ["outer"]);
+        poll(&mut stack, &[]);
+        assert!(stack.take_backtrace().is_empty());
+    }
+
+    #[test]
+    fn backtrace_skips_closed_and_duplicate_spans() {
+        let mut stack = SpanStack::default();
+        stack.push(Id::from_u64(1), SpanKind::Task);
+        stack.push(Id::from_u64(2), SpanKind::User(&OUTER));
+        // Entering the same span again doesn't add another frame.
+        stack.push(Id::from_u64(2), SpanKind::User(&OUTER));
+        stack.push(Id::from_u64(3), SpanKind::User(&INNER));
+        stack.pop(&Id::from_u64(3));
+        // The inner span completed before the task yielded.
+        stack.close(&Id::from_u64(3));
+        stack.pop(&Id::from_u64(2));
+        stack.pop(&Id::from_u64(2));
+        stack.pop(&Id::from_u64(1));
+        assert_eq!(names(stack.take_backtrace()), ["outer"]);
+        let busy = stack.take_busy_times().into_iter().map(|(meta, _)| meta);
+        assert_eq!(names(busy.collect()), ["inner", "outer"]);
+
+        // User spans entered outside of any task aren't recorded.
+        stack.push(Id::from_u64(4), SpanKind::User(&INNER));
+        assert!(stack.take_backtrace().is_empty());
+        assert!(stack.take_busy_times().is_empty());
+    }
+
+    /// Returns the names of the user spans the console layer records as the
+    /// backtrace of a task polled inside of an `outer` span.
+    fn recorded_backtrace(track_user_spans: bool) -> Vec<&'static str> {
+        let (layer, _server) = ConsoleLayer::builder()
+            .track_user_spans(track_user_spans)
+            .build();
+        let filter = layer.filter();
+        let dispatch =
+            tracing::Dispatch::new(tracing_subscriber::registry().with(layer.with_filter(filter)));
+        tracing::dispatcher::with_default(&dispatch, || {
+            let task = TaskSpan::new(Some("task"));
+            let _polling = task.enter();
+            let _outer = tracing::info_span!("outer").entered();
+            let layer = dispatch
+                .downcast_ref::<ConsoleLayer>()
+                .expect("the dispatcher has a console layer");
+            let spans = layer.current_spans.get_or_default().borrow();
+            spans
+                .backtrace
+                .iter()
+                .map(|(_, meta)| meta.name())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn user_spans_are_only_tracked_when_enabled() {
+        assert_eq!(recorded_backtrace(true), ["outer"]);
+        assert!(recorded_backtrace(false).is_empty());
+    }
 }
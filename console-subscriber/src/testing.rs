@@ -0,0 +1,315 @@
+//! Helpers for testing the async behavior of code, using the data recorded by
+//! a [`ConsoleLayer`].
+//!
+//! [`run`] runs a future on a new Tokio runtime, with a [`ConsoleLayer`]
+//! recording every task it spawns, and returns a [`Report`] of what was
+//! recorded. The report's assertions check for the same problems that the
+//! console warns about, such as tasks which have lost their wakers, as well as
+//! for slow polls and tasks which were left running.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! console_subscriber::testing::run(async {
+//!     let task = tokio::spawn(async {
+//!         tokio::time::sleep(Duration::from_millis(10)).await;
+//!     });
+//!     task.await.unwrap();
+//! })
+//! .assert_no_lost_wakers()
+//! .assert_max_poll_time(Duration::from_millis(10))
+//! .assert_all_tasks_completed();
+//! ```
+//!
+//! [`ConsoleLayer`]: crate::ConsoleLayer
+#[doc(hidden)]
+pub use crate::aggregator::PublishFixture;
+use crate::{
+    snapshot::{Snapshot, Task},
+    Builder, Command, ConsoleLayer,
+};
+use std::{fmt::Write, future::Future, thread, time::Duration};
+use tokio::{runtime, sync::oneshot};
+use tracing_subscriber::prelude::*;
+
+/// What was recorded while running a future with [`run`].
+#[derive(Debug)]
+pub struct Report<T> {
+    output: T,
+    snapshot: Snapshot,
+}
+
+/// Runs `future` to completion on a new current-thread Tokio runtime, and
+/// returns a [`Report`] of the tasks it spawned.
+///
+/// The report describes the state of every task spawned by `future` when it
+/// completed. Tasks which are still running at that point are dropped when the
+/// runtime is, after the report has been made.
+///
+/// A [`ConsoleLayer`] is only installed as the default subscriber for the
+/// runtime, so this may be used in tests which run concurrently, and in
+/// processes which have set a global default subscriber. Its aggregator runs
+/// on a separate thread, and no server is started.
+///
+/// # Panics
+///
+/// * If Tokio was not built with `RUSTFLAGS="--cfg tokio_unstable"`.
+/// * If `future` panics.
+///
+/// [`ConsoleLayer`]: crate::ConsoleLayer
+pub fn run<F: Future>(future: F) -> Report<F::Output> {
+    run_with(ConsoleLayer::builder(), future)
+}
+
+/// Runs `future` like [`run`] does, with a [`ConsoleLayer`] configured by
+/// `builder`.
+///
+/// [`ConsoleLayer`]: crate::ConsoleLayer
+pub(crate) fn run_with<F: Future>(builder: Builder, future: F) -> Report<F::Output> {
+    let (layer, mut server) = builder.build();
+    let mut aggregator = server
+        .aggregator
+        .take()
+        .expect("a new server has an aggregator");
+    aggregator.set_owns_thread();
+    let aggregator_runtime = runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("testing aggregator runtime initialization failed");
+    let aggregator_handle = aggregator_runtime.handle().clone();
+    let aggregate = thread::Builder::new()
+        .name("console_subscriber::testing".into())
+        .spawn(move || aggregator_runtime.block_on(aggregator.run()))
+        .expect("testing aggregator thread could not be spawned");
+
+    let filter = layer.filter();
+    let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter));
+    let (output, runtime, mut snapshot) = tracing::subscriber::with_default(subscriber, || {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("testing runtime initialization failed");
+        let output = runtime.block_on(future);
+        // Take the snapshot while the layer, the runtime, and any tasks which
+        // are still running, are alive. Once the layer is dropped, the
+        // aggregator stops as soon as it has drained the layer's events.
+        let snapshot = aggregator_handle
+            .block_on(async {
+                let (tx, rx) = oneshot::channel();
+                server
+                    .subscribe
+                    .send(Command::LocalSnapshot(tx))
+                    .await
+                    .ok()?;
+                rx.await.ok()
+            })
+            .expect("testing aggregator stopped");
+        (output, runtime, snapshot)
+    });
+
+    // Tokio instruments `future` itself as a task, which isn't one of the
+    // tasks it spawned.
+    snapshot.tasks.retain(|task| {
+        !task
+            .fields
+            .iter()
+            .any(|(name, value)| name == "kind" && value == "block_on")
+    });
+
+    drop(runtime);
+    // Dropping the server closes the aggregator's command channel, which
+    // stops it.
+    drop(server);
+    aggregate
+        .join()
+        .expect("testing aggregator thread panicked");
+
+    Report { output, snapshot }
+}
+
+impl<T> Report<T> {
+    /// Returns the output of the future.
+    pub fn output(&self) -> &T {
+        &self.output
+    }
+
+    /// Returns the output of the future, consuming the report.
+    pub fn into_output(self) -> T {
+        self.output
+    }
+
+    /// Returns the state of every task when the future completed.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// Asserts that no task has lost its waker.
+    ///
+    /// A task has lost its waker if it hasn't completed, and isn't waiting to
+    /// be polled, but no waker for it exists, so it will never be woken.
+    ///
+    /// # Panics
+    ///
+    /// If any task has lost its waker.
+    #[track_caller]
+    pub fn assert_no_lost_wakers(&self) -> &Self {
+        self.assert_tasks("tasks have lost their waker", |task| {
+            task.has_lost_waker().then(String::new)
+        })
+    }
+
+    /// Asserts that no more than `max_percent` percent of each task's wakes
+    /// were self-wakes.
+    ///
+    /// # Panics
+    ///
+    /// If any task woke itself more often than that.
+    #[track_caller]
+    pub fn assert_max_self_wake_percent(&self, max_percent: u64) -> &Self {
+        let summary = format!(
+            "tasks have woken themselves more than {}% of the time",
+            max_percent
+        );
+        self.assert_tasks(&summary, |task| {
+            let percent = task.self_wake_percent();
+            (percent > max_percent)
+                .then(|| format!("{}% of {} wakes were self-wakes", percent, task.wakes))
+        })
+    }
+
+    /// Asserts that no single poll of any task took longer than `max`.
+    ///
+    /// Poll times are recorded with a precision of two significant figures.
+    ///
+    /// # Panics
+    ///
+    /// If any task was polled for longer than `max` at once.
+    #[track_caller]
+    pub fn assert_max_poll_time(&self, max: Duration) -> &Self {
+        let summary = format!("tasks have been polled for longer than {:?}", max);
+        self.assert_tasks(&summary, |task| {
+            let longest = task
+                .poll_times
+                .max()
+                .max(task.blocking_poll.unwrap_or_default());
+            (longest > max).then(|| format!("longest poll took {:?}", longest))
+        })
+    }
+
+    /// Asserts that every task spawned by the future completed before it did.
+    ///
+    /// # Panics
+    ///
+    /// If any task was still running when the future completed.
+    #[track_caller]
+    pub fn assert_all_tasks_completed(&self) -> &Self {
+        self.assert_tasks("tasks did not complete", |task| {
+            (!task.is_completed()).then(String::new)
+        })
+    }
+
+    /// Panics with `summary` and a list of the tasks for which `check` returns
+    /// a description of the problem.
+    #[track_caller]
+    fn assert_tasks(&self, summary: &str, check: impl Fn(&Task) -> Option<String>) -> &Self {
+        let mut failures = String::new();
+        let mut count = 0;
+        for task in &self.snapshot.tasks {
+            if let Some(problem) = check(task) {
+                count += 1;
+                let _ = write!(failures, "\n  task {}", task.id);
+                if let Some(name) = &task.name {
+                    let _ = write!(failures, " ({})", name);
+                }
+                if let Some(location) = &task.location {
+                    let _ = write!(failures, " spawned at {}", location);
+                }
+                if !problem.is_empty() {
+                    let _ = write!(failures, ": {}", problem);
+                }
+            }
+        }
+        if count > 0 {
+            panic!(
+                "{} of {} {}:{}",
+                count,
+                self.snapshot.tasks.len(),
+                summary,
+                failures
+            );
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use std::time::Duration;
+
+    #[test]
+    fn assertions_pass_for_well_behaved_tasks() {
+        let report = run(async {
+            let task = tokio::spawn(async {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                42
+            });
+            task.await.unwrap()
+        });
+        report
+            .assert_no_lost_wakers()
+            .assert_max_self_wake_percent(0)
+            .assert_max_poll_time(Duration::from_secs(1))
+            .assert_all_tasks_completed();
+        assert_eq!(*report.output(), 42);
+        assert_eq!(report.snapshot().tasks.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 1 tasks have lost their waker")]
+    fn lost_wakers_fail() {
+        run(async {
+            tokio::spawn(std::future::pending::<()>());
+            tokio::task::yield_now().await;
+        })
+        .assert_no_lost_wakers();
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 1 tasks have woken themselves more than 50% of the time")]
+    fn self_wakes_fail() {
+        run(async {
+            let task = tokio::spawn(async {
+                for _ in 0..4 {
+                    tokio::task::yield_now().await;
+                }
+            });
+            task.await.unwrap();
+        })
+        .assert_max_self_wake_percent(50);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 1 tasks have been polled for longer than 5ms")]
+    fn slow_polls_fail() {
+        run(async {
+            let task = tokio::spawn(async {
+                std::thread::sleep(Duration::from_millis(20));
+            });
+            task.await.unwrap();
+        })
+        .assert_max_poll_time(Duration::from_millis(5));
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 1 tasks did not complete")]
+    fn running_tasks_fail() {
+        run(async {
+            tokio::spawn(tokio::time::sleep(Duration::from_secs(60)));
+            tokio::task::yield_now().await;
+        })
+        .assert_all_tasks_completed();
+    }
+}
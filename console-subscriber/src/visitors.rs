@@ -146,21 +146,39 @@ pub(crate) struct PollOpVisitor {
 /// an Event::StateUpdate from the metadata of a tracing event
 /// that has the following shape:
 ///
+/// ```text
 /// tracing::trace!(
 ///     target: "runtime::resource::state_update",
 ///     duration = duration,
 ///     duration.unit = "ms",
 ///     duration.op = "override",
 /// );
+/// ```
 ///
 /// Fields:
 /// attribute_name - a field value for a field that has the name of the resource attribute being updated
 /// value - the value for this update
 /// unit - the unit for the value being updated (e.g. ms, s, bytes)
 /// op - the operation that this update performs to the value of the resource attribute (one of: ovr, sub, add)
+///
+/// Since field names must be known when the callsite is compiled, the
+/// attribute's name may instead be given by an `attribute.name` field, in which
+/// case the value's field may have any name. This is how the
+/// `console_subscriber::resource` API records updates:
+///
+/// ```text
+/// tracing::trace!(
+///     target: "runtime::resource::state_update",
+///     attribute.name = "permits",
+///     value = 3u64,
+///     value.op = "add",
+///     value.unit = "permits",
+/// );
+/// ```
 pub(crate) struct StateUpdateVisitor {
     meta_id: proto::MetaId,
     field: Option<proto::Field>,
+    name: Option<String>,
     unit: Option<String>,
     op: Option<AttributeUpdateOp>,
 }
@@ -440,6 +458,7 @@ impl StateUpdateVisitor {
     pub(crate) const AO_STATE_UPDATE_EVENT_TARGET: &'static str =
         "runtime::resource::async_op::state_update";
 
+    const ATTRIBUTE_NAME_FIELD_NAME: &'static str = "attribute.name";
     const STATE_OP_SUFFIX: &'static str = ".op";
     const STATE_UNIT_SUFFIX: &'static str = ".unit";
 
@@ -451,14 +470,19 @@ impl StateUpdateVisitor {
         StateUpdateVisitor {
             meta_id,
             field: None,
+            name: None,
             unit: None,
             op: None,
         }
     }
 
     pub(crate) fn result(self) -> Option<AttributeUpdate> {
+        let mut field = self.field?;
+        if let Some(name) = self.name {
+            field.name = Some(name.as_str().into());
+        }
         Some(AttributeUpdate {
-            field: self.field?,
+            field,
             op: self.op,
             unit: self.unit,
         })
@@ -515,7 +539,9 @@ impl Visit for StateUpdateVisitor {
     }
 
     fn record_str(&mut self, field: &field::Field, value: &str) {
-        if field.name().ends_with(Self::STATE_OP_SUFFIX) {
+        if field.name() == Self::ATTRIBUTE_NAME_FIELD_NAME {
+            self.name = Some(value.to_string());
+        } else if field.name().ends_with(Self::STATE_OP_SUFFIX) {
             match value {
                 Self::OP_ADD => self.op = Some(AttributeUpdateOp::Add),
                 Self::OP_SUB => self.op = Some(AttributeUpdateOp::Sub),
@@ -0,0 +1,186 @@
+//! Checks that the tasks of an executor other than Tokio's, instrumented with
+//! `console_subscriber::executor`, are recorded like Tokio's own tasks.
+use console_subscriber::{executor::TaskSpan, snapshot::Task as TaskSnapshot};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    mem::ManuallyDrop,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Queue = Arc<Mutex<VecDeque<Arc<Task>>>>;
+
+/// A single-threaded executor, which polls its tasks until none are left to
+/// poll.
+#[derive(Default)]
+struct Executor {
+    queue: Queue,
+}
+
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    span: TaskSpan,
+    queue: Queue,
+}
+
+impl Executor {
+    #[track_caller]
+    fn spawn(&self, name: &str, future: impl Future<Output = ()> + Send + 'static) {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            span: TaskSpan::new(Some(name)),
+            queue: self.queue.clone(),
+        });
+        self.queue.lock().unwrap().push_back(task);
+    }
+
+    fn run(&self) {
+        loop {
+            let task = match self.queue.lock().unwrap().pop_front() {
+                Some(task) => task,
+                None => return,
+            };
+            task.poll();
+        }
+    }
+}
+
+impl Task {
+    fn poll(self: &Arc<Self>) {
+        let mut future = self.future.lock().unwrap();
+        let completed = match future.as_mut() {
+            Some(future) => {
+                let _polling = self.span.enter();
+                // The waker is only lent to the task while it is polled, so
+                // it isn't reported as cloned, or as dropped afterwards.
+                let waker = ManuallyDrop::new(unsafe {
+                    Waker::from_raw(RawWaker::new(Arc::as_ptr(self).cast(), &VTABLE))
+                });
+                let mut cx = Context::from_waker(&waker);
+                future.as_mut().poll(&mut cx).is_ready()
+            }
+            None => false,
+        };
+        if completed {
+            *future = None;
+        }
+    }
+
+    fn schedule(self: Arc<Self>) {
+        let queue = self.queue.clone();
+        queue.lock().unwrap().push_back(self);
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+
+unsafe fn clone(ptr: *const ()) -> RawWaker {
+    let task = ptr.cast::<Task>();
+    Arc::increment_strong_count(task);
+    (*task).span.waker_ops().cloned();
+    RawWaker::new(ptr, &VTABLE)
+}
+
+unsafe fn wake(ptr: *const ()) {
+    let task = Arc::from_raw(ptr.cast::<Task>());
+    task.span.waker_ops().woken();
+    task.schedule();
+}
+
+unsafe fn wake_by_ref(ptr: *const ()) {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr.cast::<Task>()));
+    task.span.waker_ops().woken_by_ref();
+    Arc::clone(&task).schedule();
+}
+
+unsafe fn drop(ptr: *const ()) {
+    let task = Arc::from_raw(ptr.cast::<Task>());
+    task.span.waker_ops().dropped();
+}
+
+/// Returns `Poll::Pending` once, after waking the task by reference.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Completes once `notify` has been called, keeping a clone of the waiting
+/// task's waker until then.
+#[derive(Clone, Default)]
+struct Signal(Arc<Mutex<(bool, Option<Waker>)>>);
+
+impl Signal {
+    fn notify(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.0 = true;
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for Signal {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.0 {
+            return Poll::Ready(());
+        }
+        state.1 = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+fn find<'a>(tasks: &'a [TaskSnapshot], name: &str) -> &'a TaskSnapshot {
+    tasks
+        .iter()
+        .find(|task| task.name.as_deref() == Some(name))
+        .unwrap_or_else(|| panic!("no task named {:?} was recorded", name))
+}
+
+#[test]
+fn tasks_are_recorded() {
+    let report = console_subscriber::testing::run(async {
+        let executor = Executor::default();
+        let signal = Signal::default();
+        executor.spawn("waiter", signal.clone());
+        executor.spawn("notifier", async move { signal.notify() });
+        executor.spawn("yielder", YieldNow(false));
+        executor.run();
+    });
+    report.assert_no_lost_wakers().assert_all_tasks_completed();
+
+    let tasks = &report.snapshot().tasks;
+
+    let waiter = find(tasks, "waiter");
+    assert_eq!(waiter.poll_stats.polls, 2);
+    assert_eq!(waiter.wakes, 1);
+    assert_eq!(waiter.self_wakes, 0);
+    assert_eq!(waiter.waker_clones, 1);
+    assert_eq!(waiter.waker_drops, 1);
+    assert!(waiter.location.as_deref().unwrap().contains("executor.rs"));
+
+    let notifier = find(tasks, "notifier");
+    assert_eq!(notifier.poll_stats.polls, 1);
+    assert_eq!(notifier.wakes, 0);
+
+    let yielder = find(tasks, "yielder");
+    assert_eq!(yielder.poll_stats.polls, 2);
+    assert_eq!(yielder.wakes, 1);
+    assert_eq!(yielder.self_wakes, 1);
+    assert_eq!(yielder.waker_clones, 0);
+}
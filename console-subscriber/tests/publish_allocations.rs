@@ -0,0 +1,59 @@
+//! Checks that publishing an update costs the same number of allocations
+//! however many clients are watching, since each update is only encoded once.
+//!
+//! This is a test binary of its own, so that its counting allocator doesn't
+//! slow down any other tests.
+use console_subscriber::testing::PublishFixture;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+/// Counts the allocations made by each thread.
+struct CountingAlloc;
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Publishes an update to `watchers` watchers, returning the number of
+/// allocations made while publishing it.
+fn publish_allocations(watchers: usize) -> usize {
+    let mut fixture = PublishFixture::new(watchers);
+    let before = allocations();
+    fixture.publish();
+    let allocated = allocations() - before;
+
+    let received = fixture.new_tasks_received();
+    assert_eq!(received.len(), watchers);
+    assert!(received
+        .iter()
+        .all(|&new_tasks| new_tasks >= PublishFixture::TASKS));
+    allocated
+}
+
+#[test]
+fn publishing_allocations_do_not_grow_with_watchers() {
+    let one = publish_allocations(1);
+    assert!(one > 0);
+    assert_eq!(publish_allocations(16), one);
+    assert_eq!(publish_allocations(64), one);
+}
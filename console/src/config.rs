@@ -1,5 +1,7 @@
 use crate::view::Palette;
-use clap::{ArgGroup, Parser as Clap, ValueHint};
+use clap::{ArgEnum, ArgGroup, Parser as Clap, Subcommand, ValueHint};
+use console_api::instrument::{snapshot_request, UpdateSettingsRequest};
+use std::path::PathBuf;
 use std::process::Command;
 use std::str::FromStr;
 use std::time::Duration;
@@ -20,6 +22,22 @@ pub struct Config {
     #[clap(default_value = "http://127.0.0.1:6669", value_hint = ValueHint::Url)]
     pub(crate) target_addr: Uri,
 
+    /// The process ID of a discoverable console-enabled process to connect
+    /// to, instead of `TARGET_ADDR`.
+    ///
+    /// A process is discoverable if it writes a discovery file into the
+    /// discovery directory. Run `tokio-console list` to list them.
+    #[clap(long, conflicts_with = "target-addr")]
+    pub(crate) pid: Option<u32>,
+
+    /// The directory in which discoverable console-enabled processes write
+    /// their discovery files.
+    ///
+    /// By default, this is `tokio-console` in `$XDG_RUNTIME_DIR`, or in the
+    /// temporary directory if that isn't set.
+    #[clap(long, env = "TOKIO_CONSOLE_DISCOVERY_DIR", value_hint = ValueHint::DirPath)]
+    discovery_dir: Option<PathBuf>,
+
     /// Log level filter for the console's internal diagnostics.
     ///
     /// The console will log to stderr if a log level filter is provided. Since
@@ -61,6 +79,106 @@ pub struct Config {
     /// * `years`, `year`, `y` -- defined as 365.25 days
     #[clap(long = "retain-for", default_value = "6s")]
     retain_for: RetainFor,
+
+    #[clap(subcommand)]
+    pub(crate) subcommand: Option<Subcmd>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Subcmd {
+    /// Print a snapshot of the current state of the instrumented process, and
+    /// exit.
+    Snapshot(SnapshotOptions),
+
+    /// Change the settings of the instrumented process, and print the settings
+    /// in effect afterwards.
+    ///
+    /// If no changes are given, this just prints the current settings.
+    Settings(SettingsOptions),
+
+    /// Open a dump of the state of an instrumented process, which it wrote to
+    /// a file when it received a signal or panicked.
+    ///
+    /// The dump is shown in the same views as a live process, but never
+    /// changes.
+    Open(OpenOptions),
+
+    /// List the discoverable console-enabled processes, and exit.
+    List,
+
+    /// Choose one of the discoverable console-enabled processes, and connect
+    /// to it.
+    ///
+    /// If there is only one, it is connected to without asking.
+    Choose,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct SnapshotOptions {
+    /// The format to print the snapshot in.
+    #[clap(long, arg_enum, default_value = "json")]
+    pub(crate) format: SnapshotFormat,
+
+    /// Only include the given kind of state in the snapshot.
+    ///
+    /// This may be repeated to include several kinds of state. By default,
+    /// every kind of state is included.
+    #[clap(long, arg_enum, multiple_occurrences(true))]
+    include: Vec<SnapshotKind>,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct OpenOptions {
+    /// The path of the dump file to open.
+    #[clap(value_hint = ValueHint::FilePath)]
+    pub(crate) path: PathBuf,
+}
+
+#[derive(Clap, Debug)]
+pub(crate) struct SettingsOptions {
+    /// Sets the interval at which updates are published to clients.
+    #[clap(long)]
+    publish_interval: Option<humantime::Duration>,
+
+    /// Sets how long data about completed tasks, dropped resources and
+    /// dropped async ops is retained.
+    #[clap(long)]
+    retention: Option<humantime::Duration>,
+
+    /// Starts recording events to a file at this path, ending any recording
+    /// which is already in progress.
+    ///
+    /// The path is relative to the directory the instrumented process allows
+    /// recordings in, on the host it is running on. Processes only allow
+    /// recordings to be started if they set a recording directory.
+    #[clap(long, value_hint = ValueHint::FilePath, conflicts_with = "stop-recording")]
+    record: Option<String>,
+
+    /// Ends the recording in progress, if there is one.
+    #[clap(long)]
+    stop_recording: bool,
+
+    /// Sets whether resources are tracked.
+    #[clap(long, possible_values = &["true", "false"])]
+    track_resources: Option<bool>,
+
+    /// Sets whether async ops are tracked.
+    ///
+    /// Async ops are only tracked while resources are tracked as well.
+    #[clap(long, possible_values = &["true", "false"])]
+    track_async_ops: Option<bool>,
+}
+
+#[derive(ArgEnum, Copy, Clone, Debug)]
+pub(crate) enum SnapshotFormat {
+    Json,
+}
+
+#[derive(ArgEnum, Copy, Clone, Debug)]
+enum SnapshotKind {
+    Tasks,
+    Resources,
+    AsyncOps,
 }
 
 #[derive(Debug)]
@@ -152,6 +270,39 @@ impl Config {
     pub(crate) fn retain_for(&self) -> Option<Duration> {
         self.retain_for.0
     }
+
+    pub(crate) fn discovery_dir(&self) -> PathBuf {
+        self.discovery_dir
+            .clone()
+            .unwrap_or_else(crate::discovery::default_dir)
+    }
+}
+
+// === impl SnapshotOptions ===
+
+impl SnapshotOptions {
+    pub(crate) fn include(&self) -> impl Iterator<Item = snapshot_request::Kind> + '_ {
+        self.include.iter().map(|kind| match kind {
+            SnapshotKind::Tasks => snapshot_request::Kind::Tasks,
+            SnapshotKind::Resources => snapshot_request::Kind::Resources,
+            SnapshotKind::AsyncOps => snapshot_request::Kind::AsyncOps,
+        })
+    }
+}
+
+// === impl SettingsOptions ===
+
+impl SettingsOptions {
+    pub(crate) fn request(&self) -> UpdateSettingsRequest {
+        UpdateSettingsRequest {
+            publish_interval: self.publish_interval.map(|d| (*d).into()),
+            retention: self.retention.map(|d| (*d).into()),
+            start_recording: self.record.clone(),
+            stop_recording: self.stop_recording,
+            track_resources: self.track_resources,
+            track_async_ops: self.track_async_ops,
+        }
+    }
 }
 
 // === impl ViewOptions ===
@@ -1,16 +1,42 @@
+use color_eyre::eyre::{eyre, WrapErr};
 use console_api::instrument::{
-    instrument_client::InstrumentClient, InstrumentRequest, PauseRequest, ResumeRequest,
-    TaskDetailsRequest, Update,
+    instrument_client::InstrumentClient, Capability, Dump, InstrumentRequest, PauseRequest,
+    Protocol, ResumeCursor, ResumeRequest, TaskDetailsRequest, Update, PROTOCOL_VERSION,
 };
 use console_api::tasks::TaskDetails;
-use futures::stream::StreamExt;
-use std::{error::Error, pin::Pin, time::Duration};
+use futures::stream::{self, Stream, StreamExt};
+use prost::Message;
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
 use tonic::{transport::Channel, transport::Uri, Streaming};
 
+/// The optional parts of the protocol which the console supports, which are
+/// advertised to instrumented processes.
+const CAPABILITIES: &[Capability] = &[
+    Capability::TaskDetails,
+    Capability::Resources,
+    Capability::AsyncOps,
+    Capability::Resume,
+    Capability::Snapshots,
+    Capability::Settings,
+    Capability::ProcessInfo,
+];
+
+/// A stream of updates to the details of a single task.
+pub type DetailsStream = Pin<Box<dyn Stream<Item = Result<TaskDetails, tonic::Status>> + Send>>;
+
 #[derive(Debug)]
 pub struct Connection {
     target: Uri,
     state: State,
+    /// Identifies the last update received, so that the stream of updates can
+    /// be resumed from it after reconnecting.
+    resume: Option<ResumeCursor>,
 }
 
 // clippy doesn't like that the "connected" case is much larger than the
@@ -26,6 +52,17 @@ enum State {
         stream: Box<Streaming<Update>>,
     },
     Disconnected(Duration),
+    /// The instrumented process speaks a version of the protocol which the
+    /// console can't understand, so it isn't reconnected to.
+    Incompatible(String),
+    /// Viewing the state saved in a dump file, rather than a live process.
+    Dump {
+        path: PathBuf,
+        reason: String,
+        /// The state, until it has been returned by `next_update`.
+        update: Option<Update>,
+        details: HashMap<u64, TaskDetails>,
+    },
 }
 
 macro_rules! with_client {
@@ -53,6 +90,14 @@ macro_rules! with_client {
                     }
                 }
                 State::Disconnected(_) => $me.connect().await,
+                State::Incompatible(ref message) => {
+                    break Err(tonic::Status::failed_precondition(message.clone()));
+                }
+                State::Dump { .. } => {
+                    break Err(tonic::Status::failed_precondition(
+                        "a dump is not connected to a live process",
+                    ));
+                }
             }
         }
     })
@@ -64,9 +109,44 @@ impl Connection {
         Self {
             target,
             state: State::Disconnected(Duration::from_secs(0)),
+            resume: None,
         }
     }
 
+    /// Returns a connection which replays the state saved in the dump file at
+    /// `path`, instead of connecting to a live process.
+    pub fn from_dump(path: &Path) -> color_eyre::Result<Self> {
+        let bytes = std::fs::read(path)
+            .wrap_err_with(|| format!("Failed to read dump file {}", path.display()))?;
+        let dump = Dump::decode(&bytes[..])
+            .wrap_err_with(|| format!("Failed to decode dump file {}", path.display()))?;
+        if let Some(protocol) = dump
+            .state
+            .as_ref()
+            .and_then(|state| state.protocol.as_ref())
+        {
+            check_protocol(protocol).map_err(|message| {
+                eyre!("Failed to read dump file {}: {}", path.display(), message)
+            })?;
+        }
+        let details = dump
+            .task_details
+            .into_iter()
+            .filter_map(|details| Some((details.task_id.as_ref()?.id, details)))
+            .collect();
+        Ok(Self {
+            // A dump is never connected to, so it has no target.
+            target: Uri::default(),
+            state: State::Dump {
+                path: path.to_owned(),
+                reason: dump.reason,
+                update: dump.state,
+                details,
+            },
+            resume: None,
+        })
+    }
+
     async fn connect(&mut self) {
         const MAX_BACKOFF: Duration = Duration::from_secs(5);
 
@@ -79,7 +159,10 @@ impl Connection {
             }
             let try_connect = async {
                 let mut client = InstrumentClient::connect(self.target.clone()).await?;
-                let request = tonic::Request::new(InstrumentRequest {});
+                let request = tonic::Request::new(InstrumentRequest {
+                    resume: self.resume.clone(),
+                    protocol: Some(Protocol::new(CAPABILITIES.iter().copied())),
+                });
                 let stream = Box::new(client.watch_updates(request).await?.into_inner());
                 Ok::<State, Box<dyn Error + Send + Sync>>(State::Connected { client, stream })
             };
@@ -88,6 +171,20 @@ impl Connection {
                     tracing::debug!("connected successfully!");
                     connected
                 }
+                // The process rejected the console's version of the protocol,
+                // so reconnecting won't help.
+                Err(error)
+                    if error
+                        .downcast_ref::<tonic::Status>()
+                        .map(tonic::Status::code)
+                        == Some(tonic::Code::FailedPrecondition) =>
+                {
+                    tracing::error!(%error, "incompatible protocol versions");
+                    let status = error
+                        .downcast::<tonic::Status>()
+                        .expect("error was just checked to be a status");
+                    State::Incompatible(status.message().to_string())
+                }
                 Err(error) => {
                     tracing::warn!(%error, "error connecting");
                     let backoff = std::cmp::max(backoff + Self::BACKOFF, MAX_BACKOFF);
@@ -101,7 +198,23 @@ impl Connection {
         loop {
             match self.state {
                 State::Connected { ref mut stream, .. } => match Pin::new(stream).next().await {
-                    Some(Ok(update)) => return update,
+                    Some(Ok(update)) => {
+                        // Only the first update of each stream, and updates
+                        // after the process's capabilities change, say which
+                        // protocol the process speaks.
+                        if let Some(ref protocol) = update.protocol {
+                            if let Err(message) = check_protocol(protocol) {
+                                tracing::error!(%message, "incompatible protocol versions");
+                                self.state = State::Incompatible(message);
+                                continue;
+                            }
+                        }
+                        self.resume = Some(ResumeCursor {
+                            clock_base: update.clock_base.clone(),
+                            seq: update.seq,
+                        });
+                        return update;
+                    }
                     Some(Err(status)) => {
                         tracing::warn!(%status, "error from stream");
                         self.state = State::Disconnected(Self::BACKOFF);
@@ -112,26 +225,40 @@ impl Connection {
                     }
                 },
                 State::Disconnected(_) => self.connect().await,
+                State::Incompatible(_) => futures::future::pending().await,
+                // A dump's state never changes, so it is the only update.
+                State::Dump { ref mut update, .. } => match update.take() {
+                    Some(update) => return update,
+                    None => futures::future::pending().await,
+                },
             }
         }
     }
 
     #[tracing::instrument(skip(self))]
-    pub async fn watch_details(
-        &mut self,
-        task_id: u64,
-    ) -> Result<Streaming<TaskDetails>, tonic::Status> {
+    pub async fn watch_details(&mut self, task_id: u64) -> Result<DetailsStream, tonic::Status> {
+        if let State::Dump { ref details, .. } = self.state {
+            let details = details.get(&task_id).cloned().ok_or_else(|| {
+                tonic::Status::not_found(format!("the dump has no details for task {}", task_id))
+            })?;
+            let stream: DetailsStream = Box::pin(stream::iter(Some(Ok(details))));
+            return Ok(stream);
+        }
+
         with_client!(self, client, {
             let request = tonic::Request::new(TaskDetailsRequest {
                 id: Some(task_id.into()),
             });
             client.watch_task_details(request).await
         })
-        .map(|watch| watch.into_inner())
+        .map(|watch| Box::pin(watch.into_inner()) as DetailsStream)
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn pause(&mut self) {
+        if self.is_dump() {
+            return;
+        }
         let res = with_client!(self, client, {
             let request = tonic::Request::new(PauseRequest {});
             client.pause(request).await
@@ -144,6 +271,9 @@ impl Connection {
 
     #[tracing::instrument(skip(self))]
     pub async fn resume(&mut self) {
+        if self.is_dump() {
+            return;
+        }
         let res = with_client!(self, client, {
             let request = tonic::Request::new(ResumeRequest {});
             client.resume(request).await
@@ -154,6 +284,10 @@ impl Connection {
         }
     }
 
+    fn is_dump(&self) -> bool {
+        matches!(self.state, State::Dump { .. })
+    }
+
     pub fn render(&self, styles: &crate::view::Styles) -> tui::text::Spans {
         use tui::{
             style::{Color, Modifier},
@@ -172,6 +306,25 @@ impl Connection {
                 format!("(RECONNECTING IN {:?})", d),
                 styles.fg(Color::Yellow).add_modifier(Modifier::BOLD),
             ),
+            State::Incompatible(ref message) => Span::styled(
+                format!("(INCOMPATIBLE: {})", message),
+                styles.fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            State::Dump {
+                ref path,
+                ref reason,
+                ..
+            } => {
+                return Spans::from(vec![
+                    Span::raw("dump: "),
+                    Span::raw(path.display().to_string()),
+                    Span::raw(" "),
+                    Span::styled(
+                        format!("({})", reason),
+                        styles.fg(Color::Cyan).add_modifier(Modifier::BOLD),
+                    ),
+                ])
+            }
         };
         Spans::from(vec![
             Span::raw("connection: "),
@@ -181,3 +334,15 @@ impl Connection {
         ])
     }
 }
+
+/// Checks that the console can understand an instrumented process which
+/// speaks `protocol`, returning why not if it can't.
+pub(crate) fn check_protocol(protocol: &Protocol) -> Result<(), String> {
+    if protocol.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "the process speaks protocol version {}, but the console speaks version {}",
+            protocol.version, PROTOCOL_VERSION
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,252 @@
+//! Finds console-enabled processes from the discovery files they write.
+//!
+//! These are written by `console-subscriber` when a server is made
+//! discoverable.
+use color_eyre::eyre::{eyre, WrapErr};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use tonic::transport::Uri;
+
+/// A console-enabled process, as described by its discovery file.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Process {
+    pub(crate) pid: u32,
+    pub(crate) name: String,
+    pub(crate) addr: SocketAddr,
+}
+
+/// Returns the directory in which console-enabled processes write their
+/// discovery files by default.
+///
+/// This must match the directory `console-subscriber` writes them to.
+pub(crate) fn default_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("tokio-console")
+}
+
+/// Returns the running processes which wrote discovery files into `dir`,
+/// ordered by their process IDs.
+pub(crate) fn processes(dir: &Path) -> color_eyre::Result<Vec<Process>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // No process has been made discoverable yet.
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).wrap_err_with(|| format!("failed to read {}", dir.display()))
+        }
+    };
+    let mut processes = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        // Files which are still being written start with a `.`.
+        let is_discovery_file = path.extension().map_or(false, |ext| ext == "json")
+            && !path
+                .file_name()
+                .map_or(true, |name| name.to_string_lossy().starts_with('.'));
+        if !is_discovery_file {
+            continue;
+        }
+        // The discovery directory may be shared with other users, who could
+        // point the console at any address.
+        match fs::metadata(&path) {
+            Ok(metadata) if is_owned_by_current_user(&metadata) => {}
+            Ok(_) => {
+                tracing::debug!(path = %path.display(), "skipping discovery file owned by another user");
+                continue;
+            }
+            Err(error) => {
+                tracing::debug!(%error, path = %path.display(), "skipping unreadable discovery file");
+                continue;
+            }
+        }
+        let process = match fs::read(&path)
+            .map_err(color_eyre::Report::from)
+            .and_then(|bytes| Ok(serde_json::from_slice::<Process>(&bytes)?))
+        {
+            Ok(process) => process,
+            Err(error) => {
+                tracing::debug!(%error, path = %path.display(), "skipping unreadable discovery file");
+                continue;
+            }
+        };
+        // A process which exits without stopping its server leaves its
+        // discovery file behind.
+        if !is_running(process.pid) {
+            tracing::debug!(path = %path.display(), "skipping discovery file of exited process");
+            continue;
+        }
+        processes.push(process);
+    }
+    processes.sort_by_key(|process| (process.pid, process.addr.port()));
+    Ok(processes)
+}
+
+/// Returns the process with the given ID, which wrote a discovery file into
+/// `dir`.
+pub(crate) fn find(dir: &Path, pid: u32) -> color_eyre::Result<Process> {
+    processes(dir)?
+        .into_iter()
+        .find(|process| process.pid == pid)
+        .ok_or_else(|| {
+            eyre!(
+                "no console-enabled process with PID {} was found in {}",
+                pid,
+                dir.display()
+            )
+        })
+}
+
+/// Prints the processes which wrote discovery files into `dir`.
+pub(crate) fn print(dir: &Path) -> color_eyre::Result<()> {
+    let processes = processes(dir)?;
+    if processes.is_empty() {
+        eprintln!(
+            "no console-enabled processes were found in {}",
+            dir.display()
+        );
+        return Ok(());
+    }
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "{:>8}  {:<21}  NAME", "PID", "ADDRESS")?;
+    for process in processes {
+        writeln!(
+            stdout,
+            "{:>8}  {:<21}  {}",
+            process.pid, process.addr, process.name
+        )?;
+    }
+    Ok(())
+}
+
+/// Asks which of the processes which wrote discovery files into `dir` to
+/// connect to.
+///
+/// If there is only one, it is chosen without asking.
+pub(crate) fn choose(dir: &Path) -> color_eyre::Result<Process> {
+    let mut processes = processes(dir)?;
+    match processes.len() {
+        0 => {
+            return Err(eyre!(
+                "no console-enabled processes were found in {}",
+                dir.display()
+            ))
+        }
+        1 => return Ok(processes.remove(0)),
+        _ => {}
+    }
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+    for (i, process) in processes.iter().enumerate() {
+        writeln!(
+            stderr,
+            "{:>3}) {} (PID {}, {})",
+            i + 1,
+            process.name,
+            process.pid,
+            process.addr
+        )?;
+    }
+    loop {
+        write!(stderr, "connect to [1-{}]: ", processes.len())?;
+        stderr.flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Err(eyre!("no process was chosen"));
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=processes.len()).contains(&n) => return Ok(processes.remove(n - 1)),
+            _ => writeln!(stderr, "enter a number between 1 and {}", processes.len())?,
+        }
+    }
+}
+
+// === impl Process ===
+
+impl Process {
+    /// Returns the address to connect to the process on.
+    pub(crate) fn target_addr(&self) -> Uri {
+        let host = match self.addr {
+            // A server bound on every interface can be reached locally.
+            SocketAddr::V4(addr) if addr.ip().is_unspecified() => {
+                SocketAddr::from(([127, 0, 0, 1], addr.port()))
+            }
+            SocketAddr::V6(addr) if addr.ip().is_unspecified() => {
+                SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, addr.port()))
+            }
+            addr => addr,
+        };
+        format!("http://{}", host)
+            .parse()
+            .expect("a socket address is a valid authority")
+    }
+}
+
+#[cfg(unix)]
+fn is_owned_by_current_user(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // Safety: `geteuid` is always successful, and has no side effects.
+    metadata.uid() == unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn is_owned_by_current_user(_: &fs::Metadata) -> bool {
+    true
+}
+
+/// Returns `false` if the process with the given ID is known to have exited.
+fn is_running(pid: u32) -> bool {
+    // Without procfs, there is no portable way to check, so assume it is
+    // still running.
+    if !Path::new("/proc/self").exists() {
+        return true;
+    }
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_running_processes_are_found() {
+        let dir = std::env::temp_dir().join(format!(
+            "tokio-console-discovery-test-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, pid: u32| {
+            let json = format!(r#"{{"pid":{},"name":"test","addr":"127.0.0.1:6669"}}"#, pid);
+            fs::write(dir.join(name), json).unwrap();
+        };
+        let running = std::process::id();
+        write(&format!("{}-6669.json", running), running);
+        // This file is still being written.
+        write(&format!(".{}-6670.json", running), running);
+        // No process can have the largest PID on Linux, so this process
+        // has exited.
+        write(&format!("{}-6669.json", u32::MAX), u32::MAX);
+
+        let processes = processes(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let pids = processes
+            .iter()
+            .map(|process| process.pid)
+            .collect::<Vec<_>>();
+        if Path::new("/proc/self").exists() {
+            assert_eq!(pids, [running]);
+        } else {
+            assert_eq!(pids, [running, u32::MAX]);
+        }
+    }
+}
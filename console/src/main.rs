@@ -1,10 +1,15 @@
 use color_eyre::{eyre::eyre, Help, SectionExt};
-use console_api::tasks::TaskDetails;
+use console_api::{
+    instrument::{instrument_client::InstrumentClient, Capability, SnapshotRequest},
+    tasks::TaskDetails,
+};
 use state::State;
 
 use clap::Parser as Clap;
 use futures::stream::StreamExt;
+use std::{convert::TryFrom, io::Write, time::Duration};
 use tokio::sync::{mpsc, watch};
+use tonic::transport::Uri;
 use tui::{
     layout::{Constraint, Direction, Layout},
     style::Color,
@@ -16,6 +21,7 @@ use crate::view::{bold, UpdateKind};
 
 mod config;
 mod conn;
+mod discovery;
 mod input;
 mod intern;
 mod state;
@@ -29,17 +35,47 @@ async fn main() -> color_eyre::Result<()> {
     let mut args = config::Config::parse();
     let retain_for = args.retain_for();
     args.trace_init()?;
+    let discovery_dir = args.discovery_dir();
+    if let Some(pid) = args.pid {
+        args.target_addr = discovery::find(&discovery_dir, pid)?.target_addr();
+    }
     tracing::debug!(?args.target_addr, ?args.view_options);
 
+    let dump = match args.subcommand.take() {
+        Some(config::Subcmd::Snapshot(options)) => {
+            return print_snapshot(args.target_addr, options).await
+        }
+        Some(config::Subcmd::Settings(options)) => {
+            return update_settings(args.target_addr, options).await
+        }
+        Some(config::Subcmd::Open(options)) => Some(options.path),
+        Some(config::Subcmd::List) => return discovery::print(&discovery_dir),
+        Some(config::Subcmd::Choose) => {
+            args.target_addr = discovery::choose(&discovery_dir)?.target_addr();
+            None
+        }
+        None => None,
+    };
+
     let styles = view::Styles::from_config(args.view_options);
     styles.error_init()?;
 
-    let target = args.target_addr;
-    tracing::info!(?target, "using target addr");
+    // Read the dump before taking over the terminal, so that errors reading
+    // it are printed normally.
+    let mut conn = match dump {
+        Some(path) => {
+            tracing::info!(?path, "opening dump");
+            conn::Connection::from_dump(&path)?
+        }
+        None => {
+            let target = args.target_addr;
+            tracing::info!(?target, "using target addr");
+            conn::Connection::new(target)
+        }
+    };
 
     let (mut terminal, _cleanup) = term::init_crossterm()?;
     terminal.clear()?;
-    let mut conn = conn::Connection::new(target);
     // A channel to send the outcome of `View::update_input` to the watch_details_stream task.
     let (update_tx, update_rx) = watch::channel(UpdateKind::Other);
     // A channel to send the task details update stream (no need to keep outdated details in the memory)
@@ -51,6 +87,8 @@ async fn main() -> color_eyre::Result<()> {
         .with_task_linters(vec![
             warnings::Linter::new(warnings::SelfWakePercent::default()),
             warnings::Linter::new(warnings::LostWaker),
+            warnings::Linter::new(warnings::BlockingPoll),
+            warnings::Linter::new(warnings::EventsDropped),
         ])
         .with_retain_for(retain_for);
     let mut input = input::EventStream::new();
@@ -80,7 +118,7 @@ async fn main() -> color_eyre::Result<()> {
                 // Using the result of update_input to manage the details watcher task
                 let _ = update_tx.send(update_kind);
                 match update_kind {
-                    UpdateKind::SelectTask(task_id) => {
+                    UpdateKind::SelectTask(task_id) if state.supports(Capability::TaskDetails) => {
                         match conn.watch_details(task_id).await {
                             Ok(stream) => {
                                 tokio::spawn(watch_details_stream(task_id, stream, update_rx.clone(), details_tx.clone()));
@@ -121,20 +159,30 @@ async fn main() -> color_eyre::Result<()> {
                 .split(f.size());
 
             let mut header_text = conn.render(&view.styles);
+            if let Some(process) = state.process_info() {
+                header_text.0.push(Span::raw(format!(
+                    " {} (PID {})",
+                    process.name, process.pid
+                )));
+                if let Some(uptime) = state.uptime() {
+                    header_text
+                        .0
+                        .push(Span::raw(format!(", up {}", view::format_uptime(uptime))));
+                }
+            }
             if state.is_paused() {
                 header_text
                     .0
                     .push(Span::styled(" PAUSED", view.styles.fg(Color::Red)));
             }
             let header = Paragraph::new(header_text).wrap(Wrap { trim: true });
-            let view_controls = Paragraph::new(Spans::from(vec![
-                Span::raw("views: "),
-                bold("t"),
-                Span::raw(" = tasks, "),
-                bold("r"),
-                Span::raw(" = resources"),
-            ]))
-            .wrap(Wrap { trim: true });
+            let mut view_controls = vec![Span::raw("views: "), bold("t"), Span::raw(" = tasks, ")];
+            if state.supports(Capability::Resources) {
+                view_controls.extend([bold("r"), Span::raw(" = resources, ")]);
+            }
+            view_controls.extend([bold("i"), Span::raw(" = process info")]);
+            let view_controls =
+                Paragraph::new(Spans::from(view_controls)).wrap(Wrap { trim: true });
 
             f.render_widget(header, chunks[0]);
             f.render_widget(view_controls, chunks[1]);
@@ -143,6 +191,55 @@ async fn main() -> color_eyre::Result<()> {
     }
 }
 
+/// Prints a snapshot of the current state of the process at `target`.
+///
+/// Unlike the TUI, this doesn't retry if the process can't be reached, so
+/// that scripts can tell that it failed.
+async fn print_snapshot(target: Uri, options: config::SnapshotOptions) -> color_eyre::Result<()> {
+    let mut client = InstrumentClient::connect(target).await?;
+    let request = tonic::Request::new(SnapshotRequest {
+        include: options.include().map(|kind| kind as i32).collect(),
+    });
+    let update = client.get_snapshot(request).await?.into_inner();
+    if let Some(ref protocol) = update.protocol {
+        conn::check_protocol(protocol).map_err(|message| eyre!(message))?;
+    }
+    let snapshot = state::snapshot::Snapshot::from_proto(update);
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    match options.format {
+        config::SnapshotFormat::Json => serde_json::to_writer_pretty(&mut stdout, &snapshot)?,
+    }
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Applies the changes in `options` to the settings of the process at
+/// `target`, and prints the settings in effect afterwards.
+async fn update_settings(target: Uri, options: config::SettingsOptions) -> color_eyre::Result<()> {
+    let mut client = InstrumentClient::connect(target).await?;
+    let request = tonic::Request::new(options.request());
+    let settings = client.update_settings(request).await?.into_inner();
+
+    let duration = |duration: Option<prost_types::Duration>| {
+        duration
+            .and_then(|duration| Duration::try_from(duration).ok())
+            .map(|duration| humantime::format_duration(duration).to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    };
+    let tracked = |tracked| if tracked { "tracked" } else { "not tracked" };
+    println!("publish interval: {}", duration(settings.publish_interval));
+    println!("retention:        {}", duration(settings.retention));
+    println!(
+        "recording:        {}",
+        settings.recording_path.as_deref().unwrap_or("off")
+    );
+    println!("resources:        {}", tracked(settings.track_resources));
+    println!("async ops:        {}", tracked(settings.track_async_ops));
+    Ok(())
+}
+
 /// Given the task details stream for the given task id, sends the updates
 /// to the `details_tx` channel until the currently-viewed task changes.
 ///
@@ -151,7 +248,7 @@ async fn main() -> color_eyre::Result<()> {
 /// replace the details stream with another one.
 async fn watch_details_stream(
     task_id: u64,
-    mut details_stream: tonic::Streaming<TaskDetails>,
+    mut details_stream: conn::DetailsStream,
     mut watch_rx: watch::Receiver<UpdateKind>,
     details_tx: mpsc::Sender<TaskDetails>,
 ) {
@@ -185,6 +185,11 @@ impl AsyncOpsState {
                 }
             }
         }
+
+        // The application has stopped tracking evicted async ops.
+        for id in update.evicted_async_ops {
+            self.async_ops.remove(&id.id);
+        }
     }
 
     pub(crate) fn retain_active(&mut self, now: SystemTime, retain_for: Duration) {
@@ -15,7 +15,7 @@ use std::{
     rc::Rc,
     time::{Duration, SystemTime},
 };
-use tasks::{Details, Task, TasksState};
+use tasks::{Details, Task, TasksState, UserSpan};
 use tui::{
     style::{Color, Modifier},
     text::Span,
@@ -23,6 +23,7 @@ use tui::{
 
 pub mod async_ops;
 pub mod resources;
+pub mod snapshot;
 pub mod tasks;
 
 pub(crate) type DetailsRef = Rc<RefCell<Option<Details>>>;
@@ -38,16 +39,44 @@ pub(crate) struct State {
     current_task_details: DetailsRef,
     retain_for: Option<Duration>,
     strings: intern::Strings,
+    process_info: Option<ProcessInfo>,
+    /// The capabilities the instrumented process supports, if it has said.
+    capabilities: Option<Vec<proto::instrument::Capability>>,
 }
 pub(crate) enum Visibility {
     Show,
     Hide,
 }
 
+/// Information about the instrumented process.
+#[derive(Debug)]
+pub(crate) struct ProcessInfo {
+    pub(crate) pid: u32,
+    /// The name of the process's executable, or the name it was invoked by.
+    pub(crate) name: String,
+    pub(crate) executable: String,
+    pub(crate) command_line: String,
+    pub(crate) started_at: Option<SystemTime>,
+    pub(crate) subscriber_version: String,
+    pub(crate) tokio_version: Option<String>,
+    /// Labels set by the application, sorted by their keys.
+    pub(crate) labels: Vec<(String, String)>,
+}
+
+/// The capabilities which instrumented processes that predate protocol
+/// versions support.
+const LEGACY_CAPABILITIES: &[proto::instrument::Capability] = &[
+    proto::instrument::Capability::TaskDetails,
+    proto::instrument::Capability::Resources,
+    proto::instrument::Capability::AsyncOps,
+];
+
 #[derive(Debug)]
 pub(crate) struct Metadata {
     field_names: Vec<InternedStr>,
+    name: InternedStr,
     target: InternedStr,
+    location: String,
     id: u64,
     //TODO: add more metadata as needed
 }
@@ -107,6 +136,14 @@ impl State {
             self.last_updated_at = Some(now);
         }
 
+        if let Some(process_info) = update.process_info {
+            self.process_info = Some(ProcessInfo::from_proto(process_info));
+        }
+
+        if let Some(protocol) = update.protocol {
+            self.capabilities = Some(protocol.capabilities().collect());
+        }
+
         let strings = &mut self.strings;
         if let Some(new_metadata) = update.new_metadata {
             let metas = new_metadata.metadata.into_iter().filter_map(|meta| {
@@ -179,6 +216,25 @@ impl State {
         self.strings.retain_referenced();
     }
 
+    pub(crate) fn process_info(&self) -> Option<&ProcessInfo> {
+        self.process_info.as_ref()
+    }
+
+    /// Returns whether the instrumented process supports `capability`.
+    pub(crate) fn supports(&self, capability: proto::instrument::Capability) -> bool {
+        self.capabilities
+            .as_deref()
+            .unwrap_or(LEGACY_CAPABILITIES)
+            .contains(&capability)
+    }
+
+    /// Returns how long the instrumented process has been running for, as of
+    /// the last update.
+    pub(crate) fn uptime(&self) -> Option<Duration> {
+        let started_at = self.process_info.as_ref()?.started_at?;
+        self.last_updated_at?.duration_since(started_at).ok()
+    }
+
     pub(crate) fn task_details_ref(&self) -> DetailsRef {
         self.current_task_details.clone()
     }
@@ -205,19 +261,44 @@ impl State {
 
     pub(crate) fn update_task_details(&mut self, update: proto::tasks::TaskDetails) {
         if let Some(id) = update.task_id {
-            let details = Details {
+            let mut details = Details {
                 task_id: id.id,
                 poll_times_histogram: update.poll_times_histogram.and_then(|data| {
                     hdrhistogram::serialization::Deserializer::new()
                         .deserialize(&mut Cursor::new(&data))
                         .ok()
                 }),
+                async_backtrace: update
+                    .async_backtrace
+                    .iter()
+                    .map(|meta_id| self.user_span(meta_id.id))
+                    .collect(),
+                span_busy_times: update
+                    .span_busy_times
+                    .into_iter()
+                    .filter_map(|span| {
+                        let busy_time = pb_duration(span.busy_time?);
+                        Some((self.user_span(span.metadata?.id), busy_time))
+                    })
+                    .collect(),
+                woken_by: self.tasks_state.wake_counts(update.woken_by),
+                woke: self.tasks_state.wake_counts(update.woke),
             };
+            details
+                .span_busy_times
+                .sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
 
             *self.current_task_details.borrow_mut() = Some(details);
         }
     }
 
+    fn user_span(&mut self, meta_id: u64) -> UserSpan {
+        match self.metas.get(&meta_id) {
+            Some(meta) => UserSpan::from_metadata(meta),
+            None => UserSpan::unknown(&mut self.strings),
+        }
+    }
+
     pub(crate) fn unset_task_details(&mut self) {
         *self.current_task_details.borrow_mut() = None;
     }
@@ -243,6 +324,30 @@ impl Default for Temporality {
     }
 }
 
+// === impl ProcessInfo ===
+
+impl ProcessInfo {
+    fn from_proto(pb: proto::instrument::ProcessInfo) -> Self {
+        let name = std::path::Path::new(&pb.executable)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .or_else(|| pb.args.first().cloned())
+            .unwrap_or_default();
+        let mut labels = pb.labels.into_iter().collect::<Vec<_>>();
+        labels.sort();
+        Self {
+            pid: pb.pid,
+            name,
+            executable: pb.executable,
+            command_line: pb.args.join(" "),
+            started_at: pb.started_at.and_then(|t| t.try_into().ok()),
+            subscriber_version: pb.subscriber_version,
+            tokio_version: Some(pb.tokio_version).filter(|version| !version.is_empty()),
+            labels,
+        }
+    }
+}
+
 impl Metadata {
     fn from_proto(pb: proto::Metadata, id: u64, strings: &mut intern::Strings) -> Self {
         Self {
@@ -251,7 +356,9 @@ impl Metadata {
                 .into_iter()
                 .map(|n| strings.string(n))
                 .collect(),
+            name: strings.string(pb.name),
             target: strings.string(pb.target),
+            location: format_location(pb.location),
             id,
         }
     }
@@ -223,6 +223,11 @@ impl ResourcesState {
                 }
             }
         }
+
+        // The application has stopped tracking evicted resources.
+        for id in update.evicted_resources {
+            self.resources.remove(&id.id);
+        }
     }
 
     pub(crate) fn retain_active(&mut self, now: SystemTime, retain_for: Duration) {
@@ -332,7 +337,7 @@ impl ResourceStats {
     }
 }
 
-fn kind_from_proto(
+pub(super) fn kind_from_proto(
     pb: proto::resources::resource::Kind,
     strings: &mut intern::Strings,
 ) -> Result<InternedStr, String> {
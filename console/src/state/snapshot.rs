@@ -0,0 +1,289 @@
+//! A point-in-time snapshot of an instrumented process's state, which can be
+//! serialized, rather than displayed in the TUI.
+use crate::{
+    intern,
+    state::{
+        format_location, pb_duration, resources::kind_from_proto, Field, FieldValue, Metadata,
+    },
+};
+use console_api as proto;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    time::SystemTime,
+};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Snapshot {
+    /// When the snapshot was taken.
+    now: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    process: Option<Process>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tasks: Option<Vec<Task>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resources: Option<Vec<Resource>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    async_ops: Option<Vec<AsyncOp>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Process {
+    pid: u32,
+    executable: String,
+    args: Vec<String>,
+    started_at: Option<String>,
+    subscriber_version: String,
+    tokio_version: Option<String>,
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Task {
+    id: u64,
+    name: Option<String>,
+    target: String,
+    location: String,
+    fields: BTreeMap<String, serde_json::Value>,
+    state: &'static str,
+    created_at: Option<String>,
+    dropped_at: Option<String>,
+    polls: u64,
+    busy_secs: f64,
+    wakes: u64,
+    waker_count: u64,
+    self_wakes: u64,
+    blocking_poll_secs: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Resource {
+    id: u64,
+    parent_id: Option<u64>,
+    kind: String,
+    concrete_type: String,
+    target: String,
+    location: String,
+    is_internal: bool,
+    created_at: Option<String>,
+    dropped_at: Option<String>,
+    attributes: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct AsyncOp {
+    id: u64,
+    parent_id: Option<u64>,
+    resource_id: Option<u64>,
+    task_id: Option<u64>,
+    source: String,
+    created_at: Option<String>,
+    dropped_at: Option<String>,
+    polls: u64,
+    busy_secs: f64,
+    attributes: BTreeMap<String, serde_json::Value>,
+}
+
+impl Snapshot {
+    pub(crate) fn from_proto(update: proto::instrument::Update) -> Self {
+        let mut strings = intern::Strings::default();
+        let metas = update
+            .new_metadata
+            .map(|new_metadata| new_metadata.metadata)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|meta| {
+                let id = meta.id?.id;
+                let metadata = meta.metadata?;
+                Some((id, Metadata::from_proto(metadata, id, &mut strings)))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let process = update.process_info.map(|info| Process {
+            pid: info.pid,
+            executable: info.executable,
+            args: info.args,
+            started_at: timestamp(info.started_at),
+            subscriber_version: info.subscriber_version,
+            tokio_version: Some(info.tokio_version).filter(|version| !version.is_empty()),
+            labels: info.labels.into_iter().collect(),
+        });
+
+        let tasks = update.task_update.map(|update| {
+            let mut stats = update.stats_update;
+            let mut tasks = update
+                .new_tasks
+                .into_iter()
+                .filter_map(|task| {
+                    let id = task.id?.id;
+                    let meta = metas.get(&task.metadata?.id)?;
+                    let stats = stats.remove(&id)?;
+                    let mut fields = fields(task.fields, meta, &mut strings);
+                    let name = fields.remove(Field::NAME).map(|name| match name {
+                        serde_json::Value::String(name) => name,
+                        name => name.to_string(),
+                    });
+                    let poll_stats = stats.poll_stats.unwrap_or_default();
+                    let state = if stats.dropped_at.is_some() {
+                        "completed"
+                    } else if is_polling(&poll_stats) {
+                        "running"
+                    } else {
+                        "idle"
+                    };
+                    Some(Task {
+                        id,
+                        name,
+                        target: meta.target.to_string(),
+                        location: format_location(task.location).trim_end().to_string(),
+                        fields,
+                        state,
+                        created_at: timestamp(stats.created_at),
+                        dropped_at: timestamp(stats.dropped_at),
+                        polls: poll_stats.polls,
+                        busy_secs: secs(poll_stats.busy_time),
+                        wakes: stats.wakes,
+                        waker_count: stats.waker_clones.saturating_sub(stats.waker_drops),
+                        self_wakes: stats.self_wakes,
+                        blocking_poll_secs: stats
+                            .blocking_poll
+                            .map(|dur| pb_duration(dur).as_secs_f64()),
+                    })
+                })
+                .collect::<Vec<_>>();
+            tasks.sort_by_key(|task| task.id);
+            tasks
+        });
+
+        let resources = update.resource_update.map(|update| {
+            let mut stats = update.stats_update;
+            let mut resources = update
+                .new_resources
+                .into_iter()
+                .filter_map(|resource| {
+                    let id = resource.id?.id;
+                    let meta = metas.get(&resource.metadata?.id)?;
+                    let stats = stats.remove(&id)?;
+                    let kind = kind_from_proto(resource.kind?, &mut strings).ok()?;
+                    Some(Resource {
+                        id,
+                        parent_id: resource.parent_resource_id.map(|id| id.id),
+                        kind: kind.to_string(),
+                        concrete_type: resource.concrete_type,
+                        target: meta.target.to_string(),
+                        location: format_location(resource.location).trim_end().to_string(),
+                        is_internal: resource.is_internal,
+                        created_at: timestamp(stats.created_at),
+                        dropped_at: timestamp(stats.dropped_at),
+                        attributes: attributes(stats.attributes, meta, &mut strings),
+                    })
+                })
+                .collect::<Vec<_>>();
+            resources.sort_by_key(|resource| resource.id);
+            resources
+        });
+
+        let async_ops = update.async_op_update.map(|update| {
+            let mut stats = update.stats_update;
+            let mut async_ops = update
+                .new_async_ops
+                .into_iter()
+                .filter_map(|async_op| {
+                    let id = async_op.id?.id;
+                    let meta = metas.get(&async_op.metadata?.id)?;
+                    let stats = stats.remove(&id)?;
+                    let poll_stats = stats.poll_stats.unwrap_or_default();
+                    Some(AsyncOp {
+                        id,
+                        parent_id: async_op.parent_async_op_id.map(|id| id.id),
+                        resource_id: async_op.resource_id.map(|id| id.id),
+                        task_id: stats.task_id.map(|id| id.id),
+                        source: async_op.source,
+                        created_at: timestamp(stats.created_at),
+                        dropped_at: timestamp(stats.dropped_at),
+                        polls: poll_stats.polls,
+                        busy_secs: secs(poll_stats.busy_time),
+                        attributes: attributes(stats.attributes, meta, &mut strings),
+                    })
+                })
+                .collect::<Vec<_>>();
+            async_ops.sort_by_key(|async_op| async_op.id);
+            async_ops
+        });
+
+        Self {
+            now: timestamp(update.now),
+            process,
+            tasks,
+            resources,
+            async_ops,
+        }
+    }
+}
+
+fn fields(
+    fields: Vec<proto::Field>,
+    meta: &Metadata,
+    strings: &mut intern::Strings,
+) -> BTreeMap<String, serde_json::Value> {
+    fields
+        .into_iter()
+        .filter_map(|pb| {
+            let field = Field::from_proto(pb, meta, strings)?;
+            Some((field.name.to_string(), json_value(field.value)))
+        })
+        .collect()
+}
+
+/// Attributes with a unit are serialized as strings including the unit, such
+/// as `"5ms"`.
+fn attributes(
+    attributes: Vec<proto::Attribute>,
+    meta: &Metadata,
+    strings: &mut intern::Strings,
+) -> BTreeMap<String, serde_json::Value> {
+    attributes
+        .into_iter()
+        .filter_map(|pb| {
+            let field = Field::from_proto(pb.field?, meta, strings)?;
+            let value = match pb.unit {
+                Some(unit) => serde_json::Value::String(format!("{}{}", field.value, unit)),
+                None => json_value(field.value),
+            };
+            Some((field.name.to_string(), value))
+        })
+        .collect()
+}
+
+fn json_value(value: FieldValue) -> serde_json::Value {
+    match value {
+        FieldValue::Bool(v) => v.into(),
+        FieldValue::Str(v) | FieldValue::Debug(v) => v.into(),
+        FieldValue::U64(v) => v.into(),
+        FieldValue::I64(v) => v.into(),
+    }
+}
+
+fn is_polling(stats: &proto::PollStats) -> bool {
+    let started = stats.last_poll_started.clone().map(to_system_time);
+    let ended = stats.last_poll_ended.clone().map(to_system_time);
+    match (started, ended) {
+        (Some(started), Some(ended)) => started > ended,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+fn to_system_time(timestamp: prost_types::Timestamp) -> SystemTime {
+    timestamp.try_into().unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+fn timestamp(timestamp: Option<prost_types::Timestamp>) -> Option<String> {
+    let time = to_system_time(timestamp?);
+    Some(humantime::format_rfc3339_nanos(time).to_string())
+}
+
+fn secs(duration: Option<prost_types::Duration>) -> f64 {
+    duration.map(pb_duration).unwrap_or_default().as_secs_f64()
+}
@@ -21,12 +21,33 @@ pub(crate) struct TasksState {
     tasks: HashMap<u64, Rc<RefCell<Task>>>,
     new_tasks: Vec<TaskRef>,
     pub(crate) linters: Vec<Linter<Task>>,
+    /// The number of tasks which the application didn't track, because it was
+    /// already tracking as many tasks as it is limited to.
+    untracked: u64,
 }
 
 #[derive(Debug, Default)]
 pub(crate) struct Details {
     pub(crate) task_id: u64,
     pub(crate) poll_times_histogram: Option<Histogram<u64>>,
+    pub(crate) async_backtrace: Vec<UserSpan>,
+    /// The task's busy time spent inside of each user span, in descending
+    /// order.
+    pub(crate) span_busy_times: Vec<(UserSpan, Duration)>,
+    /// The tasks which have woken this task, and how many times, in
+    /// descending order.
+    pub(crate) woken_by: Vec<(String, u64)>,
+    /// The tasks which this task has woken, and how many times, in descending
+    /// order.
+    pub(crate) woke: Vec<(String, u64)>,
+}
+
+/// A user-defined span that was entered while a task was being polled.
+#[derive(Debug)]
+pub(crate) struct UserSpan {
+    name: InternedStr,
+    target: InternedStr,
+    location: String,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -90,6 +111,13 @@ struct TaskStats {
     last_wake: Option<SystemTime>,
     /// Total number of times the task has woken itself.
     self_wakes: u64,
+
+    /// How long the task's current poll had been running for, if the
+    /// subscriber reported that it is blocking its worker thread.
+    blocking_poll: Option<Duration>,
+
+    /// Whether the subscriber dropped any of the task's events.
+    events_dropped: bool,
 }
 
 impl TasksState {
@@ -182,6 +210,12 @@ impl TasksState {
                 task.lint(linters);
             }
         }
+
+        // The application has stopped tracking evicted tasks.
+        for id in update.evicted_tasks {
+            self.tasks.remove(&id.id);
+        }
+        self.untracked += update.untracked_tasks;
     }
 
     pub(crate) fn retain_active(&mut self, now: SystemTime, retain_for: Duration) {
@@ -202,9 +236,33 @@ impl TasksState {
         self.linters.iter().filter(|linter| linter.count() > 0)
     }
 
+    /// Returns the number of tasks which the application didn't track, because
+    /// it was already tracking as many tasks as it is limited to.
+    pub(crate) fn untracked(&self) -> u64 {
+        self.untracked
+    }
+
     pub(crate) fn task(&self, id: u64) -> Option<TaskRef> {
         self.tasks.get(&id).map(Rc::downgrade)
     }
+
+    /// Converts wake counts between a task and other tasks into a list of
+    /// task descriptions, sorted from the most wakes to the fewest.
+    pub(crate) fn wake_counts(&self, counts: Vec<proto::tasks::WakeCount>) -> Vec<(String, u64)> {
+        let mut counts: Vec<_> = counts
+            .into_iter()
+            .filter_map(|count| {
+                let id = count.task_id?.id;
+                let desc = match self.tasks.get(&id) {
+                    Some(task) => task.borrow().short_desc().to_string(),
+                    None => format!("{} (no longer tracked)", id),
+                };
+                Some((desc, count.wakes))
+            })
+            .collect();
+        counts.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        counts
+    }
 }
 
 impl Details {
@@ -215,6 +273,60 @@ impl Details {
     pub(crate) fn poll_times_histogram(&self) -> Option<&Histogram<u64>> {
         self.poll_times_histogram.as_ref()
     }
+
+    /// Returns the task's logical async backtrace at the end of its most
+    /// recent poll, from the outermost span to the innermost.
+    pub(crate) fn async_backtrace(&self) -> &[UserSpan] {
+        &self.async_backtrace
+    }
+
+    /// Returns the task's busy time spent inside of each user span, from the
+    /// span the task spent the most time in to the least.
+    pub(crate) fn span_busy_times(&self) -> &[(UserSpan, Duration)] {
+        &self.span_busy_times
+    }
+
+    /// Returns a description of each task that has woken this task, along
+    /// with how many times it did so, from the most wakes to the fewest.
+    pub(crate) fn woken_by(&self) -> &[(String, u64)] {
+        &self.woken_by
+    }
+
+    /// Returns a description of each task that this task has woken, along
+    /// with how many times it did so, from the most wakes to the fewest.
+    pub(crate) fn woke(&self) -> &[(String, u64)] {
+        &self.woke
+    }
+}
+
+impl UserSpan {
+    pub(crate) fn from_metadata(meta: &Metadata) -> Self {
+        Self {
+            name: meta.name.clone(),
+            target: meta.target.clone(),
+            location: meta.location.clone(),
+        }
+    }
+
+    pub(crate) fn unknown(strings: &mut intern::Strings) -> Self {
+        Self {
+            name: strings.string("<unknown span>".to_string()),
+            target: strings.string(String::new()),
+            location: "<unknown location>".to_string(),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub(crate) fn location(&self) -> &str {
+        &self.location
+    }
 }
 
 impl Task {
@@ -340,6 +452,19 @@ impl Task {
         self.total_polls() == 0 || self.last_wake() > self.stats.last_poll_started
     }
 
+    /// Returns how long the task's current poll has been running for, if the
+    /// task has been polled for long enough that it is blocking the worker
+    /// thread it is running on.
+    pub(crate) fn blocking_poll(&self) -> Option<Duration> {
+        self.stats.blocking_poll
+    }
+
+    /// Returns `true` if the subscriber dropped any of this task's events, so
+    /// that its stats may be wrong.
+    pub(crate) fn events_dropped(&self) -> bool {
+        self.stats.events_dropped
+    }
+
     pub(crate) fn warnings(&self) -> &[Linter<Task>] {
         &self.warnings[..]
     }
@@ -388,6 +513,8 @@ impl From<proto::tasks::Stats> for TaskStats {
             waker_drops: pb.waker_drops,
             last_wake: pb.last_wake.map(|v| v.try_into().unwrap()),
             self_wakes: pb.self_wakes,
+            blocking_poll: pb.blocking_poll.map(pb_duration),
+            events_dropped: pb.events_dropped,
         }
     }
 }
@@ -1,5 +1,6 @@
 use crate::view::{resources::ResourcesTable, table::TableListState, tasks::TasksTable};
 use crate::{input, state::State};
+use console_api::instrument::Capability;
 use std::{borrow::Cow, cmp};
 use tui::{
     layout,
@@ -9,12 +10,14 @@ use tui::{
 
 mod async_ops;
 mod mini_histogram;
+mod process;
 mod resource;
 mod resources;
 mod styles;
 mod table;
 mod task;
 mod tasks;
+pub(crate) use self::process::format_uptime;
 pub(crate) use self::styles::{Palette, Styles};
 pub(crate) use self::table::SortBy;
 
@@ -48,6 +51,8 @@ pub(crate) enum ViewState {
     TaskInstance(self::task::TaskView),
     /// Inspecting a single resource instance.
     ResourceInstance(self::resource::ResourceView),
+    /// Information about the instrumented process.
+    ProcessInfo,
 }
 
 /// The outcome of the update_input method
@@ -110,9 +115,12 @@ impl View {
                             ));
                         }
                     }
-                    key!(Char('r')) => {
+                    key!(Char('r')) if state.supports(Capability::Resources) => {
                         self.state = ResourcesList;
                     }
+                    key!(Char('i')) => {
+                        self.state = ProcessInfo;
+                    }
                     _ => {
                         // otherwise pass on to view
                         self.tasks_list.update_input(event);
@@ -130,12 +138,24 @@ impl View {
                     key!(Char('t')) => {
                         self.state = TasksList;
                     }
+                    key!(Char('i')) => {
+                        self.state = ProcessInfo;
+                    }
                     _ => {
                         // otherwise pass on to view
                         self.resources_list.update_input(event);
                     }
                 }
             }
+            ProcessInfo => match event {
+                key!(Esc) | key!(Char('t')) => {
+                    self.state = TasksList;
+                }
+                key!(Char('r')) if state.supports(Capability::Resources) => {
+                    self.state = ResourcesList;
+                }
+                _ => {}
+            },
             ResourceInstance(ref mut view) => {
                 // The escape key changes views, so handle here since we can
                 // mutate the currently selected view.
@@ -186,11 +206,15 @@ impl View {
                 let now = state
                     .last_updated_at()
                     .expect("task view implies we've received an update");
-                view.render(&self.styles, frame, area, now);
+                let details_supported = state.supports(Capability::TaskDetails);
+                view.render(&self.styles, frame, area, now, details_supported);
             }
             ViewState::ResourceInstance(ref mut view) => {
                 view.render(&self.styles, frame, area, state);
             }
+            ViewState::ProcessInfo => {
+                self::process::render(&self.styles, frame, area, state);
+            }
         }
 
         state.retain_active();
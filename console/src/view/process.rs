@@ -0,0 +1,101 @@
+use crate::{
+    state::State,
+    view::{self, bold},
+};
+use console_api::instrument::Capability;
+use std::time::Duration;
+use tui::{
+    layout::{self, Layout},
+    text::{Span, Spans},
+    widgets::{Block, Paragraph, Wrap},
+};
+
+/// Renders the information about the instrumented process.
+pub(crate) fn render<B: tui::backend::Backend>(
+    styles: &view::Styles,
+    frame: &mut tui::terminal::Frame<B>,
+    area: layout::Rect,
+    state: &State,
+) {
+    let chunks = Layout::default()
+        .direction(layout::Direction::Vertical)
+        .constraints(
+            [
+                // controls
+                layout::Constraint::Length(1),
+                // process info
+                layout::Constraint::Min(1),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+    let (controls_area, info_area) = (chunks[0], chunks[1]);
+
+    let controls = Spans::from(vec![
+        Span::raw("controls: "),
+        bold(styles.if_utf8("\u{238B} esc", "esc")),
+        Span::raw(" = return to task list, "),
+        bold("q"),
+        Span::raw(" = quit"),
+    ]);
+
+    let info = match state.process_info() {
+        Some(info) => {
+            let started_at = info
+                .started_at
+                .map(|started_at| humantime::format_rfc3339_seconds(started_at).to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            let uptime = state
+                .uptime()
+                .map(format_uptime)
+                .unwrap_or_else(|| "unknown".to_string());
+            let mut info_text = vec![
+                Spans::from(vec![bold("PID: "), Span::raw(info.pid.to_string())]),
+                Spans::from(vec![bold("Executable: "), Span::raw(&info.executable[..])]),
+                Spans::from(vec![
+                    bold("Command line: "),
+                    Span::raw(&info.command_line[..]),
+                ]),
+                Spans::from(vec![bold("Started at: "), Span::raw(started_at)]),
+                Spans::from(vec![bold("Uptime: "), Span::raw(uptime)]),
+                Spans::from(vec![
+                    bold("console-subscriber version: "),
+                    Span::raw(&info.subscriber_version[..]),
+                ]),
+                Spans::from(vec![
+                    bold("Tokio version: "),
+                    Span::raw(info.tokio_version.as_deref().unwrap_or("unknown")),
+                ]),
+            ];
+            if !info.labels.is_empty() {
+                info_text.push(Spans::from(bold("Labels:")));
+                info_text.extend(info.labels.iter().map(|(key, value)| {
+                    Spans::from(vec![
+                        Span::raw("  "),
+                        bold(format!("{}: ", key)),
+                        Span::raw(&value[..]),
+                    ])
+                }));
+            }
+            info_text
+        }
+        None if !state.supports(Capability::ProcessInfo) => vec![Spans::from(Span::raw(
+            "the process doesn't send information about itself",
+        ))],
+        None => vec![Spans::from(Span::raw(
+            "the process hasn't sent any information about itself yet",
+        ))],
+    };
+
+    let info_widget = Paragraph::new(info)
+        .wrap(Wrap { trim: false })
+        .block(styles.border_block().title("Process"));
+
+    frame.render_widget(Block::default().title(controls), controls_area);
+    frame.render_widget(info_widget, info_area);
+}
+
+/// Formats how long the process has been running for, to the second.
+pub(crate) fn format_uptime(uptime: Duration) -> String {
+    humantime::format_duration(Duration::from_secs(uptime.as_secs())).to_string()
+}
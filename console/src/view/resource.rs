@@ -8,6 +8,7 @@ use crate::{
         bold, TableListState,
     },
 };
+use console_api::instrument::Capability;
 use std::{cell::RefCell, rc::Rc};
 use tui::{
     layout::{self, Layout},
@@ -110,6 +111,13 @@ impl ResourceView {
         frame.render_widget(Block::default().title(controls), controls_area);
         frame.render_widget(resource_widget, stats_area[0]);
         frame.render_widget(fields_widget, stats_area[1]);
+        if !state.supports(Capability::AsyncOps) {
+            let async_ops_widget =
+                Paragraph::new("async ops are not supported by the instrumented process")
+                    .block(styles.border_block().title(vec![bold("Async Ops")]));
+            frame.render_widget(async_ops_widget, async_ops_area);
+            return;
+        }
         let ctx = AsyncOpsTableCtx {
             initial_render: self.initial_render,
             resource_id: resource.id(),
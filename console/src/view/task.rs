@@ -21,6 +21,12 @@ use tui::{
     widgets::{Block, List, ListItem, Paragraph},
 };
 
+/// The number of user spans to list in the busy time breakdown.
+const MAX_SPAN_BUSY_TIMES: usize = 5;
+
+/// The number of tasks to list as waking, or being woken by, a task.
+const MAX_WAKE_COUNTS: usize = 5;
+
 pub(crate) struct TaskView {
     task: Rc<RefCell<Task>>,
     details: DetailsRef,
@@ -41,6 +47,7 @@ impl TaskView {
         frame: &mut tui::terminal::Frame<B>,
         area: layout::Rect,
         now: SystemTime,
+        details_supported: bool,
     ) {
         // Rows with the following info:
         // - Task main attributes
@@ -184,6 +191,14 @@ impl TaskView {
         overview.push(dur_percent("Busy: ", task.busy(now)));
         overview.push(dur_percent("Idle: ", task.idle(now)));
 
+        if let Some(blocking_poll) = task.blocking_poll() {
+            overview.push(Spans::from(vec![
+                styles.warning_narrow(),
+                bold("Polling for: "),
+                dur(styles, blocking_poll),
+            ]));
+        }
+
         let mut waker_stats = vec![Spans::from(vec![
             bold("Current wakers: "),
             Span::from(format!("{} (", task.waker_count())),
@@ -222,6 +237,128 @@ impl TaskView {
         let mut fields = Text::default();
         fields.extend(task.formatted_fields().iter().cloned().map(Spans::from));
 
+        // If the task was inside of any user spans when it last yielded, show
+        // them next to the fields, innermost first.
+        let backtrace: Vec<_> = details
+            .map(|details| details.async_backtrace())
+            .unwrap_or_default()
+            .iter()
+            .rev()
+            .map(|frame| {
+                ListItem::new(Text::from(vec![
+                    Spans::from(vec![
+                        bold(frame.name().to_string()),
+                        Span::raw(format!(" ({})", frame.target())),
+                    ]),
+                    Spans::from(Span::raw(format!("    at {}", frame.location()))),
+                ]))
+            })
+            .collect();
+        // Show the user spans the task spent the most busy time in, along with
+        // how much of its busy time was spent outside of any user span.
+        let busy = task.busy(now);
+        let mut span_times: Vec<_> = details
+            .map(|details| details.span_busy_times())
+            .unwrap_or_default()
+            .iter()
+            .take(MAX_SPAN_BUSY_TIMES)
+            .map(|(span, busy_time)| {
+                ListItem::new(Spans::from(vec![
+                    dur(styles, *busy_time),
+                    Span::from(format!(
+                        " ({:>5.2}%) ",
+                        busy_time.as_secs_f64().percent_of(busy.as_secs_f64())
+                    )),
+                    bold(span.name().to_string()),
+                    Span::raw(format!(" at {}", span.location())),
+                ]))
+            })
+            .collect();
+        if !span_times.is_empty() {
+            let in_spans: Duration = details
+                .map(|details| details.span_busy_times().iter().map(|(_, t)| *t).sum())
+                .unwrap_or_default();
+            let outside = busy.saturating_sub(in_spans);
+            span_times.push(ListItem::new(Spans::from(vec![
+                dur(styles, outside),
+                Span::from(format!(
+                    " ({:>5.2}%) ",
+                    outside.as_secs_f64().percent_of(busy.as_secs_f64())
+                )),
+                Span::raw("<outside of spans>"),
+            ])));
+        }
+
+        // Show the tasks that most often woke this task, and that it most
+        // often woke.
+        let wake_counts = |counts: &[(String, u64)]| -> Vec<ListItem> {
+            counts
+                .iter()
+                .take(MAX_WAKE_COUNTS)
+                .map(|(task, wakes)| {
+                    ListItem::new(Spans::from(vec![
+                        Span::from(format!("{:>6} times ", wakes)),
+                        bold(task.clone()),
+                    ]))
+                })
+                .collect()
+        };
+        let woken_by = details
+            .map(|details| wake_counts(details.woken_by()))
+            .unwrap_or_default();
+        let woke = details
+            .map(|details| wake_counts(details.woke()))
+            .unwrap_or_default();
+
+        // Lists displayed next to the task's fields, along with the number of
+        // lines each list takes up.
+        let mut side_lists: Vec<(&str, List, u16)> = Vec::new();
+        if !backtrace.is_empty() {
+            // each frame takes two lines.
+            let height = backtrace.len() as u16 * 2;
+            side_lists.push(("Last Yielded In", List::new(backtrace), height));
+        }
+        if !span_times.is_empty() {
+            let height = span_times.len() as u16;
+            side_lists.push(("Busy Time By Span", List::new(span_times), height));
+        }
+        if !woken_by.is_empty() {
+            let height = woken_by.len() as u16;
+            side_lists.push(("Woken By", List::new(woken_by), height));
+        }
+        if !woke.is_empty() {
+            let height = woke.len() as u16;
+            side_lists.push(("Woke", List::new(woke), height));
+        }
+
+        let fields_area = if side_lists.is_empty() {
+            fields_area
+        } else {
+            let chunks = Layout::default()
+                .direction(layout::Direction::Horizontal)
+                .constraints(
+                    [
+                        layout::Constraint::Percentage(50),
+                        layout::Constraint::Percentage(50),
+                    ]
+                    .as_ref(),
+                )
+                .split(fields_area);
+            // Add 2 to each list's height for the top and bottom borders.
+            let constraints: Vec<_> = side_lists
+                .iter()
+                .map(|(_, _, height)| layout::Constraint::Length(height + 2))
+                .collect();
+            let side_areas = Layout::default()
+                .direction(layout::Direction::Vertical)
+                .constraints(constraints)
+                .split(chunks[1]);
+            for ((title, list, _), area) in side_lists.into_iter().zip(side_areas) {
+                frame.render_widget(list.block(styles.border_block().title(title)), area);
+            }
+            chunks[0]
+        };
+
         // If UTF-8 is disabled we can't draw the histogram sparklne.
         if styles.utf8 {
             let sparkline_area = poll_dur_area[1];
@@ -254,11 +391,14 @@ impl TaskView {
         let task_widget = Paragraph::new(overview).block(styles.border_block().title("Task"));
         let wakers_widget = Paragraph::new(waker_stats).block(styles.border_block().title("Waker"));
         let fields_widget = Paragraph::new(fields).block(styles.border_block().title("Fields"));
-        let percentiles_widget = Paragraph::new(
+        let percentiles_widget = Paragraph::new(if details_supported {
             details
                 .map(|details| details.make_percentiles_widget(styles))
-                .unwrap_or_default(),
-        )
+                .unwrap_or_default()
+        } else {
+            // The process can't send the poll time histogram.
+            Text::from("not supported")
+        })
         .block(styles.border_block().title("Poll Times Percentiles"));
 
         frame.render_widget(Block::default().title(controls), controls_area);
@@ -184,7 +184,7 @@ impl TableList for TasksTable {
             + POLLS_LEN as u16
             + target_width.chars();
         */
-        let warnings = state
+        let mut warnings = state
             .tasks_state()
             .warnings()
             .map(|warning| {
@@ -195,6 +195,16 @@ impl TableList for TasksTable {
                 ])))
             })
             .collect::<Vec<_>>();
+        let untracked = state.tasks_state().untracked();
+        if untracked > 0 {
+            warnings.push(ListItem::new(Text::from(Spans::from(vec![
+                styles.warning_wide(),
+                Span::from(format!(
+                    "{} tasks were not tracked, as the application reached its limit on tracked tasks",
+                    untracked
+                )),
+            ]))));
+        }
 
         let layout = layout::Layout::default()
             .direction(layout::Direction::Vertical)
@@ -1,4 +1,5 @@
 use crate::state::tasks::Task;
+use console_api::warnings;
 use std::{fmt::Debug, rc::Rc};
 
 /// A warning for a particular type of monitored entity (e.g. task or resource).
@@ -97,7 +98,7 @@ pub(crate) struct SelfWakePercent {
 }
 
 impl SelfWakePercent {
-    pub(crate) const DEFAULT_PERCENT: u64 = 50;
+    pub(crate) const DEFAULT_PERCENT: u64 = warnings::DEFAULT_SELF_WAKE_PERCENT;
     pub(crate) fn new(min_percent: u64) -> Self {
         Self {
             min_percent,
@@ -126,11 +127,7 @@ impl Warn<Task> for SelfWakePercent {
     }
 
     fn format(&self, task: &Task) -> String {
-        let self_wakes = task.self_wake_percent();
-        format!(
-            "This task has woken itself for more than {}% of its total wakeups ({}%)",
-            self.min_percent, self_wakes
-        )
+        warnings::self_wakes(self.min_percent, task.self_wake_percent())
     }
 }
 
@@ -147,6 +144,40 @@ impl Warn<Task> for LostWaker {
     }
 
     fn format(&self, _: &Task) -> String {
-        "This task has lost its waker, and will never be woken again.".into()
+        warnings::LOST_WAKER.into()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BlockingPoll;
+
+impl Warn<Task> for BlockingPoll {
+    fn summary(&self) -> &str {
+        "tasks are blocking their worker thread in a single poll"
+    }
+
+    fn check(&self, task: &Task) -> bool {
+        task.blocking_poll().is_some()
+    }
+
+    fn format(&self, task: &Task) -> String {
+        warnings::blocking_poll(task.blocking_poll().unwrap_or_default())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EventsDropped;
+
+impl Warn<Task> for EventsDropped {
+    fn summary(&self) -> &str {
+        "tasks have lost events, so their stats may be wrong"
+    }
+
+    fn check(&self, task: &Task) -> bool {
+        task.events_dropped()
+    }
+
+    fn format(&self, _: &Task) -> String {
+        warnings::EVENTS_DROPPED.into()
     }
 }